@@ -4,6 +4,7 @@ import { deleteCookie, getCookie } from "vinxi/http";
 
 const logout = createServerFn({ method: "POST" }).handler(async () => {
   const refreshToken = getCookie(Cookies.REFRESH_TOKEN);
+  const accessToken = getCookie(Cookies.ACCESS_TOKEN);
   if (!refreshToken) {
     return {};
   }
@@ -12,6 +13,7 @@ const logout = createServerFn({ method: "POST" }).handler(async () => {
     method: "POST",
     headers: {
       "Content-Type": "application/json",
+      Authorization: `Bearer ${accessToken}`,
     },
     body: JSON.stringify({ refresh_token: refreshToken }),
   });
@@ -19,6 +19,7 @@ export type Me = z.infer<typeof GetMeSchema>;
 
 const accessTokenResponseSchema = z.object({
   accessToken: z.string(),
+  refreshToken: z.string(),
 });
 
 async function getAccessToken(refreshToken: string) {
@@ -56,6 +57,13 @@ export const authMiddleware = createMiddleware()
         to: "/login",
       });
     }
+    setCookie(Cookies.REFRESH_TOKEN, token.refreshToken, {
+      httpOnly: true,
+      sameSite: "strict",
+      expires: new Date(Date.now() + 30 * 24 * 60 * 60 * 1000), // 30 days
+      partitioned: true,
+      secure: true,
+    });
     setCookie(Cookies.ACCESS_TOKEN, token.accessToken);
 
     return next({});
@@ -0,0 +1,16 @@
+-- Add migration script here
+ALTER TABLE refresh_tokens
+ADD COLUMN selector VARCHAR(32),
+ADD COLUMN token_hash BYTEA;
+
+-- Existing rows only hold the plaintext token, which cannot be hashed here without the
+-- application secret. Drop them so no usable credential is left in the table, users simply
+-- have to log in again.
+DELETE FROM refresh_tokens;
+
+ALTER TABLE refresh_tokens
+DROP COLUMN token,
+ALTER COLUMN selector SET NOT NULL,
+ALTER COLUMN token_hash SET NOT NULL;
+
+CREATE UNIQUE INDEX idx_refresh_tokens_selector ON refresh_tokens(selector);
@@ -0,0 +1,7 @@
+-- Add migration script here
+ALTER TABLE refresh_tokens
+ADD COLUMN user_agent TEXT,
+ADD COLUMN ip_address VARCHAR(45),
+ADD COLUMN last_used_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP;
+
+CREATE INDEX idx_refresh_tokens_user_id ON refresh_tokens(user_id);
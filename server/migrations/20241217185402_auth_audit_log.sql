@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE auth_audit_log (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    event VARCHAR(64) NOT NULL,
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    subject VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_auth_audit_log_user_id ON auth_audit_log(user_id);
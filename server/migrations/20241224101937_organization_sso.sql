@@ -0,0 +1,18 @@
+-- Add migration script here
+CREATE TABLE organization_sso (
+    organization_id UUID PRIMARY KEY REFERENCES organizations(id) ON DELETE CASCADE,
+    -- Email domain of the organization, its identity provider is only trusted for this domain.
+    domain VARCHAR(255) NOT NULL,
+    domain_verification_token VARCHAR(255) NOT NULL,
+    domain_verified_at TIMESTAMP,
+    issuer VARCHAR(255) NOT NULL,
+    client_id VARCHAR(255) NOT NULL,
+    client_secret VARCHAR(255) NOT NULL,
+    enforce_sso BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Anyone can claim a domain, only one organization can prove it owns it.
+CREATE UNIQUE INDEX idx_organization_sso_verified_domain ON organization_sso(domain)
+WHERE domain_verified_at IS NOT NULL;
@@ -0,0 +1,24 @@
+-- Add migration script here
+ALTER TABLE user_organizations ADD COLUMN role VARCHAR(32) NOT NULL DEFAULT 'member';
+
+UPDATE user_organizations SET role = 'owner'
+FROM organizations
+WHERE organizations.id = user_organizations.organization_id
+    AND organizations.owner_user_id = user_organizations.user_id;
+
+CREATE TABLE organization_invitations (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    email VARCHAR(255) NOT NULL,
+    role VARCHAR(32) NOT NULL,
+    invited_by_user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    selector VARCHAR(32) NOT NULL UNIQUE,
+    token_hash BYTEA NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    accepted_at TIMESTAMP,
+    declined_at TIMESTAMP,
+    revoked_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_organization_invitations_organization_id ON organization_invitations(organization_id);
@@ -0,0 +1,24 @@
+-- Add migration script here
+CREATE TABLE organization_roles (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    name VARCHAR(64) NOT NULL,
+    permissions TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT unique_organization_role_name UNIQUE (organization_id, name)
+);
+
+-- Members either have one of the built-in roles or a custom role of the organization.
+ALTER TABLE user_organizations
+    ALTER COLUMN role DROP NOT NULL,
+    ALTER COLUMN role DROP DEFAULT,
+    ADD COLUMN custom_role_id UUID REFERENCES organization_roles(id) ON DELETE RESTRICT,
+    ADD CONSTRAINT user_organizations_role_check
+        CHECK (role IN ('owner', 'admin', 'member', 'viewer')),
+    ADD CONSTRAINT user_organizations_single_role
+        CHECK ((role IS NULL) <> (custom_role_id IS NULL));
+
+ALTER TABLE organization_invitations
+    ADD CONSTRAINT organization_invitations_role_check
+        CHECK (role IN ('admin', 'member', 'viewer'));
@@ -0,0 +1,48 @@
+-- Add migration script here
+ALTER TABLE organizations DROP CONSTRAINT unique_name;
+
+ALTER TABLE organizations
+ADD CONSTRAINT unique_owner_organization_name UNIQUE (owner_user_id, name);
+
+ALTER TABLE organizations ADD COLUMN slug VARCHAR(48);
+
+-- Same rules as `slugify` in src/db/organization.rs: characters outside of ASCII are left out,
+-- anything else than letters and digits separates words, and names without a valid slug become
+-- "organization". Like `available_slug`, duplicates get the lowest numeric suffix still free.
+DO $$
+DECLARE
+    organization RECORD;
+    base TEXT;
+    candidate TEXT;
+    suffix INTEGER;
+BEGIN
+    FOR organization IN SELECT id, name FROM organizations ORDER BY created_at, id LOOP
+        -- translate() rather than lower() so the database locale cannot change ASCII letters.
+        base := translate(
+            regexp_replace(organization.name, '[^\x01-\x7F]', '', 'g'),
+            'ABCDEFGHIJKLMNOPQRSTUVWXYZ',
+            'abcdefghijklmnopqrstuvwxyz'
+        );
+        base := TRIM(BOTH '-' FROM regexp_replace(base, '[^a-z0-9]+', '-', 'g'));
+        base := TRIM(TRAILING '-' FROM LEFT(base, 40));
+        -- Empty, reserved and UUID-shaped slugs are not valid.
+        IF base = '' OR base = 'invitations'
+            OR base ~ '^[0-9a-f]{32}$' OR base ~ '^[0-9a-f]{8}(-[0-9a-f]{4}){3}-[0-9a-f]{12}$'
+        THEN
+            base := 'organization';
+        END IF;
+
+        candidate := base;
+        suffix := 1;
+        WHILE EXISTS (SELECT 1 FROM organizations WHERE slug = candidate) LOOP
+            suffix := suffix + 1;
+            candidate := base || '-' || suffix;
+        END LOOP;
+
+        UPDATE organizations SET slug = candidate WHERE id = organization.id;
+    END LOOP;
+END $$;
+
+ALTER TABLE organizations
+    ALTER COLUMN slug SET NOT NULL,
+    ADD CONSTRAINT unique_organization_slug UNIQUE (slug);
@@ -0,0 +1,61 @@
+-- Add migration script here
+-- Requests scoped to an organization switch to this role inside their transaction, so row-level
+-- security limits them to the rows of that organization even if a query misses a WHERE clause.
+-- Roles are shared by all databases of the cluster, so it may already exist.
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_roles WHERE rolname = 'tick_tack_tenant') THEN
+        CREATE ROLE tick_tack_tenant NOLOGIN;
+    END IF;
+END
+$$;
+
+GRANT tick_tack_tenant TO CURRENT_USER;
+
+-- Set per transaction with set_config('app.organization_id', ..., true). Without it no rows match.
+CREATE FUNCTION current_organization_id() RETURNS UUID
+LANGUAGE sql STABLE
+AS $$ SELECT NULLIF(current_setting('app.organization_id', true), '')::UUID $$;
+
+GRANT USAGE ON SCHEMA public TO tick_tack_tenant;
+GRANT SELECT, UPDATE, DELETE ON organizations TO tick_tack_tenant;
+GRANT SELECT, INSERT, UPDATE, DELETE ON user_organizations TO tick_tack_tenant;
+GRANT SELECT, INSERT, UPDATE, DELETE ON organization_roles TO tick_tack_tenant;
+GRANT SELECT, INSERT, UPDATE, DELETE ON organization_invitations TO tick_tack_tenant;
+GRANT SELECT, INSERT, UPDATE, DELETE ON organization_sso TO tick_tack_tenant;
+GRANT SELECT, UPDATE ON api_keys TO tick_tack_tenant;
+-- Only what member lists need, never password hashes or MFA secrets.
+GRANT SELECT (id, email) ON users TO tick_tack_tenant;
+
+ALTER TABLE organizations ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON organizations TO tick_tack_tenant
+    USING (id = current_organization_id());
+
+ALTER TABLE user_organizations ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON user_organizations TO tick_tack_tenant
+    USING (organization_id = current_organization_id());
+
+ALTER TABLE organization_roles ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON organization_roles TO tick_tack_tenant
+    USING (organization_id = current_organization_id());
+
+ALTER TABLE organization_invitations ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON organization_invitations TO tick_tack_tenant
+    USING (organization_id = current_organization_id());
+
+ALTER TABLE organization_sso ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON organization_sso TO tick_tack_tenant
+    USING (organization_id = current_organization_id());
+
+ALTER TABLE api_keys ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON api_keys TO tick_tack_tenant
+    USING (organization_id = current_organization_id());
+
+-- Users are not owned by an organization, members are visible to the organizations they are in.
+ALTER TABLE users ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON users TO tick_tack_tenant
+    USING (EXISTS (
+        SELECT 1 FROM user_organizations
+        WHERE user_organizations.user_id = users.id
+            AND user_organizations.organization_id = current_organization_id()
+    ));
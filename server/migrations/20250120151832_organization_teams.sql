@@ -0,0 +1,45 @@
+-- Add migration script here
+-- Groups members of an organization, for example by department. Teams can have sub-teams but
+-- sub-teams cannot have their own, which the application checks when creating them.
+CREATE TABLE teams (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    parent_team_id UUID,
+    name VARCHAR(64) NOT NULL,
+    -- Granted to every member of the team and of its sub-teams, on top of their role.
+    permissions TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT unique_organization_team_name UNIQUE (organization_id, name),
+    CONSTRAINT unique_team_organization UNIQUE (id, organization_id),
+    -- Keeps the parent in the same organization. Teams with sub-teams cannot be deleted.
+    CONSTRAINT team_parent_fkey FOREIGN KEY (parent_team_id, organization_id)
+        REFERENCES teams(id, organization_id)
+);
+
+CREATE INDEX idx_teams_parent_team_id ON teams(parent_team_id);
+
+CREATE TABLE team_members (
+    team_id UUID NOT NULL,
+    organization_id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (team_id, user_id),
+    FOREIGN KEY (team_id, organization_id) REFERENCES teams(id, organization_id) ON DELETE CASCADE,
+    -- Leaving the organization also removes the user from its teams.
+    FOREIGN KEY (user_id, organization_id)
+        REFERENCES user_organizations(user_id, organization_id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_team_members_user_id ON team_members(user_id, organization_id);
+
+GRANT SELECT, INSERT, UPDATE, DELETE ON teams TO tick_tack_tenant;
+GRANT SELECT, INSERT, DELETE ON team_members TO tick_tack_tenant;
+
+ALTER TABLE teams ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON teams TO tick_tack_tenant
+    USING (organization_id = current_organization_id());
+
+ALTER TABLE team_members ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON team_members TO tick_tack_tenant
+    USING (organization_id = current_organization_id());
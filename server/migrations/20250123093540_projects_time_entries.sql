@@ -0,0 +1,46 @@
+-- Add migration script here
+CREATE TABLE projects (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    description TEXT NOT NULL DEFAULT '',
+    -- Archived projects keep their time entries but no new time can be tracked on them.
+    archived_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT unique_organization_project_name UNIQUE (organization_id, name),
+    CONSTRAINT unique_project_organization UNIQUE (id, organization_id)
+);
+
+CREATE TABLE time_entries (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    -- Entries outlive the membership, they are still needed for reports and invoices.
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    project_id UUID,
+    started_at TIMESTAMP NOT NULL,
+    ended_at TIMESTAMP NOT NULL,
+    description TEXT NOT NULL DEFAULT '',
+    billable BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    -- Keeps the project in the same organization. Projects with time entries cannot be deleted.
+    CONSTRAINT time_entry_project_fkey FOREIGN KEY (project_id, organization_id)
+        REFERENCES projects(id, organization_id),
+    CONSTRAINT time_entry_ends_after_start CHECK (ended_at > started_at)
+);
+
+CREATE INDEX idx_time_entries_organization_id ON time_entries(organization_id, started_at);
+CREATE INDEX idx_time_entries_user_id ON time_entries(user_id, started_at);
+CREATE INDEX idx_time_entries_project_id ON time_entries(project_id);
+
+GRANT SELECT, INSERT, UPDATE, DELETE ON projects TO tick_tack_tenant;
+GRANT SELECT, INSERT, UPDATE, DELETE ON time_entries TO tick_tack_tenant;
+
+ALTER TABLE projects ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON projects TO tick_tack_tenant
+    USING (organization_id = current_organization_id());
+
+ALTER TABLE time_entries ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON time_entries TO tick_tack_tenant
+    USING (organization_id = current_organization_id());
@@ -0,0 +1,6 @@
+-- Add migration script here
+-- A running timer is a time entry without an end.
+ALTER TABLE time_entries ALTER COLUMN ended_at DROP NOT NULL;
+
+-- Nobody can work on two things at once, across all organizations.
+CREATE UNIQUE INDEX unique_running_time_entry ON time_entries(user_id) WHERE ended_at IS NULL;
@@ -0,0 +1,56 @@
+-- Add migration script here
+CREATE TABLE clients (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT unique_organization_client_name UNIQUE (organization_id, name),
+    CONSTRAINT unique_client_organization UNIQUE (id, organization_id)
+);
+
+-- Clients with projects cannot be deleted.
+ALTER TABLE projects ADD COLUMN client_id UUID;
+ALTER TABLE projects ADD CONSTRAINT project_client_fkey FOREIGN KEY (client_id, organization_id)
+    REFERENCES clients(id, organization_id);
+CREATE INDEX idx_projects_client_id ON projects(client_id);
+
+-- A rate applies from `effective_from` until a later rate of the same level and target. Which of
+-- client, project and user are set decides the level: none for the whole organization, the
+-- client, the project, the user for a member, or both project and user for a member on a project.
+CREATE TABLE hourly_rates (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    client_id UUID,
+    project_id UUID,
+    -- Rates outlive the membership like the time entries they price.
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    -- In the minor unit of the currency, like cents.
+    amount BIGINT NOT NULL,
+    currency CHAR(3) NOT NULL,
+    effective_from TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT hourly_rate_client_fkey FOREIGN KEY (client_id, organization_id)
+        REFERENCES clients(id, organization_id) ON DELETE CASCADE,
+    CONSTRAINT hourly_rate_project_fkey FOREIGN KEY (project_id, organization_id)
+        REFERENCES projects(id, organization_id) ON DELETE CASCADE,
+    CONSTRAINT hourly_rate_level
+        CHECK (client_id IS NULL OR (project_id IS NULL AND user_id IS NULL)),
+    CONSTRAINT hourly_rate_amount CHECK (amount >= 0),
+    CONSTRAINT hourly_rate_currency CHECK (currency ~ '^[A-Z]{3}$'),
+    CONSTRAINT unique_hourly_rate UNIQUE NULLS NOT DISTINCT
+        (organization_id, client_id, project_id, user_id, effective_from)
+);
+
+CREATE INDEX idx_hourly_rates_organization_id ON hourly_rates(organization_id, effective_from);
+
+GRANT SELECT, INSERT, UPDATE, DELETE ON clients TO tick_tack_tenant;
+GRANT SELECT, INSERT, UPDATE, DELETE ON hourly_rates TO tick_tack_tenant;
+
+ALTER TABLE clients ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON clients TO tick_tack_tenant
+    USING (organization_id = current_organization_id());
+
+ALTER TABLE hourly_rates ENABLE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON hourly_rates TO tick_tack_tenant
+    USING (organization_id = current_organization_id());
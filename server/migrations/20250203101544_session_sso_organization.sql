@@ -0,0 +1,5 @@
+-- Add migration script here
+-- The organization whose identity provider the session was started through, if any. Sessions of
+-- organizations enforcing single sign-on must have been started through their provider.
+ALTER TABLE refresh_tokens
+ADD COLUMN sso_organization_id UUID REFERENCES organizations(id) ON DELETE SET NULL;
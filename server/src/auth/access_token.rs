@@ -1,7 +1,8 @@
 use axum::http::StatusCode;
 use chrono::{ Duration, Utc };
-use jsonwebtoken::{ encode, decode, Header, TokenData, Validation };
-use serde::{ Deserialize, Serialize };
+use jsonwebtoken::{ encode, decode, decode_header, Header, TokenData, Validation };
+use serde::{ de::DeserializeOwned, Deserialize, Serialize };
+use uuid::Uuid;
 
 use crate::auth::keys::Keys;
 
@@ -9,27 +10,50 @@ use crate::auth::keys::Keys;
 pub struct Claims {
     pub user_id: String,
     pub email: String,
+    pub session_id: String,
+    pub jti: String, // Unique id of the token, used to revoke it
     pub exp: usize, // Expiry time of the token
     pub iat: usize, // Issued at time of the token
 }
 
-pub fn encode_jwt(user_id: String, email: String) -> Result<String, StatusCode> {
+/// Signs claims with the current signing key, setting its `kid` in the header.
+pub fn encode_token<T: Serialize>(keys: &Keys, claims: &T) -> Result<String, StatusCode> {
+    let key = keys.signing_key();
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+
+    encode(&header, claims, &key.encoding).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Verifies a token with the key named by its `kid`, only accepting that key's algorithm.
+pub fn decode_token<T: DeserializeOwned>(
+    keys: &Keys,
+    token: &str
+) -> Result<TokenData<T>, StatusCode> {
+    let header = decode_header(token).map_err(|_| StatusCode::UNAUTHORIZED)?;
+    let key = keys.decoding_key(header.kid.as_deref()).ok_or(StatusCode::UNAUTHORIZED)?;
+
+    decode(token, &key.decoding, &Validation::new(key.algorithm)).map_err(
+        |_| StatusCode::UNAUTHORIZED
+    )
+}
+
+pub fn encode_jwt(
+    keys: &Keys,
+    user_id: String,
+    email: String,
+    session_id: String
+) -> Result<String, StatusCode> {
     let now = Utc::now();
     let expire: chrono::TimeDelta = Duration::minutes(15);
     let exp: usize = (now + expire).timestamp() as usize;
     let iat: usize = now.timestamp() as usize;
-    let claim = Claims { iat, exp, user_id, email };
+    let jti = Uuid::new_v4().to_string();
+    let claim = Claims { iat, exp, user_id, email, session_id, jti };
 
-    encode(&Header::default(), &claim, &Keys::new().encoding).map_err(
-        |_| StatusCode::INTERNAL_SERVER_ERROR
-    )
+    encode_token(keys, &claim)
 }
 
-pub fn decode_jwt(jwt_token: String) -> Result<TokenData<Claims>, StatusCode> {
-    let result: Result<TokenData<Claims>, StatusCode> = decode(
-        &jwt_token,
-        &Keys::new().decoding,
-        &Validation::default()
-    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
-    result
+pub fn decode_jwt(keys: &Keys, jwt_token: String) -> Result<TokenData<Claims>, StatusCode> {
+    decode_token(keys, &jwt_token)
 }
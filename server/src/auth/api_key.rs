@@ -0,0 +1,53 @@
+use crate::auth::opaque_token::OpaqueToken;
+
+/// Prefix of API keys, it tells them apart from JWTs and makes leaked keys easy to scan for.
+pub const API_KEY_PREFIX: &str = "tt_";
+
+/// What an API key may be used for. Login sessions are not restricted by scopes.
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Scope {
+    #[serde(rename = "user:read")]
+    UserRead,
+    #[serde(rename = "organizations:read")]
+    OrganizationsRead,
+    #[serde(rename = "organizations:write")]
+    OrganizationsWrite,
+    #[serde(rename = "time:read")]
+    TimeRead,
+    #[serde(rename = "time:write")]
+    TimeWrite,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::UserRead => "user:read",
+            Scope::OrganizationsRead => "organizations:read",
+            Scope::OrganizationsWrite => "organizations:write",
+            Scope::TimeRead => "time:read",
+            Scope::TimeWrite => "time:write",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Self> {
+        match scope {
+            "user:read" => Some(Scope::UserRead),
+            "organizations:read" => Some(Scope::OrganizationsRead),
+            "organizations:write" => Some(Scope::OrganizationsWrite),
+            "time:read" => Some(Scope::TimeRead),
+            "time:write" => Some(Scope::TimeWrite),
+            _ => None,
+        }
+    }
+}
+
+/// Generates a new key, the returned string is only ever shown to the user once.
+pub fn generate_api_key() -> (OpaqueToken, String) {
+    let token = OpaqueToken::generate();
+    let key = format!("{}{}", API_KEY_PREFIX, token);
+    (token, key)
+}
+
+pub fn parse_api_key(key: &str) -> Option<OpaqueToken> {
+    key.strip_prefix(API_KEY_PREFIX).and_then(OpaqueToken::parse)
+}
@@ -1,46 +1,247 @@
-use axum::{ extract::Request, middleware::Next, response::Response };
+use std::env;
+
+use axum::{ extract::Request, middleware::Next, response::Response, Json };
 use http::StatusCode;
+use once_cell::sync::Lazy;
+
+use uuid::Uuid;
+
+use crate::{
+    db::{ api_key::authenticate_api_key, user::get_user_by_id },
+    models::user::User,
+    state::AppState,
+};
 
-use crate::{ db::user::get_user_by_email, models::user::User, state::AppState };
+use super::{
+    access_token::decode_jwt,
+    api_key::{ Scope, API_KEY_PREFIX },
+    revocation::is_access_token_revoked,
+};
 
-use super::access_token::decode_jwt;
+static REQUIRE_EMAIL_VERIFICATION: Lazy<bool> = Lazy::new(|| {
+    env::var("REQUIRE_EMAIL_VERIFICATION").map(|value| value == "true").unwrap_or(false)
+});
+
+/// An access token of a login session.
+#[derive(Clone)]
+pub struct SessionCredential {
+    pub session_id: Uuid,
+    pub jti: Uuid,
+    pub exp: usize,
+}
+
+#[derive(Clone)]
+pub struct ApiKeyCredential {
+    /// Keys of an organization only grant access to that organization.
+    pub organization_id: Option<Uuid>,
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Clone)]
+pub enum Credential {
+    Session(SessionCredential),
+    ApiKey(ApiKeyCredential),
+}
 
 #[derive(Clone)]
 pub struct AuthExtension {
     pub user: User,
+    pub credential: Credential,
 }
 
-pub async fn auth(mut req: Request, next: Next) -> Result<Response, StatusCode> {
+impl AuthExtension {
+    /// The login session of the request, API keys are refused.
+    pub fn session(&self) -> Result<&SessionCredential, (StatusCode, Json<serde_json::Value>)> {
+        match &self.credential {
+            Credential::Session(session) => Ok(session),
+            Credential::ApiKey(_) => {
+                let error_response =
+                    serde_json::json!({
+                    "status": "error",
+                    "message": "API keys cannot be used for this request",
+                });
+                Err((StatusCode::FORBIDDEN, Json(error_response)))
+            }
+        }
+    }
+
+    /// Requires API keys to have the scope, login sessions can do anything.
+    pub fn require_scope(&self, scope: Scope) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+        match &self.credential {
+            Credential::ApiKey(api_key) if !api_key.scopes.contains(&scope) => {
+                let error_response =
+                    serde_json::json!({
+                    "status": "error",
+                    "message": format!("API key is missing the {} scope", scope.as_str()),
+                });
+                Err((StatusCode::FORBIDDEN, Json(error_response)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the credential is an API key limited to a single organization.
+    pub fn is_organization_key(&self) -> bool {
+        matches!(
+            &self.credential,
+            Credential::ApiKey(ApiKeyCredential { organization_id: Some(_), .. })
+        )
+    }
+
+    /// Whether the credential may be used for the organization at all, membership is not checked.
+    pub fn can_access_organization(&self, organization_id: &Uuid) -> bool {
+        match &self.credential {
+            Credential::ApiKey(ApiKeyCredential { organization_id: Some(key_organization), .. }) =>
+                key_organization == organization_id,
+            _ => true,
+        }
+    }
+}
+
+fn request_credentials(req: &Request) -> (Option<String>, AppState) {
     let auth_header = req
         .headers()
         .get(http::header::AUTHORIZATION)
-        .and_then(|header| header.to_str().ok());
-    let ctx = req.extensions().get::<AppState>().unwrap();
-
-    if let Some(header) = auth_header {
-        if header.starts_with("Bearer ") {
-            let jwt_token = &header[7..];
-            // Now you can use jwt_token
-            match decode_jwt(jwt_token.to_string()) {
-                Ok(token) => {
-                    match get_user_by_email(&token.claims.email, &ctx).await {
-                        Ok(user) => {
-                            req.extensions_mut().insert(AuthExtension { user });
-                            return Ok(next.run(req).await);
-                        }
-                        Err(_) => {
-                            return Err(StatusCode::UNAUTHORIZED);
-                        }
-                    }
-                }
-                Err(_) => {
-                    return Err(StatusCode::UNAUTHORIZED);
-                }
-            }
-        } else {
+        .and_then(|header| header.to_str().ok())
+        .map(|header| header.to_string());
+    let ctx = req.extensions().get::<AppState>().unwrap().clone();
+    (auth_header, ctx)
+}
+
+async fn authenticate_session(
+    jwt_token: &str,
+    ctx: &AppState
+) -> Result<AuthExtension, StatusCode> {
+    let Ok(token) = decode_jwt(&ctx.keys, jwt_token.to_string()) else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    let (Ok(user_id), Ok(session_id), Ok(jti)) = (
+        Uuid::parse_str(&token.claims.user_id),
+        Uuid::parse_str(&token.claims.session_id),
+        Uuid::parse_str(&token.claims.jti),
+    ) else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    match is_access_token_revoked(&jti, ctx).await {
+        Ok(false) => {}
+        Ok(true) => {
             return Err(StatusCode::UNAUTHORIZED);
         }
-    } else {
+        Err(_) => {
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    match get_user_by_id(&user_id, ctx).await {
+        Ok(user) =>
+            Ok(AuthExtension {
+                user,
+                credential: Credential::Session(SessionCredential {
+                    session_id,
+                    jti,
+                    exp: token.claims.exp,
+                }),
+            }),
+        Err(_) => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+async fn authenticate_api_key_header(
+    key: &str,
+    ctx: &AppState
+) -> Result<AuthExtension, StatusCode> {
+    let api_key = match authenticate_api_key(key, ctx).await {
+        Ok(Some(api_key)) => api_key,
+        Ok(None) => {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        Err(_) => {
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    match get_user_by_id(&api_key.user_id, ctx).await {
+        Ok(user) =>
+            Ok(AuthExtension {
+                user,
+                credential: Credential::ApiKey(ApiKeyCredential {
+                    organization_id: api_key.organization_id,
+                    scopes: api_key.scopes
+                        .iter()
+                        .filter_map(|scope| Scope::parse(scope))
+                        .collect(),
+                }),
+            }),
+        Err(_) => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+/// Accepts both JWTs of login sessions and `tt_` API keys as bearer tokens.
+async fn authenticate(
+    auth_header: Option<String>,
+    ctx: &AppState
+) -> Result<AuthExtension, StatusCode> {
+    let token = auth_header.as_deref().and_then(|header| header.strip_prefix("Bearer "));
+    let Some(token) = token else {
         return Err(StatusCode::UNAUTHORIZED);
+    };
+
+    if token.starts_with(API_KEY_PREFIX) {
+        authenticate_api_key_header(token, ctx).await
+    } else {
+        authenticate_session(token, ctx).await
+    }
+}
+
+/// Authenticates the access token of a login session outside of a request, like on a WebSocket.
+/// Applies the same email verification rule as [`auth`].
+pub async fn authenticate_access_token(
+    jwt_token: &str,
+    ctx: &AppState
+) -> Result<AuthExtension, StatusCode> {
+    let auth = authenticate_session(jwt_token, ctx).await?;
+
+    if *REQUIRE_EMAIL_VERIFICATION && auth.user.email_verified_at.is_none() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(auth)
+}
+
+/// Requires a valid access token or API key. When `REQUIRE_EMAIL_VERIFICATION` is enabled
+/// accounts that have not verified their email are rejected with 403.
+pub async fn auth(mut req: Request, next: Next) -> Result<Response, StatusCode> {
+    let (auth_header, ctx) = request_credentials(&req);
+    let auth = authenticate(auth_header, &ctx).await?;
+
+    if *REQUIRE_EMAIL_VERIFICATION && auth.user.email_verified_at.is_none() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    req.extensions_mut().insert(auth);
+    Ok(next.run(req).await)
+}
+
+/// Like [`auth`] but also lets unverified accounts through, for the routes they need before
+/// verifying their email.
+pub async fn auth_allow_unverified(mut req: Request, next: Next) -> Result<Response, StatusCode> {
+    let (auth_header, ctx) = request_credentials(&req);
+    let auth = authenticate(auth_header, &ctx).await?;
+
+    req.extensions_mut().insert(auth);
+    Ok(next.run(req).await)
+}
+
+/// Like [`auth`] but refuses API keys, for account and security settings that scripts must not
+/// be able to change.
+pub async fn auth_session(mut req: Request, next: Next) -> Result<Response, StatusCode> {
+    let (auth_header, ctx) = request_credentials(&req);
+    let auth = authenticate(auth_header, &ctx).await?;
+
+    if !matches!(auth.credential, Credential::Session(_)) {
+        return Err(StatusCode::FORBIDDEN);
     }
+    if *REQUIRE_EMAIL_VERIFICATION && auth.user.email_verified_at.is_none() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    req.extensions_mut().insert(auth);
+    Ok(next.run(req).await)
 }
@@ -0,0 +1,47 @@
+use std::{ env, net::SocketAddr };
+
+use axum::{ async_trait, extract::{ ConnectInfo, FromRequestParts } };
+use http::{ request::Parts, StatusCode };
+use once_cell::sync::Lazy;
+
+/// Only trust `X-Forwarded-For` when running behind a proxy that sets it.
+static TRUST_PROXY_HEADERS: Lazy<bool> = Lazy::new(|| {
+    env::var("TRUST_PROXY_HEADERS").map(|value| value == "true").unwrap_or(false)
+});
+
+/// Describes the client a request came from, recorded on sessions.
+#[derive(Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientInfo where S: Send + Sync {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user_agent = parts.headers
+            .get(http::header::USER_AGENT)
+            .and_then(|header| header.to_str().ok())
+            .map(|user_agent| user_agent.to_string());
+
+        let forwarded_for = if *TRUST_PROXY_HEADERS {
+            parts.headers
+                .get("x-forwarded-for")
+                .and_then(|header| header.to_str().ok())
+                .and_then(|header| header.split(',').next())
+                .map(|ip| ip.trim().to_string())
+        } else {
+            None
+        };
+
+        let ip_address = forwarded_for.or_else(|| {
+            parts.extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+        });
+
+        Ok(Self { user_agent, ip_address })
+    }
+}
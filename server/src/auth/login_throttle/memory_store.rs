@@ -0,0 +1,114 @@
+use std::{ collections::HashMap, sync::Mutex };
+
+use async_trait::async_trait;
+
+use super::{ AttemptStore, Attempts };
+
+/// Keeps failed attempts in process memory, only suitable for a single instance.
+#[derive(Default)]
+pub struct MemoryAttemptStore {
+    attempts: Mutex<HashMap<String, (Attempts, i64)>>,
+}
+
+#[async_trait]
+impl AttemptStore for MemoryAttemptStore {
+    async fn record_attempt(
+        &self,
+        key: &str,
+        now: i64,
+        ttl_seconds: u64
+    ) -> Result<Attempts, String> {
+        let mut attempts = self.attempts.lock().unwrap();
+        attempts.retain(|_, (_, expires_at)| *expires_at > now);
+
+        let (entry, expires_at) = attempts.entry(key.to_string()).or_default();
+        let before = *entry;
+        entry.failures += 1;
+        entry.last_failure_at = now;
+        *expires_at = now + (ttl_seconds as i64);
+
+        Ok(before)
+    }
+
+    async fn forgive(&self, key: &str) -> Result<(), String> {
+        if let Some((entry, _)) = self.attempts.lock().unwrap().get_mut(key) {
+            entry.failures = entry.failures.saturating_sub(1);
+        }
+        Ok(())
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), String> {
+        self.attempts.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::MemoryAttemptStore;
+    use crate::auth::login_throttle::AttemptStore;
+
+    #[tokio::test]
+    async fn attempts_return_the_count_before_them() {
+        let store = MemoryAttemptStore::default();
+
+        let first = store.record_attempt("login:account:jane", 100, 60).await.unwrap();
+        let second = store.record_attempt("login:account:jane", 105, 60).await.unwrap();
+        let other = store.record_attempt("login:account:john", 105, 60).await.unwrap();
+
+        assert_eq!((first.failures, first.last_failure_at), (0, 0));
+        assert_eq!((second.failures, second.last_failure_at), (1, 100));
+        assert_eq!(other.failures, 0);
+    }
+
+    #[tokio::test]
+    async fn parallel_attempts_each_see_a_different_count() {
+        let store = Arc::new(MemoryAttemptStore::default());
+
+        let tasks: Vec<_> = (0..50)
+            .map(|_| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    store.record_attempt("login:ip:203.0.113.7", 100, 60).await.unwrap().failures
+                })
+            })
+            .collect();
+        let mut counts = Vec::new();
+        for task in tasks {
+            counts.push(task.await.unwrap());
+        }
+        counts.sort();
+
+        assert_eq!(counts, (0..50).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn attempts_expire_after_the_window() {
+        let store = MemoryAttemptStore::default();
+
+        store.record_attempt("login:account:jane", 100, 60).await.unwrap();
+        store.record_attempt("login:account:jane", 150, 60).await.unwrap();
+
+        assert_eq!(store.record_attempt("login:account:jane", 209, 60).await.unwrap().failures, 2);
+        assert_eq!(store.record_attempt("login:account:jane", 270, 60).await.unwrap().failures, 0);
+    }
+
+    #[tokio::test]
+    async fn forgiving_and_resetting_take_attempts_back() {
+        let store = MemoryAttemptStore::default();
+
+        for now in 100..103 {
+            store.record_attempt("login:ip:203.0.113.7", now, 60).await.unwrap();
+        }
+        store.forgive("login:ip:203.0.113.7").await.unwrap();
+        let after_forgiving = store.record_attempt("login:ip:203.0.113.7", 110, 60).await.unwrap();
+        assert_eq!(after_forgiving.failures, 2);
+
+        store.reset("login:ip:203.0.113.7").await.unwrap();
+        store.forgive("login:ip:203.0.113.7").await.unwrap();
+        let after_reset = store.record_attempt("login:ip:203.0.113.7", 120, 60).await.unwrap();
+        assert_eq!(after_reset.failures, 0);
+    }
+}
@@ -0,0 +1,273 @@
+use std::{ env, sync::Arc };
+
+use axum::{ http::{ header::RETRY_AFTER, StatusCode }, response::{ IntoResponse, Response }, Json };
+use chrono::Utc;
+use log::{ error, info, warn };
+use uuid::Uuid;
+
+use crate::{ db::audit::insert_audit_event, state::AppState };
+
+pub mod memory_store;
+pub mod redis_store;
+
+/// Failures are forgotten after this long without a new failure.
+const FAILURE_WINDOW_SECONDS: u64 = 60 * 60;
+
+#[derive(Clone, Copy, Default)]
+pub struct Attempts {
+    pub failures: u32,
+    pub last_failure_at: i64,
+}
+
+/// Where attempts are counted. The in-memory store is enough for a single instance, multiple
+/// instances have to share the counters through Redis.
+#[async_trait::async_trait]
+pub trait AttemptStore: Send + Sync {
+    /// Counts an attempt at `now` and returns the attempts before it. Both happen in one atomic
+    /// step, so parallel attempts each see the ones counted before them.
+    async fn record_attempt(
+        &self,
+        key: &str,
+        now: i64,
+        ttl_seconds: u64
+    ) -> Result<Attempts, String>;
+    /// Takes back an attempt that turned out to be successful.
+    async fn forgive(&self, key: &str) -> Result<(), String>;
+    async fn reset(&self, key: &str) -> Result<(), String>;
+}
+
+pub enum ThrottleKey {
+    Account(String),
+    Ip(String),
+    Mfa(Uuid),
+}
+
+struct Policy {
+    /// Failures allowed before any delay is enforced.
+    free_failures: u32,
+    /// Failures after which the key is locked out for `lockout_seconds`.
+    lockout_failures: u32,
+    lockout_seconds: u64,
+}
+
+impl ThrottleKey {
+    fn store_key(&self) -> String {
+        match self {
+            ThrottleKey::Account(email) => format!("login:account:{}", email.to_lowercase()),
+            ThrottleKey::Ip(ip) => format!("login:ip:{}", ip),
+            ThrottleKey::Mfa(user_id) => format!("login:mfa:{}", user_id),
+        }
+    }
+
+    fn policy(&self) -> Policy {
+        match self {
+            // An address can be shared by many users, so it gets more room than a single account.
+            ThrottleKey::Ip(_) =>
+                Policy { free_failures: 20, lockout_failures: 100, lockout_seconds: 15 * 60 },
+            ThrottleKey::Account(_) | ThrottleKey::Mfa(_) =>
+                Policy { free_failures: 3, lockout_failures: 10, lockout_seconds: 15 * 60 },
+        }
+    }
+
+    /// Whether a successful attempt clears the failures. An address is shared, so a success
+    /// there only takes back its own attempt.
+    fn resets_on_success(&self) -> bool {
+        !matches!(self, ThrottleKey::Ip(_))
+    }
+
+    fn audit_event(&self) -> &'static str {
+        match self {
+            ThrottleKey::Account(_) => "account_locked",
+            ThrottleKey::Ip(_) => "ip_locked",
+            ThrottleKey::Mfa(_) => "mfa_locked",
+        }
+    }
+}
+
+impl Policy {
+    /// Seconds until the next attempt is allowed, doubling with every failure past the free ones
+    /// and capped at the lockout duration.
+    fn retry_after(&self, attempts: &Attempts, now: i64) -> Option<u64> {
+        let wait = if attempts.failures >= self.lockout_failures {
+            self.lockout_seconds
+        } else if attempts.failures > self.free_failures {
+            (1u64 << (attempts.failures - self.free_failures).min(20)).min(self.lockout_seconds)
+        } else {
+            return None;
+        };
+
+        let remaining = attempts.last_failure_at + (wait as i64) - now;
+        (remaining > 0).then_some(remaining as u64)
+    }
+
+    /// Whether a failure after `before` starts a lockout, either by reaching the limit or as the
+    /// first failure after an earlier lockout ended. Failures while locked out do not, so every
+    /// lockout is reported once.
+    fn locks_out(&self, before: &Attempts, now: i64) -> bool {
+        let failures = before.failures + 1;
+        failures == self.lockout_failures ||
+            (failures > self.lockout_failures && self.retry_after(before, now).is_none())
+    }
+}
+
+/// Picks the store from `LOGIN_THROTTLE_STORE`, `redis` uses `REDIS_URL`, anything else keeps
+/// the counters in memory.
+pub async fn attempt_store_from_env() -> Result<Arc<dyn AttemptStore>, String> {
+    match env::var("LOGIN_THROTTLE_STORE").as_deref() {
+        Ok("redis") => {
+            let url = env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
+            info!("Counting failed logins in Redis");
+            let store = redis_store::RedisAttemptStore
+                ::connect(&url).await
+                .map_err(|e| format!("Failed to connect to the Redis server of REDIS_URL: {}", e))?;
+            Ok(Arc::new(store))
+        }
+        _ => Ok(Arc::new(memory_store::MemoryAttemptStore::default())),
+    }
+}
+
+/// An attempt that was counted for its keys before the credentials were checked.
+pub struct LoginAttempt {
+    /// The keys with their attempts before this one.
+    counted: Vec<(ThrottleKey, Attempts)>,
+    now: i64,
+}
+
+/// Counts an attempt for every key and returns how long the caller has to wait if any of them
+/// was throttled already. Counting before the credentials are checked and deciding from the
+/// count makes parallel requests see each other, attempts made while throttled count as well.
+/// Store errors are logged and do not block logins.
+pub async fn begin_login_attempt(
+    keys: Vec<ThrottleKey>,
+    user_id: Option<Uuid>,
+    ctx: &AppState
+) -> Result<LoginAttempt, u64> {
+    let now = Utc::now().timestamp();
+    let mut counted = Vec::with_capacity(keys.len());
+
+    for key in keys {
+        match
+            ctx.login_attempts.record_attempt(&key.store_key(), now, FAILURE_WINDOW_SECONDS).await
+        {
+            Ok(before) => counted.push((key, before)),
+            Err(e) => error!("Failed to record login attempt: {}", e),
+        }
+    }
+
+    let attempt = LoginAttempt { counted, now };
+    let retry_after = attempt.counted
+        .iter()
+        .filter_map(|(key, before)| key.policy().retry_after(before, now))
+        .max();
+    match retry_after {
+        Some(retry_after) => {
+            attempt.failed(user_id, ctx).await;
+            Err(retry_after)
+        }
+        None => Ok(attempt),
+    }
+}
+
+impl LoginAttempt {
+    /// Keeps the attempt counted and writes an audit record for every key it locked out.
+    pub async fn failed(self, user_id: Option<Uuid>, ctx: &AppState) {
+        for (key, before) in &self.counted {
+            if !key.policy().locks_out(before, self.now) {
+                continue;
+            }
+            warn!("Locking out {} after {} failed logins", key.store_key(), before.failures + 1);
+            if
+                let Err(e) = insert_audit_event(
+                    key.audit_event(),
+                    user_id.as_ref(),
+                    &key.store_key(),
+                    ctx
+                ).await
+            {
+                error!("Failed to write audit event: {:?}", e);
+            }
+        }
+    }
+
+    /// Clears the failures of the account and takes the attempt back from the address.
+    pub async fn succeeded(self, ctx: &AppState) {
+        for (key, _) in &self.counted {
+            let result = if key.resets_on_success() {
+                ctx.login_attempts.reset(&key.store_key()).await
+            } else {
+                ctx.login_attempts.forgive(&key.store_key()).await
+            };
+            if let Err(e) = result {
+                error!("Failed to reset login attempts: {}", e);
+            }
+        }
+    }
+}
+
+pub fn too_many_attempts(retry_after: u64) -> Response {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": "Too many failed attempts, try again later",
+    });
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, retry_after.to_string())],
+        Json(error_response),
+    ).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ Attempts, ThrottleKey };
+
+    fn attempts(failures: u32, last_failure_at: i64) -> Attempts {
+        Attempts { failures, last_failure_at }
+    }
+
+    #[test]
+    fn accounts_wait_longer_after_every_failure_past_the_free_ones() {
+        let policy = ThrottleKey::Account("jane@example.com".to_string()).policy();
+
+        for failures in 0..=3 {
+            assert_eq!(policy.retry_after(&attempts(failures, 100), 100), None);
+        }
+        assert_eq!(policy.retry_after(&attempts(4, 100), 100), Some(2));
+        assert_eq!(policy.retry_after(&attempts(5, 100), 100), Some(4));
+        assert_eq!(policy.retry_after(&attempts(9, 100), 100), Some(64));
+        assert_eq!(policy.retry_after(&attempts(5, 100), 103), Some(1));
+        assert_eq!(policy.retry_after(&attempts(5, 100), 104), None);
+    }
+
+    #[test]
+    fn accounts_are_locked_out_from_the_tenth_failure() {
+        let policy = ThrottleKey::Account("jane@example.com".to_string()).policy();
+
+        assert_eq!(policy.retry_after(&attempts(10, 100), 100), Some(15 * 60));
+        assert_eq!(policy.retry_after(&attempts(25, 100), 100 + 15 * 60 - 1), Some(1));
+        assert_eq!(policy.retry_after(&attempts(25, 100), 100 + 15 * 60), None);
+    }
+
+    #[test]
+    fn addresses_get_more_room_than_accounts() {
+        let policy = ThrottleKey::Ip("203.0.113.7".to_string()).policy();
+
+        assert_eq!(policy.retry_after(&attempts(20, 100), 100), None);
+        assert_eq!(policy.retry_after(&attempts(21, 100), 100), Some(2));
+        assert_eq!(policy.retry_after(&attempts(40, 100), 100), Some(15 * 60));
+        assert_eq!(policy.retry_after(&attempts(100, 100), 100), Some(15 * 60));
+    }
+
+    #[test]
+    fn every_lockout_is_reported_once() {
+        let policy = ThrottleKey::Mfa(uuid::Uuid::new_v4()).policy();
+
+        assert!(!policy.locks_out(&attempts(8, 100), 200));
+        // Reaching the limit locks out even while the backoff is still running.
+        assert!(policy.locks_out(&attempts(9, 100), 101));
+        // Still locked out by the tenth failure.
+        assert!(!policy.locks_out(&attempts(10, 100), 200));
+        // The next failure after the lockout ended starts another one.
+        assert!(policy.locks_out(&attempts(11, 100), 100 + 15 * 60));
+    }
+}
@@ -0,0 +1,87 @@
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use redis::aio::MultiplexedConnection;
+
+use super::{ AttemptStore, Attempts };
+
+/// Takes back one attempt without creating the key or going below zero.
+static FORGIVE: Lazy<redis::Script> = Lazy::new(|| {
+    redis::Script::new(
+        r"
+        local failures = tonumber(redis.call('HGET', KEYS[1], 'failures'))
+        if failures and failures > 0 then
+            redis.call('HINCRBY', KEYS[1], 'failures', -1)
+        end
+        "
+    )
+});
+
+/// Shares failed attempts between instances, every key is a hash that expires on its own.
+pub struct RedisAttemptStore {
+    connection: MultiplexedConnection,
+}
+
+impl RedisAttemptStore {
+    pub async fn connect(url: &str) -> Result<Self, redis::RedisError> {
+        let client = redis::Client::open(url)?;
+        let connection = client.get_multiplexed_async_connection().await?;
+        Ok(Self { connection })
+    }
+}
+
+#[async_trait]
+impl AttemptStore for RedisAttemptStore {
+    async fn record_attempt(
+        &self,
+        key: &str,
+        now: i64,
+        ttl_seconds: u64
+    ) -> Result<Attempts, String> {
+        let mut connection = self.connection.clone();
+        // MULTI runs the read and the updates without other clients in between.
+        let ((failures, last_failure_at),): ((Option<u32>, Option<i64>),) = redis
+            ::pipe()
+            .atomic()
+            .cmd("HMGET")
+            .arg(key)
+            .arg("failures")
+            .arg("last_failure_at")
+            .cmd("HINCRBY")
+            .arg(key)
+            .arg("failures")
+            .arg(1)
+            .ignore()
+            .cmd("HSET")
+            .arg(key)
+            .arg("last_failure_at")
+            .arg(now)
+            .ignore()
+            .cmd("EXPIRE")
+            .arg(key)
+            .arg(ttl_seconds)
+            .ignore()
+            .query_async(&mut connection).await
+            .map_err(|e| e.to_string())?;
+
+        Ok(Attempts {
+            failures: failures.unwrap_or(0),
+            last_failure_at: last_failure_at.unwrap_or(0),
+        })
+    }
+
+    async fn forgive(&self, key: &str) -> Result<(), String> {
+        let mut connection = self.connection.clone();
+        FORGIVE.key(key)
+            .invoke_async::<()>(&mut connection).await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), String> {
+        let mut connection = self.connection.clone();
+        redis
+            ::cmd("DEL")
+            .arg(key)
+            .query_async::<()>(&mut connection).await
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,147 @@
+use axum::http::StatusCode;
+use chrono::{ Duration, Utc };
+use serde::{ Deserialize, Serialize };
+use totp_rs::{ Algorithm, Secret, TOTP };
+use uuid::Uuid;
+
+use crate::{
+    auth::{ access_token::{ decode_token, encode_token }, keys::Keys, opaque_token::random_string },
+    db::mfa::{ get_totp_state, record_totp_step, use_recovery_code },
+    models::user::User,
+    state::AppState,
+};
+
+const MFA_PURPOSE: &str = "mfa";
+const TOTP_ISSUER: &str = "tick-tack";
+const TOTP_STEP: u64 = 30;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Claims of the short-lived token proving the password check passed while the second factor
+/// is still outstanding. It is not an access token and is rejected by the `auth` middleware.
+#[derive(Deserialize, Serialize)]
+pub struct MfaClaims {
+    pub user_id: String,
+    pub purpose: String,
+    /// The organization whose identity provider the first factor was checked by.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sso_organization_id: Option<String>,
+    pub exp: usize,
+    pub iat: usize,
+}
+
+/// The login waiting for its second factor.
+pub struct MfaChallenge {
+    pub user_id: Uuid,
+    pub sso_organization_id: Option<Uuid>,
+}
+
+pub fn encode_mfa_token(
+    keys: &Keys,
+    user_id: &Uuid,
+    sso_organization_id: Option<&Uuid>
+) -> Result<String, StatusCode> {
+    let now = Utc::now();
+    let claims = MfaClaims {
+        user_id: user_id.to_string(),
+        purpose: MFA_PURPOSE.to_string(),
+        sso_organization_id: sso_organization_id.map(Uuid::to_string),
+        exp: (now + Duration::minutes(5)).timestamp() as usize,
+        iat: now.timestamp() as usize,
+    };
+
+    encode_token(keys, &claims)
+}
+
+pub fn decode_mfa_token(keys: &Keys, token: &str) -> Result<MfaChallenge, StatusCode> {
+    let token = decode_token::<MfaClaims>(keys, token)?;
+    if token.claims.purpose != MFA_PURPOSE {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let parse = |id: &str| Uuid::parse_str(id).map_err(|_| StatusCode::UNAUTHORIZED);
+    Ok(MfaChallenge {
+        user_id: parse(&token.claims.user_id)?,
+        sso_organization_id: token.claims.sso_organization_id.as_deref().map(parse).transpose()?,
+    })
+}
+
+pub fn generate_totp_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+fn totp(secret: &str, email: &str) -> Option<TOTP> {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        TOTP_STEP,
+        secret,
+        Some(TOTP_ISSUER.to_string()),
+        email.to_string()
+    ).ok()
+}
+
+/// The `otpauth://` URI authenticator apps enrol from, usually shown as a QR code.
+pub fn totp_provisioning_uri(secret: &str, email: &str) -> Option<String> {
+    totp(secret, email).map(|totp| totp.get_url())
+}
+
+/// Checks a code against the current time step and one step either side to allow for clock
+/// drift. Returns the matched step so callers can refuse to accept it twice.
+pub fn verify_totp_code(secret: &str, email: &str, code: &str) -> Option<i64> {
+    let totp = totp(secret, email)?;
+    let now = Utc::now().timestamp() as u64;
+    let current_step = now / TOTP_STEP;
+
+    [current_step - 1, current_step, current_step + 1]
+        .into_iter()
+        .find(|step| {
+            let expected = totp.generate(step * TOTP_STEP);
+            expected.len() == code.len() &&
+                expected
+                    .bytes()
+                    .zip(code.bytes())
+                    .fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+        })
+        .map(|step| step as i64)
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = random_string(10).to_lowercase();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// Recovery codes are compared without the dash and case insensitively.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.trim().replace('-', "").to_lowercase()
+}
+
+/// Checks the second factor of a user with TOTP enabled, either a TOTP code, which is only
+/// accepted once, or a recovery code, which is used up.
+pub async fn verify_second_factor(
+    user: &User,
+    code: Option<&str>,
+    recovery_code: Option<&str>,
+    ctx: &AppState
+) -> Result<bool, sqlx::Error> {
+    let state = get_totp_state(&user.id, ctx).await?;
+    let (Some(secret), Some(_)) = (state.secret, state.enabled_at) else {
+        return Ok(false);
+    };
+
+    match (code, recovery_code) {
+        (Some(code), _) =>
+            match verify_totp_code(&secret, &user.email, code.trim()) {
+                Some(step) => record_totp_step(&user.id, step, ctx).await,
+                None => Ok(false),
+            }
+        (None, Some(recovery_code)) => {
+            use_recovery_code(&user.id, &normalize_recovery_code(recovery_code), ctx).await
+        }
+        (None, None) => Ok(false),
+    }
+}
@@ -1,3 +1,14 @@
 pub mod keys;
 pub mod access_token;
 pub mod authorization_middleware;
+pub mod opaque_token;
+pub mod client_info;
+pub mod revocation;
+pub mod mfa;
+pub mod session;
+pub mod login_throttle;
+pub mod oidc;
+pub mod sso;
+pub mod api_key;
+pub mod passkey;
+pub mod organization_access;
@@ -0,0 +1,49 @@
+use http::header::{ AUTHORIZATION, USER_AGENT };
+use serde::Deserialize;
+
+use super::{ send_json, ProviderIdentity };
+
+pub const AUTHORIZATION_ENDPOINT: &str = "https://github.com/login/oauth/authorize";
+pub const TOKEN_ENDPOINT: &str = "https://github.com/login/oauth/access_token";
+pub const SCOPES: &str = "read:user user:email";
+const API_URL: &str = "https://api.github.com";
+
+#[derive(Deserialize)]
+struct GithubUser {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct GithubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+fn api_request(http: &reqwest::Client, path: &str, access_token: &str) -> reqwest::RequestBuilder {
+    http.get(format!("{}{}", API_URL, path))
+        .header(AUTHORIZATION, format!("Bearer {}", access_token))
+        // GitHub rejects API requests without a user agent.
+        .header(USER_AGENT, "tick-tack")
+}
+
+/// GitHub has no id token, the user and their primary email are read from the API instead.
+pub async fn fetch_identity(
+    http: &reqwest::Client,
+    access_token: &str
+) -> Result<ProviderIdentity, String> {
+    let user: GithubUser = send_json(api_request(http, "/user", access_token)).await.map_err(|e|
+        format!("Failed to fetch GitHub user: {}", e)
+    )?;
+    let emails: Vec<GithubEmail> = send_json(
+        api_request(http, "/user/emails", access_token)
+    ).await.map_err(|e| format!("Failed to fetch GitHub emails: {}", e))?;
+
+    let email = emails.into_iter().find(|email| email.primary && email.verified);
+
+    Ok(ProviderIdentity {
+        subject: user.id.to_string(),
+        email_verified: email.is_some(),
+        email: email.map(|email| email.email),
+    })
+}
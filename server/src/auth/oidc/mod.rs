@@ -0,0 +1,381 @@
+use std::{ env, time::Duration };
+
+use base64::{ engine::general_purpose::URL_SAFE_NO_PAD, Engine };
+use http::header::ACCEPT;
+use jsonwebtoken::{ jwk::JwkSet, Algorithm, DecodingKey, Validation };
+use log::info;
+use reqwest::{ RequestBuilder, Url };
+use serde::{ de::DeserializeOwned, Deserialize };
+use sha2::{ Digest, Sha256 };
+use tokio::sync::OnceCell;
+
+use crate::auth::opaque_token::random_string;
+
+use public_network::{ check_public_url, public_client, SSO_ALLOW_PRIVATE_NETWORKS };
+
+pub mod github;
+pub mod public_network;
+
+const GOOGLE_ISSUER: &str = "https://accounts.google.com";
+const OIDC_SCOPES: &str = "openid email profile";
+/// Id token algorithms accepted from providers, shared secrets are never accepted.
+const ID_TOKEN_ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// Endpoints of an OpenID provider, read from its discovery document.
+#[derive(Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+enum ProviderKind {
+    /// Any OpenID Connect provider, its endpoints are discovered on first use.
+    Oidc {
+        issuer: String,
+        discovery: OnceCell<Discovery>,
+    },
+    /// GitHub only speaks OAuth2, the identity comes from its REST API.
+    Github,
+}
+
+pub struct Provider {
+    pub name: String,
+    client_id: String,
+    client_secret: String,
+    kind: ProviderKind,
+    /// Only talk to https URLs on public addresses, for providers configured by users.
+    public_only: bool,
+}
+
+/// The user as asserted by the provider.
+pub struct ProviderIdentity {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+}
+
+/// Where to send the browser to log in. The state, nonce and code verifier have to be kept
+/// until the callback.
+pub struct AuthorizationRequest {
+    pub url: String,
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    id_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    /// Some providers send the flag as a string.
+    email_verified: Option<serde_json::Value>,
+}
+
+/// The configured login providers together with the HTTP clients used to talk to them.
+pub struct OidcProviders {
+    providers: Vec<Provider>,
+    http: reqwest::Client,
+    public_http: reqwest::Client,
+}
+
+/// Base URL of this server as seen by browsers, used for the redirect URIs.
+pub fn api_url() -> String {
+    env::var("API_URL").unwrap_or_else(|_| "http://localhost:3001".to_string())
+}
+
+async fn send_json<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, String> {
+    request
+        .header(ACCEPT, "application/json")
+        .send().await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| e.to_string())?
+        .json::<T>().await
+        .map_err(|e| e.to_string())
+}
+
+impl OidcProviders {
+    /// Loads the providers listed in `OIDC_PROVIDERS`, e.g. `google,github,mock`. Every provider
+    /// needs `OIDC_<NAME>_CLIENT_ID` and `OIDC_<NAME>_CLIENT_SECRET`, generic OpenID providers
+    /// also `OIDC_<NAME>_ISSUER`. The endpoints of `google` and `github` are built in.
+    pub fn from_env() -> Self {
+        let providers: Vec<Provider> = env
+            ::var("OIDC_PROVIDERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(Provider::from_env)
+            .collect();
+
+        if !providers.is_empty() {
+            info!(
+                "Loaded OIDC providers {:?}",
+                providers
+                    .iter()
+                    .map(|provider| provider.name.as_str())
+                    .collect::<Vec<_>>()
+            );
+        }
+
+        Self::new(providers)
+    }
+
+    pub fn new(providers: Vec<Provider>) -> Self {
+        Self {
+            providers,
+            http: reqwest::Client
+                ::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("Failed to create HTTP client"),
+            public_http: public_client(Duration::from_secs(10)),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Provider> {
+        self.providers.iter().find(|provider| provider.name == name)
+    }
+
+    /// The client for the provider, providers configured by users only reach public addresses.
+    pub fn http_for(&self, provider: &Provider) -> &reqwest::Client {
+        if provider.public_only { &self.public_http } else { &self.http }
+    }
+}
+
+impl Provider {
+    fn from_env(name: &str) -> Self {
+        let var = |suffix: &str| {
+            let key = format!("OIDC_{}_{}", name.to_uppercase(), suffix);
+            env::var(&key).unwrap_or_else(|_| panic!("{} is not set", key))
+        };
+
+        let kind = match name {
+            "github" => ProviderKind::Github,
+            "google" =>
+                ProviderKind::Oidc {
+                    issuer: GOOGLE_ISSUER.to_string(),
+                    discovery: OnceCell::new(),
+                },
+            _ =>
+                ProviderKind::Oidc {
+                    issuer: var("ISSUER"),
+                    discovery: OnceCell::new(),
+                },
+        };
+
+        Self {
+            name: name.to_string(),
+            client_id: var("CLIENT_ID"),
+            client_secret: var("CLIENT_SECRET"),
+            kind,
+            public_only: false,
+        }
+    }
+
+    /// A generic OpenID Connect provider.
+    pub fn oidc(name: &str, issuer: &str, client_id: &str, client_secret: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            kind: ProviderKind::Oidc {
+                issuer: issuer.to_string(),
+                discovery: OnceCell::new(),
+            },
+            public_only: false,
+        }
+    }
+
+    /// The identity provider of an organization. Its issuer and the endpoints it announces must
+    /// be https URLs on public addresses unless `SSO_ALLOW_PRIVATE_NETWORKS` is enabled.
+    pub fn organization(name: &str, issuer: &str, client_id: &str, client_secret: &str) -> Self {
+        Self {
+            public_only: !*SSO_ALLOW_PRIVATE_NETWORKS,
+            ..Self::oidc(name, issuer, client_id, client_secret)
+        }
+    }
+
+    /// Checks that the provider can be reached, i.e. that its discovery document can be loaded.
+    pub async fn check(&self, http: &reqwest::Client) -> Result<(), String> {
+        self.discovery(http).await.map(|_| ())
+    }
+
+    /// The discovery document of OpenID providers, `None` for GitHub.
+    async fn discovery(&self, http: &reqwest::Client) -> Result<Option<&Discovery>, String> {
+        match &self.kind {
+            ProviderKind::Oidc { issuer, discovery } =>
+                discover(issuer, discovery, self.public_only, http).await.map(Some),
+            ProviderKind::Github => Ok(None),
+        }
+    }
+
+    /// Builds the authorization URL with a fresh state, nonce and PKCE challenge.
+    pub async fn authorization_request(
+        &self,
+        http: &reqwest::Client,
+        redirect_uri: &str
+    ) -> Result<AuthorizationRequest, String> {
+        let (endpoint, scopes) = match self.discovery(http).await? {
+            Some(discovery) => (discovery.authorization_endpoint.clone(), OIDC_SCOPES),
+            None => (github::AUTHORIZATION_ENDPOINT.to_string(), github::SCOPES),
+        };
+
+        let state = random_string(32);
+        let nonce = random_string(32);
+        let code_verifier = random_string(64);
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+        let url = Url::parse_with_params(
+            &endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.client_id),
+                ("redirect_uri", redirect_uri),
+                ("scope", scopes),
+                ("state", &state),
+                ("nonce", &nonce),
+                ("code_challenge", &code_challenge),
+                ("code_challenge_method", "S256"),
+            ]
+        ).map_err(|e| format!("Invalid authorization endpoint {}: {}", endpoint, e))?;
+
+        Ok(AuthorizationRequest {
+            url: url.to_string(),
+            state,
+            nonce,
+            code_verifier,
+        })
+    }
+
+    /// Redeems the authorization code and returns the identity it was issued for. Id tokens are
+    /// checked for their signature, issuer, audience, expiry and the nonce of the login.
+    pub async fn exchange_code(
+        &self,
+        http: &reqwest::Client,
+        code: &str,
+        redirect_uri: &str,
+        code_verifier: &str,
+        nonce: &str
+    ) -> Result<ProviderIdentity, String> {
+        let discovery = self.discovery(http).await?;
+        let token_endpoint = discovery
+            .map(|discovery| discovery.token_endpoint.as_str())
+            .unwrap_or(github::TOKEN_ENDPOINT);
+
+        let tokens: TokenResponse = send_json(
+            http
+                .post(token_endpoint)
+                .form(
+                    &[
+                        ("grant_type", "authorization_code"),
+                        ("code", code),
+                        ("redirect_uri", redirect_uri),
+                        ("client_id", &self.client_id),
+                        ("client_secret", &self.client_secret),
+                        ("code_verifier", code_verifier),
+                    ]
+                )
+        ).await.map_err(|e| format!("Token request failed: {}", e))?;
+
+        match discovery {
+            Some(discovery) => {
+                let id_token = tokens.id_token.ok_or("Token response without id_token")?;
+                self.verify_id_token(http, discovery, &id_token, nonce).await
+            }
+            None => github::fetch_identity(http, &tokens.access_token).await,
+        }
+    }
+
+    async fn verify_id_token(
+        &self,
+        http: &reqwest::Client,
+        discovery: &Discovery,
+        id_token: &str,
+        nonce: &str
+    ) -> Result<ProviderIdentity, String> {
+        let header = jsonwebtoken::decode_header(id_token).map_err(|e| e.to_string())?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(format!("Unsupported id token algorithm {:?}", header.alg));
+        }
+
+        // The keys are fetched for every login so key rotations at the provider need no restart.
+        let jwks: JwkSet = send_json(http.get(&discovery.jwks_uri)).await.map_err(|e|
+            format!("Failed to fetch JWKS: {}", e)
+        )?;
+        let jwk = match &header.kid {
+            Some(kid) => jwks.find(kid),
+            None => jwks.keys.first(),
+        }.ok_or("Id token signed with an unknown key")?;
+        let key = DecodingKey::from_jwk(jwk).map_err(|e| e.to_string())?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_issuer(&[&discovery.issuer]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let claims = jsonwebtoken
+            ::decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|e| format!("Invalid id token: {}", e))?.claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err("Id token nonce does not match".to_string());
+        }
+
+        let email_verified = match claims.email_verified {
+            Some(serde_json::Value::Bool(verified)) => verified,
+            Some(serde_json::Value::String(verified)) => verified == "true",
+            _ => false,
+        };
+
+        Ok(ProviderIdentity {
+            subject: claims.sub,
+            email: claims.email,
+            email_verified,
+        })
+    }
+}
+
+async fn discover<'a>(
+    issuer: &str,
+    discovery: &'a OnceCell<Discovery>,
+    public_only: bool,
+    http: &reqwest::Client
+) -> Result<&'a Discovery, String> {
+    discovery.get_or_try_init(|| async {
+        if public_only {
+            check_public_url(issuer)?;
+        }
+        let url = format!("{}/.well-known/openid-configuration", issuer.trim_end_matches('/'));
+        let document: Discovery = send_json(http.get(&url)).await.map_err(|e|
+            format!("Discovery of {} failed: {}", issuer, e)
+        )?;
+        if document.issuer.trim_end_matches('/') != issuer.trim_end_matches('/') {
+            return Err(format!("Discovery of {} returned issuer {}", issuer, document.issuer));
+        }
+        // The authorization endpoint is only visited by browsers.
+        if public_only {
+            check_public_url(&document.token_endpoint)?;
+            check_public_url(&document.jwks_uri)?;
+        }
+        Ok(document)
+    }).await
+}
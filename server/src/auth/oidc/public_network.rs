@@ -0,0 +1,191 @@
+use std::{
+    env,
+    net::{ IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr },
+    sync::Arc,
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use reqwest::{ dns::{ Addrs, Name, Resolve, Resolving }, redirect, Url };
+
+/// Lifts the restrictions on identity providers of organizations, for local development against
+/// the mock provider. Never enable it in production.
+pub static SSO_ALLOW_PRIVATE_NETWORKS: Lazy<bool> = Lazy::new(|| {
+    env::var("SSO_ALLOW_PRIVATE_NETWORKS").map(|value| value == "true").unwrap_or(false)
+});
+
+/// Whether the address is reachable on the internet, as opposed to loopback, private, link-local
+/// and other special purpose ranges.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) =>
+            match ip.to_ipv4_mapped() {
+                Some(mapped) => is_public_ipv4(mapped),
+                None => is_public_ipv6(ip),
+            }
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(
+        ip.is_unspecified() ||
+        ip.is_loopback() ||
+        ip.is_private() ||
+        ip.is_link_local() ||
+        ip.is_broadcast() ||
+        ip.is_documentation() ||
+        ip.is_multicast() ||
+        // "This network", shared address space, IETF protocol assignments, benchmarking and
+        // reserved ranges.
+        a == 0 ||
+        (a == 100 && (64..128).contains(&b)) ||
+        (a == 192 && b == 0 && c == 0) ||
+        (a == 198 && (18..20).contains(&b)) ||
+        a >= 240
+    )
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    let [first, second, ..] = ip.segments();
+    !(
+        ip.is_unspecified() ||
+        ip.is_loopback() ||
+        ip.is_multicast() ||
+        // Unique local, link-local and documentation ranges.
+        (first & 0xfe00) == 0xfc00 ||
+        (first & 0xffc0) == 0xfe80 ||
+        (first == 0x2001 && second == 0x0db8)
+    )
+}
+
+/// Requires an https URL whose host, when it is an IP address, is public. Host names are checked
+/// when they are resolved, see [`public_client`].
+pub fn check_public_url(url: &str) -> Result<(), String> {
+    let url = Url::parse(url).map_err(|e| format!("Invalid URL {}: {}", url, e))?;
+    if url.scheme() != "https" {
+        return Err(format!("{} does not use https", url));
+    }
+    let public = match url.host_str() {
+        Some(host) =>
+            match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+                Ok(ip) => is_public_ip(ip),
+                Err(_) => true,
+            }
+        None => false,
+    };
+    if !public {
+        return Err(format!("{} is not a public address", url));
+    }
+    Ok(())
+}
+
+/// Resolves host names like the system does but fails when any of the addresses is not public,
+/// so a name cannot be pointed at the internal network after it has been checked.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net
+                ::lookup_host((host.as_str(), 0)).await?
+                .collect();
+            if let Some(addr) = addrs.iter().find(|addr| !is_public_ip(addr.ip())) {
+                let message = format!("{} resolves to {}, which is not public", host, addr.ip());
+                return Err(message.into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// An HTTP client that only connects to public addresses over https, redirects included. Used
+/// for identity providers configured by organizations, whose URLs must not reach the server's own
+/// network.
+pub fn public_client(timeout: Duration) -> reqwest::Client {
+    reqwest::Client
+        ::builder()
+        .timeout(timeout)
+        .https_only(true)
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(
+            redirect::Policy::custom(|attempt| {
+                if attempt.previous().len() >= 10 {
+                    return attempt.error("Too many redirects");
+                }
+                match check_public_url(attempt.url().as_str()) {
+                    Ok(()) => attempt.follow(),
+                    Err(e) => attempt.error(e),
+                }
+            })
+        )
+        .build()
+        .expect("Failed to create HTTP client")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{} should not be public", ip);
+        }
+    }
+
+    #[test]
+    fn internet_addresses_are_public() {
+        for ip in ["8.8.8.8", "1.1.1.1", "2606:4700:4700::1111", "::ffff:8.8.8.8"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{} should be public", ip);
+        }
+    }
+
+    #[test]
+    fn only_https_urls_of_public_hosts_are_allowed() {
+        assert!(check_public_url("https://login.example.com/realm").is_ok());
+        assert!(check_public_url("https://8.8.8.8/").is_ok());
+        assert!(check_public_url("http://login.example.com").is_err());
+        assert!(check_public_url("https://127.0.0.1:8080/default").is_err());
+        assert!(check_public_url("https://[::1]/").is_err());
+        assert!(check_public_url("https://169.254.169.254/latest").is_err());
+        assert!(check_public_url("file:///etc/passwd").is_err());
+        assert!(check_public_url("not a url").is_err());
+    }
+
+    #[tokio::test]
+    async fn host_names_resolving_to_internal_addresses_are_refused() {
+        let error = public_client(Duration::from_secs(5))
+            .get("https://localhost:8089/.well-known/openid-configuration")
+            .send().await
+            .unwrap_err();
+
+        let mut source: Option<&dyn std::error::Error> = Some(&error);
+        let mut messages = Vec::new();
+        while let Some(error) = source {
+            messages.push(error.to_string());
+            source = error.source();
+        }
+        assert!(
+            messages.iter().any(|message| message.contains("which is not public")),
+            "{:?}",
+            messages
+        );
+    }
+}
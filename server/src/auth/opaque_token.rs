@@ -0,0 +1,84 @@
+use std::{ env, fmt };
+
+use hmac::{ Hmac, Mac };
+use once_cell::sync::Lazy;
+use rand::Rng;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const SELECTOR_LENGTH: usize = 16;
+const VERIFIER_LENGTH: usize = 48;
+
+static TOKEN_HASH_SECRET: Lazy<String> = Lazy::new(|| env::var("TOKEN_HASH_SECRET").unwrap());
+
+/// A random token handed out to clients. Only the selector is stored in clear text so the row
+/// can be looked up, the verifier part is stored as a keyed hash.
+pub struct OpaqueToken {
+    pub selector: String,
+    verifier: String,
+}
+
+pub fn random_string(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+fn mac() -> HmacSha256 {
+    HmacSha256::new_from_slice(TOKEN_HASH_SECRET.as_bytes()).expect("HMAC accepts keys of any size")
+}
+
+/// Keyed hash of a secret value, for secrets that are stored but never read back.
+pub fn keyed_hash(value: &str) -> Vec<u8> {
+    let mut mac = mac();
+    mac.update(value.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Compares a value against a stored [`keyed_hash`] in constant time.
+pub fn verify_keyed_hash(value: &str, hash: &[u8]) -> bool {
+    let mut mac = mac();
+    mac.update(value.as_bytes());
+    mac.verify_slice(hash).is_ok()
+}
+
+impl OpaqueToken {
+    pub fn generate() -> Self {
+        Self {
+            selector: random_string(SELECTOR_LENGTH),
+            verifier: random_string(VERIFIER_LENGTH),
+        }
+    }
+
+    pub fn parse(token: &str) -> Option<Self> {
+        if
+            token.len() != SELECTOR_LENGTH + VERIFIER_LENGTH ||
+            !token.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            return None;
+        }
+        let (selector, verifier) = token.split_at(SELECTOR_LENGTH);
+        Some(Self {
+            selector: selector.to_string(),
+            verifier: verifier.to_string(),
+        })
+    }
+
+    pub fn hash(&self) -> Vec<u8> {
+        keyed_hash(&self.verifier)
+    }
+
+    /// Compares the verifier against a stored hash in constant time.
+    pub fn verify(&self, hash: &[u8]) -> bool {
+        verify_keyed_hash(&self.verifier, hash)
+    }
+}
+
+impl fmt::Display for OpaqueToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.selector, self.verifier)
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+
+use axum::{ async_trait, extract::{ FromRequestParts, Path }, Json };
+use http::{ request::Parts, StatusCode };
+use sqlx::{ Postgres, Transaction };
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        organization::get_organization_id_by_slug,
+        role::{ get_membership, Membership },
+        tenant::begin_tenant_transaction,
+    },
+    state::AppState,
+};
+
+use super::{ api_key::Scope, authorization_middleware::AuthExtension };
+
+/// Selects the organization for routes that do not have it in their path.
+pub const ORGANIZATION_HEADER: &str = "x-organization-id";
+
+/// What a member may do inside an organization.
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Permission {
+    #[serde(rename = "organization:read")]
+    OrganizationRead,
+    #[serde(rename = "organization:update")]
+    OrganizationUpdate,
+    #[serde(rename = "organization:delete")]
+    OrganizationDelete,
+    #[serde(rename = "members:manage")]
+    MembersManage,
+    #[serde(rename = "roles:manage")]
+    RolesManage,
+    #[serde(rename = "sso:manage")]
+    SsoManage,
+    #[serde(rename = "teams:manage")]
+    TeamsManage,
+    #[serde(rename = "projects:manage")]
+    ProjectsManage,
+    /// Tracking one's own time.
+    #[serde(rename = "time:track")]
+    TimeTrack,
+    /// Seeing and changing the time entries of every member.
+    #[serde(rename = "time:manage")]
+    TimeManage,
+    /// Setting what the time of members is billed at.
+    #[serde(rename = "rates:manage")]
+    RatesManage,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 11] = [
+        Permission::OrganizationRead,
+        Permission::OrganizationUpdate,
+        Permission::OrganizationDelete,
+        Permission::MembersManage,
+        Permission::RolesManage,
+        Permission::SsoManage,
+        Permission::TeamsManage,
+        Permission::ProjectsManage,
+        Permission::TimeTrack,
+        Permission::TimeManage,
+        Permission::RatesManage,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::OrganizationRead => "organization:read",
+            Permission::OrganizationUpdate => "organization:update",
+            Permission::OrganizationDelete => "organization:delete",
+            Permission::MembersManage => "members:manage",
+            Permission::RolesManage => "roles:manage",
+            Permission::SsoManage => "sso:manage",
+            Permission::TeamsManage => "teams:manage",
+            Permission::ProjectsManage => "projects:manage",
+            Permission::TimeTrack => "time:track",
+            Permission::TimeManage => "time:manage",
+            Permission::RatesManage => "rates:manage",
+        }
+    }
+
+    pub fn parse(permission: &str) -> Option<Self> {
+        Permission::ALL.into_iter().find(|candidate| candidate.as_str() == permission)
+    }
+
+    /// The scope an API key needs on top of the permission.
+    pub fn scope(&self) -> Scope {
+        match self {
+            Permission::OrganizationRead => Scope::OrganizationsRead,
+            Permission::TimeTrack | Permission::TimeManage => Scope::TimeWrite,
+            _ => Scope::OrganizationsWrite,
+        }
+    }
+}
+
+/// The built-in roles every organization has.
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Owner,
+    Admin,
+    Member,
+    Viewer,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Owner, Role::Admin, Role::Member, Role::Viewer];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Admin => "admin",
+            Role::Member => "member",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Self> {
+        Role::ALL.into_iter().find(|candidate| candidate.as_str() == role)
+    }
+
+    /// Admins can do everything except deleting the organization and changing its single sign-on,
+    /// which stay with the owner. Members track their own time, viewers can only look.
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            Role::Owner => &Permission::ALL,
+            Role::Admin =>
+                &[
+                    Permission::OrganizationRead,
+                    Permission::OrganizationUpdate,
+                    Permission::MembersManage,
+                    Permission::RolesManage,
+                    Permission::TeamsManage,
+                    Permission::ProjectsManage,
+                    Permission::TimeTrack,
+                    Permission::TimeManage,
+                    Permission::RatesManage,
+                ],
+            Role::Member => &[Permission::OrganizationRead, Permission::TimeTrack],
+            Role::Viewer => &[Permission::OrganizationRead],
+        }
+    }
+}
+
+/// The role a member has in an organization.
+#[derive(Clone)]
+pub enum MemberRole {
+    BuiltIn(Role),
+    Custom {
+        name: String,
+        permissions: Vec<Permission>,
+    },
+}
+
+/// Permissions stored in the database, unknown ones are ignored.
+pub fn parse_permissions(permissions: &[String]) -> Vec<Permission> {
+    permissions
+        .iter()
+        .filter_map(|permission| Permission::parse(permission))
+        .collect()
+}
+
+impl MemberRole {
+    pub fn from_membership(membership: Membership) -> Option<Self> {
+        match (membership.role.as_deref().and_then(Role::parse), membership.custom_role_id) {
+            (Some(role), _) => Some(MemberRole::BuiltIn(role)),
+            (None, Some(_)) =>
+                Some(MemberRole::Custom {
+                    name: membership.custom_role_name.unwrap_or_default(),
+                    permissions: parse_permissions(
+                        &membership.custom_role_permissions.unwrap_or_default()
+                    ),
+                }),
+            (None, None) => None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            MemberRole::BuiltIn(role) => role.as_str(),
+            MemberRole::Custom { name, .. } => name,
+        }
+    }
+
+    pub fn permissions(&self) -> &[Permission] {
+        match self {
+            MemberRole::BuiltIn(role) => role.permissions(),
+            MemberRole::Custom { permissions, .. } => permissions,
+        }
+    }
+
+    pub fn is_owner(&self) -> bool {
+        matches!(self, MemberRole::BuiltIn(Role::Owner))
+    }
+}
+
+/// The authenticated user as a member of the organization in the `organization_id` path
+/// parameter, or in the `X-Organization-Id` header on routes without it. Both can be the id or the
+/// slug of the organization. Requires the `auth` middleware. Users who are not members, and API
+/// keys of other organizations, get a 404 as if the organization did not exist.
+#[derive(Clone)]
+pub struct OrganizationMember {
+    pub organization_id: Uuid,
+    pub role: MemberRole,
+    /// Granted through teams on top of the role.
+    pub team_permissions: Vec<Permission>,
+    pub auth: AuthExtension,
+}
+
+fn forbidden(message: String) -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": message,
+    });
+    (StatusCode::FORBIDDEN, Json(error_response))
+}
+
+impl OrganizationMember {
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.role.permissions().contains(&permission) ||
+            self.team_permissions.contains(&permission)
+    }
+
+    /// Everything the member may do, from the role and from teams.
+    pub fn permissions(&self) -> Vec<Permission> {
+        Permission::ALL.into_iter()
+            .filter(|permission| self.has_permission(*permission))
+            .collect()
+    }
+
+    /// Requires the member's role to grant the permission and API keys to have its scope.
+    pub fn require(
+        &self,
+        permission: Permission
+    ) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+        self.auth.require_scope(permission.scope())?;
+        if !self.has_permission(permission) {
+            return Err(forbidden(format!("Missing the {} permission", permission.as_str())));
+        }
+        Ok(())
+    }
+
+    /// Members can only hand out permissions they have themselves, so nobody can raise their own
+    /// privileges through roles.
+    pub fn require_grantable(
+        &self,
+        permissions: &[Permission]
+    ) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+        match permissions.iter().find(|permission| !self.has_permission(**permission)) {
+            Some(permission) =>
+                Err(forbidden(format!("Cannot grant the {} permission", permission.as_str()))),
+            None => Ok(()),
+        }
+    }
+
+    /// Loads the membership of the user, `None` if they are not a member or the credential cannot
+    /// be used for the organization.
+    pub async fn find(
+        auth: AuthExtension,
+        organization_id: Uuid,
+        ctx: &AppState
+    ) -> Result<Option<Self>, sqlx::Error> {
+        if !auth.can_access_organization(&organization_id) {
+            return Ok(None);
+        }
+
+        // Validating the membership decides which organization the request may see, so it
+        // cannot run inside the tenant transaction yet.
+        let membership = get_membership(&auth.user.id, &organization_id, &ctx.db).await?;
+        let Some(membership) = membership else {
+            return Ok(None);
+        };
+        let team_permissions = parse_permissions(&membership.team_permissions);
+
+        Ok(
+            MemberRole::from_membership(membership).map(|role| Self {
+                organization_id,
+                role,
+                team_permissions,
+                auth,
+            })
+        )
+    }
+
+    /// Starts a transaction in which row-level security hides the data of every other
+    /// organization. Queries on organization data go through it.
+    pub async fn begin_tenant(
+        &self,
+        ctx: &AppState
+    ) -> Result<Transaction<'static, Postgres>, (StatusCode, Json<serde_json::Value>)> {
+        begin_tenant_transaction(&self.organization_id, &ctx.db).await.map_err(|_e|
+            internal_error()
+        )
+    }
+}
+
+fn internal_error() -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": "Failed to fetch organization",
+    });
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+fn organization_not_found() -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": "Organization not found",
+    });
+    (StatusCode::NOT_FOUND, Json(error_response))
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for OrganizationMember where S: Send + Sync {
+    type Rejection = (StatusCode, Json<serde_json::Value>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let (Some(auth), Some(ctx)) = (
+            parts.extensions.get::<AuthExtension>().cloned(),
+            parts.extensions.get::<AppState>().cloned(),
+        ) else {
+            let error_response =
+                serde_json::json!({
+                "status": "error",
+                "message": "Unauthorized",
+            });
+            return Err((StatusCode::UNAUTHORIZED, Json(error_response)));
+        };
+
+        let params = Path::<HashMap<String, String>>::from_request_parts(parts, state).await
+            .map(|Path(params)| params)
+            .unwrap_or_default();
+        let organization = match params.get("organization_id") {
+            Some(organization) => organization.clone(),
+            None =>
+                parts.headers
+                    .get(ORGANIZATION_HEADER)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string)
+                    .ok_or_else(organization_not_found)?,
+        };
+        let organization_id = match Uuid::parse_str(&organization) {
+            Ok(id) => id,
+            Err(_) =>
+                get_organization_id_by_slug(&organization, &ctx).await
+                    .map_err(|_e| internal_error())?
+                    .ok_or_else(organization_not_found)?,
+        };
+
+        OrganizationMember::find(auth, organization_id, &ctx).await
+            .map_err(|_e| internal_error())?
+            .ok_or_else(organization_not_found)
+    }
+}
@@ -0,0 +1,29 @@
+use std::{ env, sync::Arc };
+
+use log::info;
+use webauthn_rs::prelude::Url;
+use webauthn_rs::{ Webauthn, WebauthnBuilder };
+
+use crate::mailer::app_url;
+
+const RP_NAME: &str = "tick-tack";
+
+/// Builds the WebAuthn relying party once at startup. `WEBAUTHN_RP_ID` is the domain passkeys are
+/// bound to and `WEBAUTHN_RP_ORIGIN` the origin of the frontend, which defaults to `APP_URL`.
+pub fn webauthn_from_env() -> Arc<Webauthn> {
+    let origin = env::var("WEBAUTHN_RP_ORIGIN").unwrap_or_else(|_| app_url());
+    let origin = Url::parse(&origin).expect("WEBAUTHN_RP_ORIGIN is not a valid URL");
+    let rp_id = env
+        ::var("WEBAUTHN_RP_ID")
+        .ok()
+        .or_else(|| origin.host_str().map(|host| host.to_string()))
+        .expect("WEBAUTHN_RP_ID is not set");
+
+    info!("WebAuthn relying party {} for {}", rp_id, origin);
+
+    Arc::new(
+        WebauthnBuilder::new(&rp_id, &origin)
+            .and_then(|builder| builder.rp_name(RP_NAME).build())
+            .expect("Invalid WebAuthn configuration")
+    )
+}
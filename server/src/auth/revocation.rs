@@ -0,0 +1,155 @@
+use std::{ collections::HashMap, sync::{ Arc, RwLock }, time::{ Duration, Instant } };
+
+use chrono::{ DateTime, NaiveDateTime, Utc };
+use log::{ debug, error };
+use uuid::Uuid;
+
+use crate::{
+    db::auth::{
+        delete_expired_revoked_access_tokens,
+        insert_revoked_access_token,
+        is_access_token_jti_revoked,
+    },
+    state::AppState,
+};
+
+/// How long a token found not to be revoked is trusted without asking Postgres again. Revocations
+/// of this instance take effect right away, those made by other instances within this time.
+const NOT_REVOKED_TTL: Duration = Duration::from_secs(5);
+/// How often the revocations of expired tokens are deleted.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// In-memory cache in front of the `revoked_access_tokens` table. Revocations are cached until the
+/// token expires, tokens found not to be revoked only briefly so that revocations made by other
+/// instances are still seen.
+#[derive(Clone, Default)]
+pub struct RevocationCache {
+    revoked: Arc<RwLock<HashMap<Uuid, NaiveDateTime>>>,
+    not_revoked: Arc<RwLock<HashMap<Uuid, Instant>>>,
+}
+
+impl RevocationCache {
+    fn contains(&self, jti: &Uuid) -> bool {
+        self.revoked.read().unwrap().contains_key(jti)
+    }
+
+    fn is_known_not_revoked(&self, jti: &Uuid) -> bool {
+        self.not_revoked
+            .read()
+            .unwrap()
+            .get(jti)
+            .is_some_and(|until| *until > Instant::now())
+    }
+
+    fn insert_not_revoked(&self, jti: Uuid) {
+        let now = Instant::now();
+        let mut not_revoked = self.not_revoked.write().unwrap();
+        not_revoked.retain(|_, until| *until > now);
+        not_revoked.insert(jti, now + NOT_REVOKED_TTL);
+    }
+
+    /// Caches a revocation once it is stored in `revoked_access_tokens`.
+    pub fn insert(&self, jti: Uuid, expires_at: NaiveDateTime) {
+        let now = Utc::now().naive_utc();
+        let mut revoked = self.revoked.write().unwrap();
+        revoked.retain(|_, expires_at| *expires_at > now);
+        revoked.insert(jti, expires_at);
+        self.not_revoked.write().unwrap().remove(&jti);
+    }
+}
+
+pub async fn is_access_token_revoked(jti: &Uuid, ctx: &AppState) -> Result<bool, sqlx::Error> {
+    if ctx.revoked_access_tokens.contains(jti) {
+        return Ok(true);
+    }
+    if ctx.revoked_access_tokens.is_known_not_revoked(jti) {
+        return Ok(false);
+    }
+
+    match is_access_token_jti_revoked(jti, ctx).await? {
+        Some(expires_at) => {
+            ctx.revoked_access_tokens.insert(*jti, expires_at);
+            Ok(true)
+        }
+        None => {
+            ctx.revoked_access_tokens.insert_not_revoked(*jti);
+            Ok(false)
+        }
+    }
+}
+
+/// Deletes the revocations of expired tokens in the background, every `CLEANUP_INTERVAL`.
+pub fn spawn_revocation_cleanup(ctx: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            match delete_expired_revoked_access_tokens(&ctx).await {
+                Ok(deleted) => debug!("Deleted {} expired access token revocations", deleted),
+                Err(e) => error!("Failed to delete expired access token revocations: {}", e),
+            }
+        }
+    });
+}
+
+/// Until when the revocation of an access token with the `exp` claim has to be kept, after that
+/// it is rejected as expired anyway.
+pub fn access_token_expiry(exp: usize) -> NaiveDateTime {
+    DateTime::from_timestamp(exp as i64, 0).unwrap_or_else(Utc::now).naive_utc()
+}
+
+/// Revokes an access token until it expires on its own.
+pub async fn revoke_access_token(
+    jti: &Uuid,
+    user_id: &Uuid,
+    exp: usize,
+    ctx: &AppState
+) -> Result<(), sqlx::Error> {
+    let expires_at = access_token_expiry(exp);
+
+    insert_revoked_access_token(jti, user_id, expires_at, &ctx.db).await?;
+    ctx.revoked_access_tokens.insert(*jti, expires_at);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{ Duration, Utc };
+    use sqlx::{ query_scalar, PgPool };
+    use uuid::Uuid;
+
+    use super::is_access_token_revoked;
+    use crate::db::auth::{ delete_expired_revoked_access_tokens, insert_revoked_access_token };
+    use crate::test_support::app_state;
+
+    #[sqlx::test]
+    async fn deletes_only_expired_revocations(db: PgPool) {
+        let ctx = app_state(db);
+        let user_id = query_scalar!(
+            "INSERT INTO users (email, password) VALUES ($1, 'x') RETURNING id",
+            "jane@example.com"
+        ).fetch_one(&ctx.db).await.unwrap();
+        let (expired, valid) = (Uuid::new_v4(), Uuid::new_v4());
+        let now = Utc::now().naive_utc();
+        let expired_at = now - Duration::minutes(1);
+        insert_revoked_access_token(&expired, &user_id, expired_at, &ctx.db).await.unwrap();
+        let expires_at = now + Duration::minutes(10);
+        insert_revoked_access_token(&valid, &user_id, expires_at, &ctx.db).await.unwrap();
+
+        assert_eq!(delete_expired_revoked_access_tokens(&ctx).await.unwrap(), 1);
+        assert!(is_access_token_revoked(&valid, &ctx).await.unwrap());
+    }
+
+    #[sqlx::test]
+    async fn revoking_a_token_overrides_the_cached_lookup(db: PgPool) {
+        let ctx = app_state(db);
+        let jti = Uuid::new_v4();
+        assert!(!is_access_token_revoked(&jti, &ctx).await.unwrap());
+
+        let expires_at = Utc::now().naive_utc() + Duration::minutes(10);
+        ctx.revoked_access_tokens.insert(jti, expires_at);
+
+        assert!(is_access_token_revoked(&jti, &ctx).await.unwrap());
+    }
+}
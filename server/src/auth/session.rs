@@ -0,0 +1,94 @@
+use axum::{ http::StatusCode, Json };
+use log::error;
+use uuid::Uuid;
+
+use crate::{
+    auth::{ access_token::encode_jwt, client_info::ClientInfo },
+    db::{ auth::create_refresh_token, sso::is_sso_enforced_for_user },
+    state::AppState,
+};
+
+pub struct SessionTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+pub enum SessionError {
+    /// An organization of the user only allows logging in through its identity provider.
+    SsoRequired,
+    Failed(String),
+}
+
+impl SessionError {
+    /// The response of the JSON login endpoints.
+    pub fn response(&self) -> (StatusCode, Json<serde_json::Value>) {
+        match self {
+            SessionError::SsoRequired => {
+                let error_response =
+                    serde_json::json!({
+                    "status": "sso_required",
+                    "message": "Your organization requires logging in with single sign-on",
+                });
+                (StatusCode::FORBIDDEN, Json(error_response))
+            }
+            SessionError::Failed(message) => {
+                let error_response =
+                    serde_json::json!({
+                    "status": "error",
+                    "message": message,
+                });
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+            }
+        }
+    }
+}
+
+/// Fails when an organization of the user enforces single sign-on and the login did not go
+/// through its identity provider. `sso_organization_id` is the organization whose provider was
+/// used, if any.
+pub async fn require_sso(
+    user_id: &Uuid,
+    sso_organization_id: Option<&Uuid>,
+    ctx: &AppState
+) -> Result<(), SessionError> {
+    match is_sso_enforced_for_user(user_id, sso_organization_id, ctx).await {
+        Ok(false) => Ok(()),
+        Ok(true) => Err(SessionError::SsoRequired),
+        Err(e) => {
+            error!("Failed to check single sign-on enforcement: {}", e);
+            Err(SessionError::Failed("Failed to check single sign-on settings".to_string()))
+        }
+    }
+}
+
+/// Starts a new session once the user has fully authenticated, whatever the method was. Every
+/// login method ends here, so this is where single sign-on enforcement is applied.
+pub async fn start_session(
+    user_id: &Uuid,
+    email: &str,
+    client: &ClientInfo,
+    sso_organization_id: Option<&Uuid>,
+    ctx: &AppState
+) -> Result<SessionTokens, SessionError> {
+    require_sso(user_id, sso_organization_id, ctx).await?;
+
+    let failed = |message: String| {
+        error!("Failed to start session: {}", message);
+        SessionError::Failed(message)
+    };
+
+    let refresh_token = create_refresh_token(*user_id, client, sso_organization_id, ctx).await
+        .map_err(|e| failed(format!("Failed to create refresh token: {}", e)))?;
+
+    let access_token = encode_jwt(
+        &ctx.keys,
+        user_id.to_string(),
+        email.to_string(),
+        refresh_token.session_id.to_string()
+    ).map_err(|e| failed(format!("JWT error: {}", e)))?;
+
+    Ok(SessionTokens {
+        access_token,
+        refresh_token: refresh_token.token,
+    })
+}
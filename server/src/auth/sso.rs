@@ -0,0 +1,79 @@
+use hickory_resolver::{ error::ResolveErrorKind, TokioAsyncResolver };
+use uuid::Uuid;
+
+use crate::auth::oidc::{ api_url, Provider };
+use crate::db::sso::OrganizationSso;
+
+/// Organizations prove they own a domain with a TXT record `<prefix>.<domain>` containing
+/// `<value prefix><token>`.
+const VERIFICATION_RECORD_PREFIX: &str = "_tick-tack-challenge";
+const VERIFICATION_VALUE_PREFIX: &str = "tick-tack-domain-verification=";
+
+/// The lowercase domain of an email address.
+pub fn email_domain(email: &str) -> Option<String> {
+    email
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.to_lowercase())
+        .filter(|domain| !domain.is_empty())
+}
+
+/// Lowercases a domain and rejects anything that is not a plain host name.
+pub fn normalize_domain(domain: &str) -> Option<String> {
+    let domain = domain.trim().trim_end_matches('.').to_lowercase();
+    let valid =
+        domain.contains('.') &&
+        domain.split('.').all(|label| {
+            !label.is_empty() &&
+                !label.starts_with('-') &&
+                label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    valid.then_some(domain)
+}
+
+pub fn verification_record_name(domain: &str) -> String {
+    format!("{}.{}", VERIFICATION_RECORD_PREFIX, domain)
+}
+
+pub fn verification_record_value(token: &str) -> String {
+    format!("{}{}", VERIFICATION_VALUE_PREFIX, token)
+}
+
+/// Looks up whether the verification record of the domain contains the token.
+pub async fn has_verification_record(domain: &str, token: &str) -> Result<bool, String> {
+    let resolver = TokioAsyncResolver::tokio_from_system_conf().map_err(|e| e.to_string())?;
+    let expected = verification_record_value(token);
+
+    match resolver.txt_lookup(verification_record_name(domain)).await {
+        Ok(records) =>
+            Ok(
+                records.iter().any(|record| {
+                    record
+                        .txt_data()
+                        .iter()
+                        .map(|data| String::from_utf8_lossy(data))
+                        .collect::<String>() == expected
+                })
+            ),
+        Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(false),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Name of the identities and pending logins of an organization's identity provider.
+pub fn sso_provider_name(organization_id: &Uuid) -> String {
+    format!("sso:{}", organization_id)
+}
+
+pub fn sso_provider(sso: &OrganizationSso) -> Provider {
+    Provider::organization(
+        &sso_provider_name(&sso.organization_id),
+        &sso.issuer,
+        &sso.client_id,
+        &sso.client_secret
+    )
+}
+
+/// The redirect URI to register at the organization's identity provider.
+pub fn sso_redirect_uri(organization_id: &Uuid) -> String {
+    format!("{}/api/auth/sso/{}/callback", api_url(), organization_id)
+}
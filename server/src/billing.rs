@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::{
+    db::{ hourly_rate::get_hourly_rates, project::get_projects },
+    models::{ hourly_rate::HourlyRate, time_entry::TimeEntry },
+};
+
+/// How specific a rate is. Each level overrides the ones before it.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RateLevel {
+    Organization,
+    Client,
+    Project,
+    Member,
+    ProjectMember,
+}
+
+impl RateLevel {
+    pub fn of(rate: &HourlyRate) -> Self {
+        match (rate.client_id, rate.project_id, rate.user_id) {
+            (_, Some(_), Some(_)) => RateLevel::ProjectMember,
+            (_, None, Some(_)) => RateLevel::Member,
+            (_, Some(_), None) => RateLevel::Project,
+            (Some(_), None, None) => RateLevel::Client,
+            (None, None, None) => RateLevel::Organization,
+        }
+    }
+}
+
+/// A time entry together with what it is worth.
+#[derive(serde::Serialize)]
+pub struct BilledTimeEntry {
+    #[serde(flatten)]
+    pub time_entry: TimeEntry,
+    /// The rate in effect when the entry started, in the minor unit of the currency.
+    pub hourly_rate: Option<i64>,
+    pub currency: Option<String>,
+    /// `None` without a rate and while the timer is running, zero for entries that are not
+    /// billable.
+    pub billable_amount: Option<i64>,
+}
+
+/// The rates of an organization and the clients of its projects, to price its time entries.
+pub struct RateCard {
+    rates: Vec<HourlyRate>,
+    project_clients: HashMap<Uuid, Uuid>,
+}
+
+impl RateCard {
+    pub async fn load(organization_id: &Uuid, tx: &mut PgConnection) -> Result<Self, sqlx::Error> {
+        let rates = get_hourly_rates(organization_id, &mut *tx).await?;
+        let project_clients = get_projects(organization_id, &mut *tx).await?
+            .into_iter()
+            .filter_map(|project| project.client_id.map(|client_id| (project.id, client_id)))
+            .collect();
+        Ok(Self { rates, project_clients })
+    }
+
+    /// The most specific rate for the entry, of those that were in effect when it started. A
+    /// newer rate only replaces an older one of the same level.
+    pub fn resolve(&self, entry: &TimeEntry) -> Option<&HourlyRate> {
+        let client_id = entry.project_id.and_then(|id| self.project_clients.get(&id).copied());
+        self.rates
+            .iter()
+            .filter(|rate| {
+                rate.organization_id == entry.organization_id &&
+                    rate.effective_from <= entry.started_at &&
+                    rate.client_id.is_none_or(|id| Some(id) == client_id) &&
+                    rate.project_id.is_none_or(|id| Some(id) == entry.project_id) &&
+                    rate.user_id.is_none_or(|id| id == entry.user_id)
+            })
+            .max_by_key(|rate| (RateLevel::of(rate), rate.effective_from))
+    }
+
+    pub fn bill(&self, time_entry: TimeEntry) -> BilledTimeEntry {
+        let rate = self.resolve(&time_entry);
+        let billable_amount = rate.and_then(|rate| billable_amount(rate, &time_entry));
+        BilledTimeEntry {
+            hourly_rate: rate.map(|rate| rate.amount),
+            currency: rate.map(|rate| rate.currency.clone()),
+            billable_amount,
+            time_entry,
+        }
+    }
+}
+
+/// The rate applied to the duration of the entry, rounded to the nearest minor unit.
+fn billable_amount(rate: &HourlyRate, entry: &TimeEntry) -> Option<i64> {
+    let ended_at = entry.ended_at?;
+    if !entry.billable {
+        return Some(0);
+    }
+    let seconds = (ended_at - entry.started_at).num_seconds() as i128;
+    i64::try_from(((rate.amount as i128) * seconds + 1800) / 3600).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::{ NaiveDate, NaiveDateTime };
+    use uuid::Uuid;
+
+    use super::RateCard;
+    use crate::models::{ hourly_rate::HourlyRate, time_entry::TimeEntry };
+
+    fn at(day: u32, hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2025, 1, day).unwrap().and_hms_opt(hour, 0, 0).unwrap()
+    }
+
+    struct Fixture {
+        organization_id: Uuid,
+        client_id: Uuid,
+        project_id: Uuid,
+        user_id: Uuid,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            Self {
+                organization_id: Uuid::new_v4(),
+                client_id: Uuid::new_v4(),
+                project_id: Uuid::new_v4(),
+                user_id: Uuid::new_v4(),
+            }
+        }
+
+        fn rate(
+            &self,
+            client: bool,
+            project: bool,
+            user: bool,
+            amount: i64,
+            effective_from: NaiveDateTime
+        ) -> HourlyRate {
+            HourlyRate {
+                id: Uuid::new_v4(),
+                organization_id: self.organization_id,
+                client_id: client.then_some(self.client_id),
+                project_id: project.then_some(self.project_id),
+                user_id: user.then_some(self.user_id),
+                amount,
+                currency: "EUR".to_string(),
+                effective_from,
+                created_at: effective_from,
+            }
+        }
+
+        fn card(&self, rates: Vec<HourlyRate>) -> RateCard {
+            RateCard { rates, project_clients: HashMap::from([(self.project_id, self.client_id)]) }
+        }
+
+        fn entry(&self, started_at: NaiveDateTime, hours: i64) -> TimeEntry {
+            TimeEntry {
+                id: Uuid::new_v4(),
+                organization_id: self.organization_id,
+                user_id: self.user_id,
+                project_id: Some(self.project_id),
+                started_at,
+                ended_at: Some(started_at + chrono::Duration::hours(hours)),
+                description: String::new(),
+                billable: true,
+                created_at: started_at,
+            }
+        }
+    }
+
+    #[test]
+    fn the_most_specific_rate_wins() {
+        let f = Fixture::new();
+        let levels = [
+            f.rate(false, false, false, 1000, at(1, 0)),
+            f.rate(true, false, false, 2000, at(1, 0)),
+            f.rate(false, true, false, 3000, at(1, 0)),
+            f.rate(false, false, true, 4000, at(1, 0)),
+            f.rate(false, true, true, 5000, at(1, 0)),
+        ];
+        let entry = f.entry(at(2, 9), 1);
+
+        for count in 1..=levels.len() {
+            let card = f.card(levels[..count].to_vec());
+            assert_eq!(card.resolve(&entry).unwrap().amount, (count as i64) * 1000);
+        }
+    }
+
+    #[test]
+    fn rates_apply_from_when_they_take_effect() {
+        let f = Fixture::new();
+        let card = f.card(
+            vec![
+                f.rate(false, false, false, 1000, at(1, 0)),
+                f.rate(false, false, false, 1500, at(10, 0)),
+                f.rate(false, true, false, 3000, at(20, 0))
+            ]
+        );
+
+        assert!(card.resolve(&f.entry(at(1, 0) - chrono::Duration::hours(1), 1)).is_none());
+        assert_eq!(card.resolve(&f.entry(at(5, 9), 1)).unwrap().amount, 1000);
+        assert_eq!(card.resolve(&f.entry(at(10, 0), 1)).unwrap().amount, 1500);
+        assert_eq!(card.resolve(&f.entry(at(20, 9), 1)).unwrap().amount, 3000);
+    }
+
+    #[test]
+    fn rates_of_other_projects_clients_and_members_do_not_apply() {
+        let f = Fixture::new();
+        let other = Fixture { organization_id: f.organization_id, ..Fixture::new() };
+        let card = f.card(
+            vec![
+                other.rate(true, false, false, 2000, at(1, 0)),
+                other.rate(false, true, false, 3000, at(1, 0)),
+                other.rate(false, false, true, 4000, at(1, 0))
+            ]
+        );
+
+        assert!(card.resolve(&f.entry(at(2, 9), 1)).is_none());
+    }
+
+    #[test]
+    fn amounts_follow_the_duration_and_billability() {
+        let f = Fixture::new();
+        let card = f.card(vec![f.rate(false, false, false, 9000, at(1, 0))]);
+
+        let mut entry = f.entry(at(2, 9), 2);
+        entry.ended_at = Some(at(2, 9) + chrono::Duration::minutes(100));
+        let billed = card.bill(entry.clone());
+        assert_eq!(billed.billable_amount, Some(15000));
+        assert_eq!(billed.currency.as_deref(), Some("EUR"));
+
+        entry.billable = false;
+        assert_eq!(card.bill(entry.clone()).billable_amount, Some(0));
+
+        entry.ended_at = None;
+        let running = card.bill(entry);
+        assert_eq!(running.billable_amount, None);
+        assert_eq!(running.hourly_rate, Some(9000));
+    }
+}
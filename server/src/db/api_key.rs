@@ -0,0 +1,119 @@
+use sqlx::{ query, query_as };
+use uuid::Uuid;
+
+use crate::auth::api_key::{ generate_api_key, parse_api_key };
+use crate::AppState;
+
+#[derive(serde::Serialize)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    pub organization_id: Option<Uuid>,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+pub struct CreateApiKey {
+    pub user_id: Uuid,
+    pub organization_id: Option<Uuid>,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
+/// The owner and permissions of an API key presented to the `auth` middleware.
+pub struct AuthenticatedApiKey {
+    pub user_id: Uuid,
+    pub organization_id: Option<Uuid>,
+    pub scopes: Vec<String>,
+}
+
+/// Stores a new API key and returns it together with the key itself, which is not stored.
+pub async fn create_api_key(
+    key: CreateApiKey,
+    ctx: &AppState
+) -> Result<(ApiKey, String), sqlx::Error> {
+    let (token, secret) = generate_api_key();
+
+    query_as!(
+        ApiKey,
+        r#"INSERT INTO api_keys (user_id, organization_id, name, selector, token_hash, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, name, organization_id, scopes, expires_at, last_used_at, created_at"#,
+        key.user_id,
+        key.organization_id,
+        key.name,
+        token.selector,
+        token.hash(),
+        &key.scopes,
+        key.expires_at
+    )
+        .fetch_one(&ctx.db).await
+        .map(|api_key| (api_key, secret))
+}
+
+pub async fn get_api_keys_by_user_id(
+    user_id: &Uuid,
+    ctx: &AppState
+) -> Result<Vec<ApiKey>, sqlx::Error> {
+    query_as!(
+        ApiKey,
+        r#"SELECT id, name, organization_id, scopes, expires_at, last_used_at, created_at
+        FROM api_keys WHERE user_id = $1 AND revoked_at IS NULL
+        ORDER BY created_at DESC"#,
+        user_id
+    ).fetch_all(&ctx.db).await
+}
+
+/// Revokes an API key of the user, returns whether a key was found.
+pub async fn revoke_api_key(
+    user_id: &Uuid,
+    api_key_id: &Uuid,
+    ctx: &AppState
+) -> Result<bool, sqlx::Error> {
+    query!(
+        r#"UPDATE api_keys SET revoked_at = NOW(), updated_at = NOW()
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL"#,
+        api_key_id,
+        user_id
+    )
+        .execute(&ctx.db).await
+        .map(|result| result.rows_affected() > 0)
+}
+
+/// Looks up an active API key and records that it was used.
+pub async fn authenticate_api_key(
+    key: &str,
+    ctx: &AppState
+) -> Result<Option<AuthenticatedApiKey>, sqlx::Error> {
+    let Some(token) = parse_api_key(key) else {
+        return Ok(None);
+    };
+
+    let api_key = query!(
+        r#"SELECT id, user_id, organization_id, scopes, token_hash FROM api_keys
+        WHERE selector = $1 AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > NOW())"#,
+        token.selector
+    ).fetch_optional(&ctx.db).await?;
+
+    let Some(api_key) = api_key.filter(|api_key| token.verify(&api_key.token_hash)) else {
+        return Ok(None);
+    };
+
+    // Scripts can make many requests per second, the time is only kept to the minute.
+    query!(
+        r#"UPDATE api_keys SET last_used_at = NOW()
+        WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < NOW() - INTERVAL '1 minute')"#,
+        api_key.id
+    ).execute(&ctx.db).await?;
+
+    Ok(
+        Some(AuthenticatedApiKey {
+            user_id: api_key.user_id,
+            organization_id: api_key.organization_id,
+            scopes: api_key.scopes,
+        })
+    )
+}
@@ -0,0 +1,21 @@
+use sqlx::query;
+use uuid::Uuid;
+
+use crate::AppState;
+
+/// Records a security relevant event, `subject` identifies what it is about, e.g. a throttle key.
+pub async fn insert_audit_event(
+    event: &str,
+    user_id: Option<&Uuid>,
+    subject: &str,
+    ctx: &AppState
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"INSERT INTO auth_audit_log (event, user_id, subject) VALUES ($1, $2, $3)"#,
+        event,
+        user_id,
+        subject
+    )
+        .execute(&ctx.db).await
+        .map(|_| ())
+}
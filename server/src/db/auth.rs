@@ -1,30 +1,285 @@
-use sqlx::query_scalar;
+use log::warn;
+use sqlx::{ query, query_as, query_scalar, PgExecutor };
 use uuid::Uuid;
 
+use crate::auth::client_info::ClientInfo;
+use crate::auth::opaque_token::OpaqueToken;
 use crate::AppState;
-use rand::Rng;
 
+pub struct RefreshToken {
+    pub token: String,
+    pub session_id: Uuid,
+    /// The organization whose identity provider the session was started through.
+    pub sso_organization_id: Option<Uuid>,
+}
+
+/// A login session, i.e. the currently active refresh token of a token family.
+#[derive(serde::Serialize)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+pub enum RotatedRefreshToken {
+    Rotated {
+        user_id: Uuid,
+        email: String,
+        refresh_token: RefreshToken,
+    },
+    /// An already rotated token was presented again, every session of the user was revoked.
+    Reused,
+    Invalid,
+}
+
+fn refresh_token_expiry() -> chrono::NaiveDateTime {
+    (chrono::Utc::now() + chrono::Duration::days(30)).naive_utc()
+}
+
+/// Creates a refresh token starting a new token family, i.e. a new login session.
 pub async fn create_refresh_token(
     user_id: Uuid,
+    client: &ClientInfo,
+    sso_organization_id: Option<&Uuid>,
+    ctx: &AppState
+) -> Result<RefreshToken, sqlx::Error> {
+    let token = OpaqueToken::generate();
+
+    query_scalar!(
+        r#"insert into refresh_tokens (selector, token_hash, user_id, expires_at, user_agent, ip_address, sso_organization_id)
+        values ($1, $2, $3, $4, $5, $6, $7) returning family_id"#,
+        token.selector,
+        token.hash(),
+        user_id,
+        refresh_token_expiry(),
+        client.user_agent,
+        client.ip_address,
+        sso_organization_id
+    )
+        .fetch_one(&ctx.db).await
+        .map(|session_id| RefreshToken {
+            token: token.to_string(),
+            session_id,
+            sso_organization_id: sso_organization_id.copied(),
+        })
+}
+
+/// Exchanges a refresh token for a new one in the same family and invalidates the old one.
+pub async fn rotate_refresh_token(
+    token: &str,
+    client: &ClientInfo,
     ctx: &AppState
-) -> Result<std::string::String, sqlx::Error> {
-    let token: String = rand
-        ::thread_rng()
-        .sample_iter(&rand::distributions::Alphanumeric)
-        .take(64)
-        .map(char::from)
-        .collect();
-    let expires_at = (chrono::Utc::now() + chrono::Duration::days(30)).naive_utc();
-
-    match
-        query_scalar!(
-            r#"insert into refresh_tokens (token, user_id, expires_at) values ($1, $2, $3) returning token"#,
-            token,
-            user_id,
-            expires_at
-        ).fetch_one(&ctx.db).await
-    {
-        Ok(token) => Ok(token),
-        Err(e) => Err(e),
+) -> Result<RotatedRefreshToken, sqlx::Error> {
+    let Some(token) = OpaqueToken::parse(token) else {
+        return Ok(RotatedRefreshToken::Invalid);
+    };
+
+    let mut tx = ctx.db.begin().await?;
+
+    let current = query!(
+        r#"SELECT refresh_tokens.id, refresh_tokens.token_hash, refresh_tokens.family_id,
+            refresh_tokens.revoked_at, refresh_tokens.replaced_by,
+            refresh_tokens.sso_organization_id, refresh_tokens.expires_at > NOW() AS "active!",
+            users.id AS user_id, users.email
+        FROM refresh_tokens
+        JOIN users ON refresh_tokens.user_id = users.id
+        WHERE selector = $1
+        FOR UPDATE OF refresh_tokens"#,
+        token.selector
+    ).fetch_optional(&mut *tx).await?;
+
+    let Some(current) = current.filter(|current| token.verify(&current.token_hash)) else {
+        return Ok(RotatedRefreshToken::Invalid);
+    };
+
+    if current.replaced_by.is_some() {
+        warn!("Refresh token reuse detected for user {}, revoking all sessions", current.user_id);
+        revoke_all_refresh_tokens(&current.user_id, &mut *tx).await?;
+        tx.commit().await?;
+        return Ok(RotatedRefreshToken::Reused);
+    }
+
+    if current.revoked_at.is_some() || !current.active {
+        return Ok(RotatedRefreshToken::Invalid);
     }
+
+    let new_token = OpaqueToken::generate();
+    let new_id = query_scalar!(
+        r#"insert into refresh_tokens (selector, token_hash, user_id, family_id, expires_at, user_agent, ip_address, sso_organization_id)
+        values ($1, $2, $3, $4, $5, $6, $7, $8) returning id"#,
+        new_token.selector,
+        new_token.hash(),
+        current.user_id,
+        current.family_id,
+        refresh_token_expiry(),
+        client.user_agent,
+        client.ip_address,
+        current.sso_organization_id
+    ).fetch_one(&mut *tx).await?;
+
+    query!(
+        r#"UPDATE refresh_tokens SET revoked_at = NOW(), replaced_by = $2, updated_at = NOW() WHERE id = $1"#,
+        current.id,
+        new_id
+    ).execute(&mut *tx).await?;
+
+    tx.commit().await?;
+
+    Ok(RotatedRefreshToken::Rotated {
+        user_id: current.user_id,
+        email: current.email,
+        refresh_token: RefreshToken {
+            token: new_token.to_string(),
+            session_id: current.family_id,
+            sso_organization_id: current.sso_organization_id,
+        },
+    })
+}
+
+/// Revokes every refresh token of the user, logging them out everywhere. Takes an executor so it
+/// can run as part of a larger transaction.
+pub async fn revoke_all_refresh_tokens<'e, E: PgExecutor<'e>>(
+    user_id: &Uuid,
+    executor: E
+) -> Result<u64, sqlx::Error> {
+    query!(
+        r#"UPDATE refresh_tokens SET revoked_at = NOW(), updated_at = NOW()
+        WHERE user_id = $1 AND revoked_at IS NULL"#,
+        user_id
+    )
+        .execute(executor).await
+        .map(|result| result.rows_affected())
+}
+
+/// Revokes the sessions of the organization's members that were not started through its identity
+/// provider, once it enforces single sign-on. The owner is exempt like at login.
+pub async fn revoke_sessions_without_sso(
+    organization_id: &Uuid,
+    ctx: &AppState
+) -> Result<u64, sqlx::Error> {
+    query!(
+        r#"UPDATE refresh_tokens SET revoked_at = NOW(), updated_at = NOW()
+        WHERE revoked_at IS NULL
+            AND sso_organization_id IS DISTINCT FROM $1
+            AND user_id IN (
+                SELECT user_organizations.user_id FROM user_organizations
+                JOIN organizations ON organizations.id = user_organizations.organization_id
+                WHERE user_organizations.organization_id = $1
+                    AND organizations.owner_user_id <> user_organizations.user_id
+            )"#,
+        organization_id
+    )
+        .execute(&ctx.db).await
+        .map(|result| result.rows_affected())
+}
+
+/// Revokes every token in the family of the given refresh token.
+pub async fn revoke_refresh_token_family(token: &str, ctx: &AppState) -> Result<(), sqlx::Error> {
+    let Some(token) = OpaqueToken::parse(token) else {
+        return Ok(());
+    };
+
+    let current = query!(
+        r#"SELECT token_hash, family_id FROM refresh_tokens WHERE selector = $1"#,
+        token.selector
+    ).fetch_optional(&ctx.db).await?;
+
+    let Some(current) = current.filter(|current| token.verify(&current.token_hash)) else {
+        return Ok(());
+    };
+
+    query!(
+        r#"UPDATE refresh_tokens SET revoked_at = NOW(), updated_at = NOW()
+        WHERE family_id = $1 AND revoked_at IS NULL"#,
+        current.family_id
+    )
+        .execute(&ctx.db).await
+        .map(|_| ())
+}
+
+pub async fn get_sessions_by_user_id(
+    user_id: &Uuid,
+    ctx: &AppState
+) -> Result<Vec<Session>, sqlx::Error> {
+    query_as!(
+        Session,
+        r#"SELECT family_id AS id, user_agent, ip_address,
+            (SELECT MIN(family.created_at) FROM refresh_tokens family
+                WHERE family.family_id = refresh_tokens.family_id) AS "created_at!",
+            last_used_at, expires_at
+        FROM refresh_tokens
+        WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+        ORDER BY last_used_at DESC"#,
+        user_id
+    ).fetch_all(&ctx.db).await
+}
+
+/// Revokes a single session of the user, returns whether a session was found.
+pub async fn revoke_session(
+    user_id: &Uuid,
+    session_id: &Uuid,
+    ctx: &AppState
+) -> Result<bool, sqlx::Error> {
+    query!(
+        r#"UPDATE refresh_tokens SET revoked_at = NOW(), updated_at = NOW()
+        WHERE user_id = $1 AND family_id = $2 AND revoked_at IS NULL"#,
+        user_id,
+        session_id
+    )
+        .execute(&ctx.db).await
+        .map(|result| result.rows_affected() > 0)
+}
+
+/// Revokes every session of the user except the given one.
+pub async fn revoke_other_sessions<'e, E: PgExecutor<'e>>(
+    user_id: &Uuid,
+    current_session_id: &Uuid,
+    executor: E
+) -> Result<u64, sqlx::Error> {
+    query!(
+        r#"UPDATE refresh_tokens SET revoked_at = NOW(), updated_at = NOW()
+        WHERE user_id = $1 AND family_id <> $2 AND revoked_at IS NULL"#,
+        user_id,
+        current_session_id
+    )
+        .execute(executor).await
+        .map(|result| result.rows_affected())
+}
+
+pub async fn insert_revoked_access_token<'e, E: PgExecutor<'e>>(
+    jti: &Uuid,
+    user_id: &Uuid,
+    expires_at: chrono::NaiveDateTime,
+    executor: E
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"INSERT INTO revoked_access_tokens (jti, user_id, expires_at) VALUES ($1, $2, $3)
+        ON CONFLICT (jti) DO NOTHING"#,
+        jti,
+        user_id,
+        expires_at
+    )
+        .execute(executor).await
+        .map(|_| ())
+}
+
+/// Deletes the revocations of expired access tokens, those are rejected as expired anyway.
+pub async fn delete_expired_revoked_access_tokens(ctx: &AppState) -> Result<u64, sqlx::Error> {
+    query!(r#"DELETE FROM revoked_access_tokens WHERE expires_at < NOW()"#)
+        .execute(&ctx.db).await
+        .map(|result| result.rows_affected())
+}
+
+/// Returns the expiry of the revoked access token, if it has been revoked.
+pub async fn is_access_token_jti_revoked(
+    jti: &Uuid,
+    ctx: &AppState
+) -> Result<Option<chrono::NaiveDateTime>, sqlx::Error> {
+    query_scalar!(
+        r#"SELECT expires_at FROM revoked_access_tokens WHERE jti = $1 AND expires_at > NOW()"#,
+        jti
+    ).fetch_optional(&ctx.db).await
 }
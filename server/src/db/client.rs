@@ -0,0 +1,77 @@
+use sqlx::{ query, query_as, PgExecutor };
+use uuid::Uuid;
+
+use crate::models::client::Client;
+
+pub async fn get_clients<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    executor: E
+) -> Result<Vec<Client>, sqlx::Error> {
+    query_as!(
+        Client,
+        r#"SELECT id, organization_id, name, created_at
+        FROM clients WHERE organization_id = $1 ORDER BY name"#,
+        organization_id
+    ).fetch_all(executor).await
+}
+
+pub async fn get_client<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    client_id: &Uuid,
+    executor: E
+) -> Result<Option<Client>, sqlx::Error> {
+    query_as!(
+        Client,
+        r#"SELECT id, organization_id, name, created_at
+        FROM clients WHERE id = $1 AND organization_id = $2"#,
+        client_id,
+        organization_id
+    ).fetch_optional(executor).await
+}
+
+pub async fn create_client<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    name: &str,
+    executor: E
+) -> Result<Client, sqlx::Error> {
+    query_as!(
+        Client,
+        r#"INSERT INTO clients (organization_id, name) VALUES ($1, $2)
+        RETURNING id, organization_id, name, created_at"#,
+        organization_id,
+        name
+    ).fetch_one(executor).await
+}
+
+pub async fn rename_client<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    client_id: &Uuid,
+    name: &str,
+    executor: E
+) -> Result<Option<Client>, sqlx::Error> {
+    query_as!(
+        Client,
+        r#"UPDATE clients SET name = $3, updated_at = NOW()
+        WHERE id = $1 AND organization_id = $2
+        RETURNING id, organization_id, name, created_at"#,
+        client_id,
+        organization_id,
+        name
+    ).fetch_optional(executor).await
+}
+
+/// Deletes the client and its rates, which fails with a foreign key violation while it has
+/// projects.
+pub async fn delete_client<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    client_id: &Uuid,
+    executor: E
+) -> Result<bool, sqlx::Error> {
+    query!(
+        r#"DELETE FROM clients WHERE id = $1 AND organization_id = $2"#,
+        client_id,
+        organization_id
+    )
+        .execute(executor).await
+        .map(|result| result.rows_affected() > 0)
+}
@@ -0,0 +1,67 @@
+use sqlx::query;
+use uuid::Uuid;
+
+use crate::{ auth::opaque_token::OpaqueToken, AppState };
+
+/// Creates a token proving ownership of `email`, valid for two days.
+pub async fn create_email_verification_token(
+    user_id: &Uuid,
+    email: &str,
+    ctx: &AppState
+) -> Result<String, sqlx::Error> {
+    let token = OpaqueToken::generate();
+    let expires_at = (chrono::Utc::now() + chrono::Duration::days(2)).naive_utc();
+
+    query!(
+        r#"INSERT INTO email_verification_tokens (user_id, email, selector, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4, $5)"#,
+        user_id,
+        email,
+        token.selector,
+        token.hash(),
+        expires_at
+    )
+        .execute(&ctx.db).await
+        .map(|_| token.to_string())
+}
+
+/// Marks the email the token was issued for as verified and makes it the user's email.
+/// Returns the user id, or `None` if the token is invalid, used or expired.
+pub async fn verify_email_with_token(
+    token: &str,
+    ctx: &AppState
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let Some(token) = OpaqueToken::parse(token) else {
+        return Ok(None);
+    };
+
+    let mut tx = ctx.db.begin().await?;
+
+    let verification = query!(
+        r#"SELECT id, user_id, email, token_hash FROM email_verification_tokens
+        WHERE selector = $1 AND used_at IS NULL AND expires_at > NOW()
+        FOR UPDATE"#,
+        token.selector
+    ).fetch_optional(&mut *tx).await?;
+
+    let Some(verification) = verification.filter(|verification|
+        token.verify(&verification.token_hash)
+    ) else {
+        return Ok(None);
+    };
+
+    query!(
+        r#"UPDATE email_verification_tokens SET used_at = NOW() WHERE id = $1"#,
+        verification.id
+    ).execute(&mut *tx).await?;
+
+    query!(
+        r#"UPDATE users SET email = $2, email_verified_at = NOW(), updated_at = NOW() WHERE id = $1"#,
+        verification.user_id,
+        verification.email
+    ).execute(&mut *tx).await?;
+
+    tx.commit().await?;
+
+    Ok(Some(verification.user_id))
+}
@@ -0,0 +1,67 @@
+use sqlx::{ query, query_as, PgExecutor };
+use uuid::Uuid;
+
+use crate::models::hourly_rate::HourlyRate;
+
+/// What a new rate applies to and from when.
+pub struct HourlyRateFields {
+    pub client_id: Option<Uuid>,
+    pub project_id: Option<Uuid>,
+    pub user_id: Option<Uuid>,
+    pub amount: i64,
+    pub currency: String,
+    pub effective_from: chrono::NaiveDateTime,
+}
+
+/// Every rate of the organization, the most recent first.
+pub async fn get_hourly_rates<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    executor: E
+) -> Result<Vec<HourlyRate>, sqlx::Error> {
+    query_as!(
+        HourlyRate,
+        r#"SELECT id, organization_id, client_id, project_id, user_id, amount, currency,
+            effective_from, created_at
+        FROM hourly_rates WHERE organization_id = $1
+        ORDER BY effective_from DESC"#,
+        organization_id
+    ).fetch_all(executor).await
+}
+
+/// Fails with a foreign key violation if the client or project is not one of the organization,
+/// and with a unique violation if the same target already has a rate starting at that time.
+pub async fn create_hourly_rate<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    rate: &HourlyRateFields,
+    executor: E
+) -> Result<HourlyRate, sqlx::Error> {
+    query_as!(
+        HourlyRate,
+        r#"INSERT INTO hourly_rates
+            (organization_id, client_id, project_id, user_id, amount, currency, effective_from)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, organization_id, client_id, project_id, user_id, amount, currency,
+            effective_from, created_at"#,
+        organization_id,
+        rate.client_id,
+        rate.project_id,
+        rate.user_id,
+        rate.amount,
+        rate.currency,
+        rate.effective_from
+    ).fetch_one(executor).await
+}
+
+pub async fn delete_hourly_rate<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    rate_id: &Uuid,
+    executor: E
+) -> Result<bool, sqlx::Error> {
+    query!(
+        r#"DELETE FROM hourly_rates WHERE id = $1 AND organization_id = $2"#,
+        rate_id,
+        organization_id
+    )
+        .execute(executor).await
+        .map(|result| result.rows_affected() > 0)
+}
@@ -0,0 +1,179 @@
+use sqlx::{ query, query_as, PgConnection, PgExecutor };
+use uuid::Uuid;
+
+use crate::{
+    auth::opaque_token::OpaqueToken,
+    db::organization::attach_user_to_organization,
+    AppState,
+};
+
+#[derive(serde::Serialize)]
+pub struct Invitation {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub email: String,
+    pub role: String,
+    pub invited_by_user_id: Option<Uuid>,
+    pub expires_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+/// A pending invitation matching a presented token.
+pub struct PendingInvitation {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub email: String,
+    pub role: String,
+}
+
+/// Invites `email` to the organization and returns the invitation together with its token, which
+/// is only sent to the invitee. Inviting an address again replaces its pending invitation, so this
+/// has to run in a transaction.
+pub async fn create_invitation(
+    organization_id: &Uuid,
+    email: &str,
+    role: &str,
+    invited_by_user_id: &Uuid,
+    tx: &mut PgConnection
+) -> Result<(Invitation, String), sqlx::Error> {
+    let token = OpaqueToken::generate();
+    let expires_at = (chrono::Utc::now() + chrono::Duration::days(7)).naive_utc();
+
+    query!(
+        r#"UPDATE organization_invitations SET revoked_at = NOW()
+        WHERE organization_id = $1 AND LOWER(email) = LOWER($2)
+            AND accepted_at IS NULL AND declined_at IS NULL AND revoked_at IS NULL"#,
+        organization_id,
+        email
+    ).execute(&mut *tx).await?;
+
+    let invitation = query_as!(
+        Invitation,
+        r#"INSERT INTO organization_invitations
+            (organization_id, email, role, invited_by_user_id, selector, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, organization_id, email, role, invited_by_user_id, expires_at, created_at AS "created_at!""#,
+        organization_id,
+        email,
+        role,
+        invited_by_user_id,
+        token.selector,
+        token.hash(),
+        expires_at
+    ).fetch_one(&mut *tx).await?;
+
+    Ok((invitation, token.to_string()))
+}
+
+/// Invitations of the organization that were neither answered, revoked nor have expired.
+pub async fn get_pending_invitations<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    executor: E
+) -> Result<Vec<Invitation>, sqlx::Error> {
+    query_as!(
+        Invitation,
+        r#"SELECT id, organization_id, email, role, invited_by_user_id, expires_at, created_at AS "created_at!"
+        FROM organization_invitations
+        WHERE organization_id = $1 AND accepted_at IS NULL AND declined_at IS NULL
+            AND revoked_at IS NULL AND expires_at > NOW()
+        ORDER BY created_at"#,
+        organization_id
+    ).fetch_all(executor).await
+}
+
+/// Revokes a pending invitation. Returns `false` if there is no such pending invitation.
+pub async fn revoke_invitation<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    invitation_id: &Uuid,
+    executor: E
+) -> Result<bool, sqlx::Error> {
+    query!(
+        r#"UPDATE organization_invitations SET revoked_at = NOW()
+        WHERE id = $1 AND organization_id = $2
+            AND accepted_at IS NULL AND declined_at IS NULL AND revoked_at IS NULL"#,
+        invitation_id,
+        organization_id
+    )
+        .execute(executor).await
+        .map(|result| result.rows_affected() > 0)
+}
+
+/// Looks up the pending, unexpired invitation the token was issued for.
+pub async fn get_pending_invitation_by_token(
+    token: &str,
+    ctx: &AppState
+) -> Result<Option<PendingInvitation>, sqlx::Error> {
+    let Some(token) = OpaqueToken::parse(token) else {
+        return Ok(None);
+    };
+
+    let invitation = query!(
+        r#"SELECT id, organization_id, email, role, token_hash FROM organization_invitations
+        WHERE selector = $1 AND accepted_at IS NULL AND declined_at IS NULL
+            AND revoked_at IS NULL AND expires_at > NOW()"#,
+        token.selector
+    ).fetch_optional(&ctx.db).await?;
+
+    Ok(
+        invitation
+            .filter(|invitation| token.verify(&invitation.token_hash))
+            .map(|invitation| PendingInvitation {
+                id: invitation.id,
+                organization_id: invitation.organization_id,
+                email: invitation.email,
+                role: invitation.role,
+            })
+    )
+}
+
+/// Accepts the invitation and adds the user to the organization with the invited role. The token
+/// was delivered to the invited address, so it also verifies the user's email if it matches.
+/// Returns `false` if the invitation is no longer pending.
+pub async fn accept_invitation(
+    invitation_id: &Uuid,
+    user_id: &Uuid,
+    ctx: &AppState
+) -> Result<bool, sqlx::Error> {
+    let mut tx = ctx.db.begin().await?;
+
+    let invitation = query!(
+        r#"UPDATE organization_invitations SET accepted_at = NOW()
+        WHERE id = $1 AND accepted_at IS NULL AND declined_at IS NULL
+            AND revoked_at IS NULL AND expires_at > NOW()
+        RETURNING organization_id, email, role"#,
+        invitation_id
+    ).fetch_optional(&mut *tx).await?;
+
+    let Some(invitation) = invitation else {
+        return Ok(false);
+    };
+
+    attach_user_to_organization(
+        user_id,
+        &invitation.organization_id,
+        &invitation.role,
+        &mut *tx
+    ).await?;
+
+    query!(
+        r#"UPDATE users SET email_verified_at = NOW(), updated_at = NOW()
+        WHERE id = $1 AND email_verified_at IS NULL AND LOWER(email) = LOWER($2)"#,
+        user_id,
+        invitation.email
+    ).execute(&mut *tx).await?;
+
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+/// Declines the invitation. Returns `false` if the invitation is no longer pending.
+pub async fn decline_invitation(invitation_id: &Uuid, ctx: &AppState) -> Result<bool, sqlx::Error> {
+    query!(
+        r#"UPDATE organization_invitations SET declined_at = NOW()
+        WHERE id = $1 AND accepted_at IS NULL AND declined_at IS NULL AND revoked_at IS NULL"#,
+        invitation_id
+    )
+        .execute(&ctx.db).await
+        .map(|result| result.rows_affected() > 0)
+}
@@ -0,0 +1,123 @@
+use sqlx::{ query, query_as };
+use uuid::Uuid;
+
+use crate::{ auth::opaque_token::{ keyed_hash, verify_keyed_hash }, AppState };
+
+pub struct TotpState {
+    pub secret: Option<String>,
+    pub enabled_at: Option<chrono::NaiveDateTime>,
+}
+
+pub async fn get_totp_state(user_id: &Uuid, ctx: &AppState) -> Result<TotpState, sqlx::Error> {
+    query_as!(
+        TotpState,
+        r#"SELECT totp_secret AS secret, totp_enabled_at AS enabled_at FROM users WHERE id = $1"#,
+        user_id
+    ).fetch_one(&ctx.db).await
+}
+
+/// Stores a secret that is not used for login until it has been confirmed with a first code.
+pub async fn set_pending_totp_secret(
+    user_id: &Uuid,
+    secret: &str,
+    ctx: &AppState
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"UPDATE users SET totp_secret = $2, totp_enabled_at = NULL, totp_last_used_step = NULL,
+            updated_at = NOW()
+        WHERE id = $1"#,
+        user_id,
+        secret
+    )
+        .execute(&ctx.db).await
+        .map(|_| ())
+}
+
+/// Enables TOTP and replaces the recovery codes of the user.
+pub async fn enable_totp(
+    user_id: &Uuid,
+    step: i64,
+    recovery_codes: &[String],
+    ctx: &AppState
+) -> Result<(), sqlx::Error> {
+    let mut tx = ctx.db.begin().await?;
+
+    query!(
+        r#"UPDATE users SET totp_enabled_at = NOW(), totp_last_used_step = $2, updated_at = NOW()
+        WHERE id = $1"#,
+        user_id,
+        step
+    ).execute(&mut *tx).await?;
+
+    query!(r#"DELETE FROM mfa_recovery_codes WHERE user_id = $1"#, user_id).execute(
+        &mut *tx
+    ).await?;
+
+    for code in recovery_codes {
+        query!(
+            r#"INSERT INTO mfa_recovery_codes (user_id, code_hash) VALUES ($1, $2)"#,
+            user_id,
+            keyed_hash(code)
+        ).execute(&mut *tx).await?;
+    }
+
+    tx.commit().await
+}
+
+pub async fn disable_totp(user_id: &Uuid, ctx: &AppState) -> Result<(), sqlx::Error> {
+    let mut tx = ctx.db.begin().await?;
+
+    query!(
+        r#"UPDATE users SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_used_step = NULL,
+            updated_at = NOW()
+        WHERE id = $1"#,
+        user_id
+    ).execute(&mut *tx).await?;
+
+    query!(r#"DELETE FROM mfa_recovery_codes WHERE user_id = $1"#, user_id).execute(
+        &mut *tx
+    ).await?;
+
+    tx.commit().await
+}
+
+/// Records the time step of an accepted TOTP code. Returns false if the step, or a later one,
+/// has already been used so a code cannot be replayed.
+pub async fn record_totp_step(
+    user_id: &Uuid,
+    step: i64,
+    ctx: &AppState
+) -> Result<bool, sqlx::Error> {
+    query!(
+        r#"UPDATE users SET totp_last_used_step = $2
+        WHERE id = $1 AND (totp_last_used_step IS NULL OR totp_last_used_step < $2)"#,
+        user_id,
+        step
+    )
+        .execute(&ctx.db).await
+        .map(|result| result.rows_affected() > 0)
+}
+
+/// Marks a matching unused recovery code as used, returns whether one was found.
+pub async fn use_recovery_code(
+    user_id: &Uuid,
+    code: &str,
+    ctx: &AppState
+) -> Result<bool, sqlx::Error> {
+    let codes = query!(
+        r#"SELECT id, code_hash FROM mfa_recovery_codes WHERE user_id = $1 AND used_at IS NULL"#,
+        user_id
+    ).fetch_all(&ctx.db).await?;
+
+    let matching = codes.into_iter().find(|row| verify_keyed_hash(code, &row.code_hash));
+    let Some(matching) = matching else {
+        return Ok(false);
+    };
+
+    query!(
+        r#"UPDATE mfa_recovery_codes SET used_at = NOW() WHERE id = $1 AND used_at IS NULL"#,
+        matching.id
+    )
+        .execute(&ctx.db).await
+        .map(|result| result.rows_affected() > 0)
+}
@@ -1,3 +1,19 @@
 pub mod user;
 pub mod auth;
 pub mod organization;
+pub mod password_reset;
+pub mod email_verification;
+pub mod mfa;
+pub mod audit;
+pub mod oidc;
+pub mod sso;
+pub mod api_key;
+pub mod passkey;
+pub mod invitation;
+pub mod role;
+pub mod tenant;
+pub mod team;
+pub mod project;
+pub mod time_entry;
+pub mod client;
+pub mod hourly_rate;
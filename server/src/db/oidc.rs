@@ -0,0 +1,106 @@
+use sqlx::{ query, query_as, query_scalar };
+use uuid::Uuid;
+
+use crate::auth::opaque_token::keyed_hash;
+use crate::AppState;
+
+pub struct OidcLoginState {
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+/// Remembers a started login until the provider redirects back, for at most ten minutes.
+pub async fn create_oidc_login_state(
+    provider: &str,
+    state: &str,
+    nonce: &str,
+    code_verifier: &str,
+    ctx: &AppState
+) -> Result<(), sqlx::Error> {
+    query!(r#"DELETE FROM oidc_login_states WHERE expires_at < NOW()"#).execute(&ctx.db).await?;
+
+    query!(
+        r#"INSERT INTO oidc_login_states (state_hash, provider, nonce, code_verifier, expires_at)
+        VALUES ($1, $2, $3, $4, $5)"#,
+        keyed_hash(state),
+        provider,
+        nonce,
+        code_verifier,
+        (chrono::Utc::now() + chrono::Duration::minutes(10)).naive_utc()
+    )
+        .execute(&ctx.db).await
+        .map(|_| ())
+}
+
+/// Consumes the pending login of a state, so every state can only be used once.
+pub async fn take_oidc_login_state(
+    provider: &str,
+    state: &str,
+    ctx: &AppState
+) -> Result<Option<OidcLoginState>, sqlx::Error> {
+    query_as!(
+        OidcLoginState,
+        r#"DELETE FROM oidc_login_states
+        WHERE state_hash = $1 AND provider = $2 AND expires_at > NOW()
+        RETURNING nonce, code_verifier"#,
+        keyed_hash(state),
+        provider
+    ).fetch_optional(&ctx.db).await
+}
+
+pub async fn get_user_id_by_identity(
+    provider: &str,
+    subject: &str,
+    ctx: &AppState
+) -> Result<Option<Uuid>, sqlx::Error> {
+    query_scalar!(
+        r#"SELECT user_id FROM user_identities WHERE provider = $1 AND subject = $2"#,
+        provider,
+        subject
+    ).fetch_optional(&ctx.db).await
+}
+
+pub async fn link_identity(
+    user_id: &Uuid,
+    provider: &str,
+    subject: &str,
+    email: &str,
+    ctx: &AppState
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"INSERT INTO user_identities (user_id, provider, subject, email) VALUES ($1, $2, $3, $4)"#,
+        user_id,
+        provider,
+        subject,
+        email
+    )
+        .execute(&ctx.db).await
+        .map(|_| ())
+}
+
+/// Creates an account without a password for a provider identity. The provider has verified the
+/// email, so the account is verified as well.
+pub async fn create_user_with_identity(
+    email: &str,
+    provider: &str,
+    subject: &str,
+    ctx: &AppState
+) -> Result<Uuid, sqlx::Error> {
+    let mut tx = ctx.db.begin().await?;
+
+    let user_id = query_scalar!(
+        r#"INSERT INTO users (email, email_verified_at) VALUES ($1, NOW()) RETURNING id"#,
+        email
+    ).fetch_one(&mut *tx).await?;
+
+    query!(
+        r#"INSERT INTO user_identities (user_id, provider, subject, email) VALUES ($1, $2, $3, $4)"#,
+        user_id,
+        provider,
+        subject,
+        email
+    ).execute(&mut *tx).await?;
+
+    tx.commit().await?;
+    Ok(user_id)
+}
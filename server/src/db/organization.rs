@@ -1,13 +1,82 @@
 use log::debug;
-use sqlx::{ query_as, query_scalar };
+use sqlx::{ query, query_as, query_scalar, Connection, PgConnection, PgExecutor };
 use uuid::Uuid;
 
-use crate::{ db::user, state::AppState };
+use crate::{ auth::organization_access::Role, state::AppState };
+
+/// Slugs can be chosen up to this length, generated ones are shorter to leave room for a suffix.
+const MAX_SLUG_LENGTH: usize = 48;
+const MAX_GENERATED_SLUG_LENGTH: usize = 40;
+
+/// Slugs that would clash with static routes next to `/organizations/:organization_id`.
+const RESERVED_SLUGS: [&str; 1] = ["invitations"];
+
+pub const SLUG_CONSTRAINT: &str = "unique_organization_slug";
+/// Names only have to be unique among the organizations of the same owner.
+pub const OWNER_NAME_CONSTRAINT: &str = "unique_owner_organization_name";
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Organization {
     pub id: Uuid,
     pub name: String,
+    pub slug: String,
+}
+
+/// Lowercase ASCII letters and digits in groups separated by single hyphens. Slugs that parse as
+/// a UUID are refused, routes accept both and try the UUID first.
+pub fn is_valid_slug(slug: &str) -> bool {
+    slug.len() <= MAX_SLUG_LENGTH &&
+        slug
+            .split('-')
+            .all(|part| {
+                !part.is_empty() &&
+                    part.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+            }) &&
+        Uuid::parse_str(slug).is_err() &&
+        !RESERVED_SLUGS.contains(&slug)
+}
+
+/// Turns an organization name into a slug, e.g. "ACME Engineering" into "acme-engineering".
+/// Characters outside of ASCII are left out rather than splitting words, the same rules are used
+/// for the existing organizations by the `organization_slugs` migration.
+pub fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.chars().filter(char::is_ascii) {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug: String = slug.chars().take(MAX_GENERATED_SLUG_LENGTH).collect();
+    let slug = slug.trim_end_matches('-');
+
+    if is_valid_slug(slug) { slug.to_string() } else { "organization".to_string() }
+}
+
+/// Whether the error is a unique violation of the given constraint.
+pub fn is_unique_violation(e: &sqlx::Error, constraint: &str) -> bool {
+    e.as_database_error().is_some_and(|db_error| {
+        db_error.code().as_deref() == Some("23505") && db_error.constraint() == Some(constraint)
+    })
+}
+
+/// The slug itself if it is free, otherwise the slug with the lowest free numeric suffix.
+async fn available_slug<'e, E: PgExecutor<'e>>(
+    slug: &str,
+    executor: E
+) -> Result<String, sqlx::Error> {
+    let taken = query_scalar!(
+        r#"SELECT slug FROM organizations WHERE slug = $1 OR slug LIKE $1 || '-%'"#,
+        slug
+    ).fetch_all(executor).await?;
+
+    Ok(
+        std::iter::once(slug.to_string())
+            .chain((2..).map(|n| format!("{}-{}", slug, n)))
+            .find(|candidate| !taken.contains(candidate))
+            .expect("there is always a free suffix")
+    )
 }
 
 pub async fn get_orgs_by_user_id(
@@ -17,13 +86,13 @@ pub async fn get_orgs_by_user_id(
     match
         query_as!(
             Organization,
-            r#"SELECT organizations.id, organizations.name FROM organizations JOIN user_organizations ON organizations.id = user_organizations.organization_id WHERE user_organizations.user_id = $1"#,
+            r#"SELECT organizations.id, organizations.name, organizations.slug FROM organizations JOIN user_organizations ON organizations.id = user_organizations.organization_id WHERE user_organizations.user_id = $1"#,
             user_id
         ).fetch_all(&ctx.db).await
     {
         Ok(organizations) => Ok(organizations),
         Err(e) => {
-            return Err(e);
+            Err(e)
         }
     }
 }
@@ -33,45 +102,348 @@ pub struct CreateOrganization {
     pub user_id: Uuid,
 }
 
+/// Creates the organization with a slug generated from its name and makes the user its owner, in
+/// one transaction so there is never an organization without owner.
 pub async fn create_organization(
     org: CreateOrganization,
     ctx: &AppState
-) -> Result<Uuid, sqlx::Error> {
-    let organization_id: Uuid = match
-        query_scalar!(
-            r#"INSERT INTO organizations (name, owner_user_id) VALUES ($1, $2) RETURNING id"#,
-            org.name,
-            org.user_id
-        ).fetch_one(&ctx.db).await
-    {
-        Ok(id) => id,
-        Err(e) => {
-            debug!("Failed to create organization: {:?}", e);
-            return Err(e);
+) -> Result<Organization, sqlx::Error> {
+    let base_slug = slugify(&org.name);
+    let mut attempts = 0;
+    let mut tx = ctx.db.begin().await?;
+
+    let organization = loop {
+        let slug = available_slug(&base_slug, &mut *tx).await?;
+        // A failed insert aborts the transaction, the savepoint keeps it usable for a retry.
+        let mut savepoint = tx.begin().await?;
+        match
+            query_as!(
+                Organization,
+                r#"INSERT INTO organizations (name, owner_user_id, slug) VALUES ($1, $2, $3) RETURNING id, name, slug"#,
+                org.name,
+                org.user_id,
+                slug
+            ).fetch_one(&mut *savepoint).await
+        {
+            Ok(organization) => {
+                savepoint.commit().await?;
+                break organization;
+            }
+            // Another organization took the slug in the meantime.
+            Err(e) if is_unique_violation(&e, SLUG_CONSTRAINT) && attempts < 3 => {
+                savepoint.rollback().await?;
+                attempts += 1;
+            }
+            Err(e) => {
+                debug!("Failed to create organization: {:?}", e);
+                return Err(e);
+            }
         }
     };
 
-    attach_user_to_organization(&org.user_id, &organization_id, ctx).await?;
+    let role = Role::Owner.as_str();
+    attach_user_to_organization(&org.user_id, &organization.id, role, &mut *tx).await?;
+    tx.commit().await?;
 
-    Ok(organization_id)
+    Ok(organization)
 }
 
-pub async fn attach_user_to_organization(
+/// Adds the user to the organization with the given role. Takes an executor so it can run as part
+/// of a larger transaction.
+pub async fn attach_user_to_organization<'e, E: PgExecutor<'e>>(
     user_id: &Uuid,
     organization_id: &Uuid,
-    ctx: &AppState
+    role: &str,
+    executor: E
 ) -> Result<(), sqlx::Error> {
     match
         query_scalar!(
-            r#"INSERT INTO user_organizations (user_id, organization_id) VALUES ($1, $2) RETURNING (user_id, organization_id)"#,
+            r#"INSERT INTO user_organizations (user_id, organization_id, role) VALUES ($1, $2, $3) RETURNING (user_id, organization_id)"#,
             user_id,
-            organization_id
-        ).fetch_one(&ctx.db).await
+            organization_id,
+            role
+        ).fetch_one(executor).await
     {
         Ok(_) => Ok(()),
         Err(e) => {
             debug!("Failed to create organization xxxx: {:?}", e);
-            return Err(e);
+            Err(e)
         }
     }
 }
+
+pub async fn get_organization_by_id<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    executor: E
+) -> Result<Option<Organization>, sqlx::Error> {
+    query_as!(
+        Organization,
+        r#"SELECT id, name, slug FROM organizations WHERE id = $1"#,
+        organization_id
+    ).fetch_optional(executor).await
+}
+
+pub async fn get_organization_id_by_slug(
+    slug: &str,
+    ctx: &AppState
+) -> Result<Option<Uuid>, sqlx::Error> {
+    query_scalar!(r#"SELECT id FROM organizations WHERE slug = $1"#, slug)
+        .fetch_optional(&ctx.db).await
+}
+
+pub async fn is_user_in_organization(
+    user_id: &Uuid,
+    organization_id: &Uuid,
+    ctx: &AppState
+) -> Result<bool, sqlx::Error> {
+    query_scalar!(
+        r#"SELECT EXISTS (
+            SELECT 1 FROM user_organizations WHERE user_id = $1 AND organization_id = $2
+        ) AS "exists!""#,
+        user_id,
+        organization_id
+    ).fetch_one(&ctx.db).await
+}
+
+pub async fn is_email_in_organization<'e, E: PgExecutor<'e>>(
+    email: &str,
+    organization_id: &Uuid,
+    executor: E
+) -> Result<bool, sqlx::Error> {
+    query_scalar!(
+        r#"SELECT EXISTS (
+            SELECT 1 FROM user_organizations
+            JOIN users ON users.id = user_organizations.user_id
+            WHERE LOWER(users.email) = LOWER($1) AND user_organizations.organization_id = $2
+        ) AS "exists!""#,
+        email,
+        organization_id
+    ).fetch_one(executor).await
+}
+
+#[derive(serde::Serialize)]
+pub struct OrganizationDetails {
+    pub id: Uuid,
+    pub name: String,
+    pub slug: String,
+    pub owner_user_id: Uuid,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+pub async fn get_organization_details<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    executor: E
+) -> Result<Option<OrganizationDetails>, sqlx::Error> {
+    query_as!(
+        OrganizationDetails,
+        r#"SELECT id, name, slug, owner_user_id, created_at AS "created_at!" FROM organizations WHERE id = $1"#,
+        organization_id
+    ).fetch_optional(executor).await
+}
+
+/// Renames the organization and/or changes its slug, `None` leaves a field unchanged.
+pub async fn update_organization<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    name: Option<&str>,
+    slug: Option<&str>,
+    executor: E
+) -> Result<Option<OrganizationDetails>, sqlx::Error> {
+    query_as!(
+        OrganizationDetails,
+        r#"UPDATE organizations SET name = COALESCE($2, name), slug = COALESCE($3, slug), updated_at = NOW()
+        WHERE id = $1
+        RETURNING id, name, slug, owner_user_id, created_at AS "created_at!""#,
+        organization_id,
+        name,
+        slug
+    ).fetch_optional(executor).await
+}
+
+/// Deletes the organization together with its memberships, invitations, roles, API keys and
+/// single sign-on settings.
+pub async fn delete_organization<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    executor: E
+) -> Result<bool, sqlx::Error> {
+    query!(r#"DELETE FROM organizations WHERE id = $1"#, organization_id)
+        .execute(executor).await
+        .map(|result| result.rows_affected() > 0)
+}
+
+#[derive(serde::Serialize)]
+pub struct Member {
+    pub user_id: Uuid,
+    pub email: String,
+    pub role: Option<String>,
+    pub custom_role_id: Option<Uuid>,
+    pub custom_role_name: Option<String>,
+    pub joined_at: chrono::NaiveDateTime,
+}
+
+pub async fn get_members<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    executor: E
+) -> Result<Vec<Member>, sqlx::Error> {
+    query_as!(
+        Member,
+        r#"SELECT users.id AS user_id, users.email, user_organizations.role,
+            user_organizations.custom_role_id, organization_roles.name AS "custom_role_name?",
+            user_organizations.created_at AS "joined_at!"
+        FROM user_organizations
+        JOIN users ON users.id = user_organizations.user_id
+        LEFT JOIN organization_roles ON organization_roles.id = user_organizations.custom_role_id
+        WHERE user_organizations.organization_id = $1
+        ORDER BY user_organizations.created_at"#,
+        organization_id
+    ).fetch_all(executor).await
+}
+
+/// Removes the user from the organization and revokes the user's API keys of the organization,
+/// which would otherwise keep working for someone who is no longer a member. Has to run in a
+/// transaction.
+pub async fn remove_member(
+    user_id: &Uuid,
+    organization_id: &Uuid,
+    tx: &mut PgConnection
+) -> Result<bool, sqlx::Error> {
+    let removed = query!(
+        r#"DELETE FROM user_organizations WHERE user_id = $1 AND organization_id = $2"#,
+        user_id,
+        organization_id
+    ).execute(&mut *tx).await?;
+
+    if removed.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    query!(
+        r#"UPDATE api_keys SET revoked_at = NOW(), updated_at = NOW()
+        WHERE user_id = $1 AND organization_id = $2 AND revoked_at IS NULL"#,
+        user_id,
+        organization_id
+    ).execute(&mut *tx).await?;
+
+    Ok(true)
+}
+
+/// Makes another member the owner. The previous owner stays in the organization as an admin.
+/// Returns `false` if the new owner is not a member. Has to run in a transaction.
+pub async fn transfer_ownership(
+    organization_id: &Uuid,
+    current_owner_id: &Uuid,
+    new_owner_id: &Uuid,
+    tx: &mut PgConnection
+) -> Result<bool, sqlx::Error> {
+    let promoted = query!(
+        r#"UPDATE user_organizations SET role = $3, custom_role_id = NULL, updated_at = NOW()
+        WHERE user_id = $1 AND organization_id = $2"#,
+        new_owner_id,
+        organization_id,
+        Role::Owner.as_str()
+    ).execute(&mut *tx).await?;
+
+    if promoted.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    query!(
+        r#"UPDATE user_organizations SET role = $3, updated_at = NOW()
+        WHERE user_id = $1 AND organization_id = $2"#,
+        current_owner_id,
+        organization_id,
+        Role::Admin.as_str()
+    ).execute(&mut *tx).await?;
+
+    query!(
+        r#"UPDATE organizations SET owner_user_id = $2, updated_at = NOW() WHERE id = $1"#,
+        organization_id,
+        new_owner_id
+    ).execute(&mut *tx).await?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_support::app_state;
+
+    const UUID: &str = "123e4567-e89b-12d3-a456-426614174000";
+
+    #[test]
+    fn slugs_are_lowercase_words_separated_by_single_hyphens() {
+        assert_eq!(slugify("ACME Engineering"), "acme-engineering");
+        assert_eq!(slugify("  Acme -- 2.0!  "), "acme-2-0");
+        assert_eq!(slugify(&format!("{} b", "a".repeat(39))), "a".repeat(39));
+    }
+
+    #[test]
+    fn characters_outside_of_ascii_are_left_out() {
+        assert_eq!(slugify("Müller GmbH"), "mller-gmbh");
+        assert_eq!(slugify("Crème Brûlée – Café"), "crme-brle-caf");
+    }
+
+    #[test]
+    fn names_without_a_valid_slug_become_organization() {
+        for name in ["", "!!!", "日本", "Invitations", UUID, &UUID.to_uppercase()] {
+            assert_eq!(slugify(name), "organization", "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn slugs_are_validated() {
+        assert!(is_valid_slug("acme-2"));
+        assert!(is_valid_slug(&"a".repeat(MAX_SLUG_LENGTH)));
+
+        for slug in [
+            "",
+            "-acme",
+            "acme-",
+            "acme--2",
+            "Acme",
+            "acme corp",
+            "müller",
+            "invitations",
+            UUID,
+            &UUID.replace('-', ""),
+            &"a".repeat(MAX_SLUG_LENGTH + 1),
+        ] {
+            assert!(!is_valid_slug(slug), "{:?}", slug);
+        }
+    }
+
+    #[sqlx::test]
+    async fn retries_slugs_taken_while_creating(db: PgPool) {
+        let ctx = app_state(db.clone());
+        let mut user_ids = vec![];
+        for email in ["jane@example.com", "john@example.com"] {
+            let user_id = query_scalar!(
+                "INSERT INTO users (email, password) VALUES ($1, 'x') RETURNING id",
+                email
+            ).fetch_one(&db).await.unwrap();
+            user_ids.push(user_id);
+        }
+
+        // The slug is not visible yet, so the insert waits for the other transaction and fails.
+        let mut other = db.begin().await.unwrap();
+        query!(
+            "INSERT INTO organizations (name, owner_user_id, slug) VALUES ('Acme', $1, 'acme')",
+            user_ids[1]
+        ).execute(&mut *other).await.unwrap();
+        let org = CreateOrganization { name: "Acme".to_string(), user_id: user_ids[0] };
+        let created = tokio::spawn(async move { create_organization(org, &ctx).await });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        other.commit().await.unwrap();
+
+        let organization = created.await.unwrap().unwrap();
+        assert_eq!(organization.slug, "acme-2");
+        let role = query_scalar!(
+            "SELECT role FROM user_organizations WHERE user_id = $1 AND organization_id = $2",
+            user_ids[0],
+            organization.id
+        ).fetch_one(&db).await.unwrap();
+        assert_eq!(role.as_deref(), Some("owner"));
+    }
+}
@@ -0,0 +1,136 @@
+use sqlx::{ query, query_as, query_scalar };
+use uuid::Uuid;
+
+use crate::AppState;
+
+pub const REGISTRATION_CHALLENGE: &str = "registration";
+pub const AUTHENTICATION_CHALLENGE: &str = "authentication";
+
+/// A registered passkey as listed to its user.
+#[derive(serde::Serialize)]
+pub struct PasskeyInfo {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+}
+
+pub struct StoredPasskey {
+    pub id: Uuid,
+    pub passkey: serde_json::Value,
+}
+
+/// Stores the state of a ceremony for five minutes and returns its id.
+pub async fn create_webauthn_challenge(
+    user_id: Option<&Uuid>,
+    kind: &str,
+    state: serde_json::Value,
+    ctx: &AppState
+) -> Result<Uuid, sqlx::Error> {
+    query!(r#"DELETE FROM webauthn_challenges WHERE expires_at < NOW()"#).execute(&ctx.db).await?;
+
+    query_scalar!(
+        r#"INSERT INTO webauthn_challenges (user_id, kind, state, expires_at)
+        VALUES ($1, $2, $3, $4) RETURNING id"#,
+        user_id,
+        kind,
+        state,
+        (chrono::Utc::now() + chrono::Duration::minutes(5)).naive_utc()
+    ).fetch_one(&ctx.db).await
+}
+
+/// Consumes the state of a ceremony, so every challenge can only be answered once.
+pub async fn take_webauthn_challenge(
+    id: &Uuid,
+    user_id: Option<&Uuid>,
+    kind: &str,
+    ctx: &AppState
+) -> Result<Option<serde_json::Value>, sqlx::Error> {
+    query_scalar!(
+        r#"DELETE FROM webauthn_challenges
+        WHERE id = $1 AND user_id IS NOT DISTINCT FROM $2 AND kind = $3 AND expires_at > NOW()
+        RETURNING state"#,
+        id,
+        user_id,
+        kind
+    ).fetch_optional(&ctx.db).await
+}
+
+pub async fn get_passkeys_by_user_id(
+    user_id: &Uuid,
+    ctx: &AppState
+) -> Result<Vec<PasskeyInfo>, sqlx::Error> {
+    query_as!(
+        PasskeyInfo,
+        r#"SELECT id, name, created_at, last_used_at FROM webauthn_credentials
+        WHERE user_id = $1 ORDER BY created_at"#,
+        user_id
+    ).fetch_all(&ctx.db).await
+}
+
+pub async fn get_credential_ids_by_user_id(
+    user_id: &Uuid,
+    ctx: &AppState
+) -> Result<Vec<Vec<u8>>, sqlx::Error> {
+    query_scalar!(r#"SELECT credential_id FROM webauthn_credentials WHERE user_id = $1"#, user_id)
+        .fetch_all(&ctx.db).await
+}
+
+pub async fn create_passkey(
+    user_id: &Uuid,
+    name: &str,
+    credential_id: &[u8],
+    passkey: serde_json::Value,
+    ctx: &AppState
+) -> Result<PasskeyInfo, sqlx::Error> {
+    query_as!(
+        PasskeyInfo,
+        r#"INSERT INTO webauthn_credentials (user_id, name, credential_id, passkey)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, name, created_at, last_used_at"#,
+        user_id,
+        name,
+        credential_id,
+        passkey
+    ).fetch_one(&ctx.db).await
+}
+
+pub async fn get_passkey_by_credential_id(
+    user_id: &Uuid,
+    credential_id: &[u8],
+    ctx: &AppState
+) -> Result<Option<StoredPasskey>, sqlx::Error> {
+    query_as!(
+        StoredPasskey,
+        r#"SELECT id, passkey FROM webauthn_credentials WHERE user_id = $1 AND credential_id = $2"#,
+        user_id,
+        credential_id
+    ).fetch_optional(&ctx.db).await
+}
+
+/// Records a login with the passkey, storing its new state when the signature counter moved.
+pub async fn record_passkey_use(
+    id: &Uuid,
+    passkey: Option<serde_json::Value>,
+    ctx: &AppState
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"UPDATE webauthn_credentials
+        SET passkey = COALESCE($2, passkey), last_used_at = NOW(), updated_at = NOW()
+        WHERE id = $1"#,
+        id,
+        passkey
+    )
+        .execute(&ctx.db).await
+        .map(|_| ())
+}
+
+pub async fn delete_passkey(
+    user_id: &Uuid,
+    id: &Uuid,
+    ctx: &AppState
+) -> Result<bool, sqlx::Error> {
+    query!(r#"DELETE FROM webauthn_credentials WHERE id = $1 AND user_id = $2"#, id, user_id)
+        .execute(&ctx.db).await
+        .map(|result| result.rows_affected() > 0)
+}
@@ -0,0 +1,66 @@
+use sqlx::query;
+use uuid::Uuid;
+
+use crate::{ auth::opaque_token::OpaqueToken, db::auth::revoke_all_refresh_tokens, AppState };
+
+/// Creates a single-use password reset token valid for one hour.
+pub async fn create_password_reset_token(
+    user_id: &Uuid,
+    ctx: &AppState
+) -> Result<String, sqlx::Error> {
+    let token = OpaqueToken::generate();
+    let expires_at = (chrono::Utc::now() + chrono::Duration::hours(1)).naive_utc();
+
+    query!(
+        r#"INSERT INTO password_reset_tokens (user_id, selector, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4)"#,
+        user_id,
+        token.selector,
+        token.hash(),
+        expires_at
+    )
+        .execute(&ctx.db).await
+        .map(|_| token.to_string())
+}
+
+/// Sets a new password if the token is valid, unused and not expired. Every outstanding reset
+/// token and every session of the user is invalidated. Returns the user id on success.
+pub async fn reset_password_with_token(
+    token: &str,
+    hashed_password: &str,
+    ctx: &AppState
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let Some(token) = OpaqueToken::parse(token) else {
+        return Ok(None);
+    };
+
+    let mut tx = ctx.db.begin().await?;
+
+    let reset = query!(
+        r#"SELECT id, user_id, token_hash FROM password_reset_tokens
+        WHERE selector = $1 AND used_at IS NULL AND expires_at > NOW()
+        FOR UPDATE"#,
+        token.selector
+    ).fetch_optional(&mut *tx).await?;
+
+    let Some(reset) = reset.filter(|reset| token.verify(&reset.token_hash)) else {
+        return Ok(None);
+    };
+
+    query!(
+        r#"UPDATE password_reset_tokens SET used_at = NOW() WHERE user_id = $1 AND used_at IS NULL"#,
+        reset.user_id
+    ).execute(&mut *tx).await?;
+
+    query!(
+        r#"UPDATE users SET password = $2, updated_at = NOW() WHERE id = $1"#,
+        reset.user_id,
+        hashed_password
+    ).execute(&mut *tx).await?;
+
+    revoke_all_refresh_tokens(&reset.user_id, &mut *tx).await?;
+
+    tx.commit().await?;
+
+    Ok(Some(reset.user_id))
+}
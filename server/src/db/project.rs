@@ -0,0 +1,100 @@
+use sqlx::{ query, query_as, PgExecutor };
+use uuid::Uuid;
+
+use crate::models::project::Project;
+
+pub async fn get_projects<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    executor: E
+) -> Result<Vec<Project>, sqlx::Error> {
+    query_as!(
+        Project,
+        r#"SELECT id, organization_id, client_id, name, description, archived_at, created_at
+        FROM projects WHERE organization_id = $1 ORDER BY name"#,
+        organization_id
+    ).fetch_all(executor).await
+}
+
+pub async fn get_project<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    project_id: &Uuid,
+    executor: E
+) -> Result<Option<Project>, sqlx::Error> {
+    query_as!(
+        Project,
+        r#"SELECT id, organization_id, client_id, name, description, archived_at, created_at
+        FROM projects WHERE id = $1 AND organization_id = $2"#,
+        project_id,
+        organization_id
+    ).fetch_optional(executor).await
+}
+
+/// Fails with a foreign key violation if the client is not one of the organization.
+pub async fn create_project<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    client_id: Option<&Uuid>,
+    name: &str,
+    description: &str,
+    executor: E
+) -> Result<Project, sqlx::Error> {
+    query_as!(
+        Project,
+        r#"INSERT INTO projects (organization_id, client_id, name, description)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, organization_id, client_id, name, description, archived_at, created_at"#,
+        organization_id,
+        client_id,
+        name,
+        description
+    ).fetch_one(executor).await
+}
+
+/// Changes the given fields, `None` leaves a field unchanged and `Some(None)` removes the client.
+/// Archiving an archived project keeps its original archival time.
+pub async fn update_project<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    project_id: &Uuid,
+    client_id: Option<Option<&Uuid>>,
+    name: Option<&str>,
+    description: Option<&str>,
+    archived: Option<bool>,
+    executor: E
+) -> Result<Option<Project>, sqlx::Error> {
+    query_as!(
+        Project,
+        r#"UPDATE projects SET
+            client_id = CASE WHEN $6 THEN $7 ELSE client_id END,
+            name = COALESCE($3, name),
+            description = COALESCE($4, description),
+            archived_at = CASE
+                WHEN $5::BOOLEAN IS NULL THEN archived_at
+                WHEN $5 THEN COALESCE(archived_at, NOW())
+                ELSE NULL
+            END,
+            updated_at = NOW()
+        WHERE id = $1 AND organization_id = $2
+        RETURNING id, organization_id, client_id, name, description, archived_at, created_at"#,
+        project_id,
+        organization_id,
+        name,
+        description,
+        archived,
+        client_id.is_some(),
+        client_id.flatten()
+    ).fetch_optional(executor).await
+}
+
+/// Deletes the project, which fails with a foreign key violation while it has time entries.
+pub async fn delete_project<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    project_id: &Uuid,
+    executor: E
+) -> Result<bool, sqlx::Error> {
+    query!(
+        r#"DELETE FROM projects WHERE id = $1 AND organization_id = $2"#,
+        project_id,
+        organization_id
+    )
+        .execute(executor).await
+        .map(|result| result.rows_affected() > 0)
+}
@@ -0,0 +1,144 @@
+use sqlx::{ query, query_as, PgExecutor };
+use uuid::Uuid;
+
+/// A role defined by an organization with its own set of permissions.
+#[derive(serde::Serialize)]
+pub struct CustomRole {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub name: String,
+    pub permissions: Vec<String>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+/// The role of a member, either one of the built-in roles or a custom role.
+pub struct Membership {
+    pub role: Option<String>,
+    pub custom_role_id: Option<Uuid>,
+    pub custom_role_name: Option<String>,
+    pub custom_role_permissions: Option<Vec<String>>,
+    /// Granted through the member's teams and their parent teams.
+    pub team_permissions: Vec<String>,
+}
+
+pub async fn get_membership<'e, E: PgExecutor<'e>>(
+    user_id: &Uuid,
+    organization_id: &Uuid,
+    executor: E
+) -> Result<Option<Membership>, sqlx::Error> {
+    query_as!(
+        Membership,
+        r#"SELECT user_organizations.role, user_organizations.custom_role_id,
+            organization_roles.name AS "custom_role_name?",
+            organization_roles.permissions AS "custom_role_permissions?",
+            ARRAY(
+                SELECT DISTINCT UNNEST(teams.permissions) FROM team_members
+                JOIN teams AS team ON team.id = team_members.team_id
+                JOIN teams ON teams.id = team.id OR teams.id = team.parent_team_id
+                WHERE team_members.user_id = $1 AND team_members.organization_id = $2
+            ) AS "team_permissions!"
+        FROM user_organizations
+        LEFT JOIN organization_roles ON organization_roles.id = user_organizations.custom_role_id
+        WHERE user_organizations.user_id = $1 AND user_organizations.organization_id = $2"#,
+        user_id,
+        organization_id
+    ).fetch_optional(executor).await
+}
+
+pub async fn get_custom_roles<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    executor: E
+) -> Result<Vec<CustomRole>, sqlx::Error> {
+    query_as!(
+        CustomRole,
+        r#"SELECT id, organization_id, name, permissions, created_at AS "created_at!"
+        FROM organization_roles WHERE organization_id = $1 ORDER BY name"#,
+        organization_id
+    ).fetch_all(executor).await
+}
+
+pub async fn get_custom_role<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    role_id: &Uuid,
+    executor: E
+) -> Result<Option<CustomRole>, sqlx::Error> {
+    query_as!(
+        CustomRole,
+        r#"SELECT id, organization_id, name, permissions, created_at AS "created_at!"
+        FROM organization_roles WHERE id = $1 AND organization_id = $2"#,
+        role_id,
+        organization_id
+    ).fetch_optional(executor).await
+}
+
+pub async fn create_custom_role<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    name: &str,
+    permissions: &[String],
+    executor: E
+) -> Result<CustomRole, sqlx::Error> {
+    query_as!(
+        CustomRole,
+        r#"INSERT INTO organization_roles (organization_id, name, permissions) VALUES ($1, $2, $3)
+        RETURNING id, organization_id, name, permissions, created_at AS "created_at!""#,
+        organization_id,
+        name,
+        permissions
+    ).fetch_one(executor).await
+}
+
+/// Renames the role and/or replaces its permissions, `None` leaves a field unchanged.
+pub async fn update_custom_role<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    role_id: &Uuid,
+    name: Option<&str>,
+    permissions: Option<&[String]>,
+    executor: E
+) -> Result<Option<CustomRole>, sqlx::Error> {
+    query_as!(
+        CustomRole,
+        r#"UPDATE organization_roles
+        SET name = COALESCE($3, name), permissions = COALESCE($4, permissions), updated_at = NOW()
+        WHERE id = $1 AND organization_id = $2
+        RETURNING id, organization_id, name, permissions, created_at AS "created_at!""#,
+        role_id,
+        organization_id,
+        name,
+        permissions
+    ).fetch_optional(executor).await
+}
+
+/// Deletes the role, which fails with a foreign key violation while members still have it.
+pub async fn delete_custom_role<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    role_id: &Uuid,
+    executor: E
+) -> Result<bool, sqlx::Error> {
+    query!(
+        r#"DELETE FROM organization_roles WHERE id = $1 AND organization_id = $2"#,
+        role_id,
+        organization_id
+    )
+        .execute(executor).await
+        .map(|result| result.rows_affected() > 0)
+}
+
+/// Gives the member a built-in role or a custom role, exactly one of them has to be set.
+pub async fn set_member_role<'e, E: PgExecutor<'e>>(
+    user_id: &Uuid,
+    organization_id: &Uuid,
+    role: Option<&str>,
+    custom_role_id: Option<&Uuid>,
+    executor: E
+) -> Result<bool, sqlx::Error> {
+    query!(
+        r#"UPDATE user_organizations SET role = $3, custom_role_id = $4, updated_at = NOW()
+        WHERE user_id = $1 AND organization_id = $2"#,
+        user_id,
+        organization_id,
+        role,
+        custom_role_id
+    )
+        .execute(executor).await
+        .map(|result| result.rows_affected() > 0)
+}
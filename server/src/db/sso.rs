@@ -0,0 +1,135 @@
+use sqlx::{ query, query_as, query_scalar, PgExecutor };
+use uuid::Uuid;
+
+use crate::AppState;
+
+#[derive(serde::Serialize)]
+pub struct OrganizationSso {
+    pub organization_id: Uuid,
+    pub domain: String,
+    pub domain_verification_token: String,
+    pub domain_verified_at: Option<chrono::NaiveDateTime>,
+    pub issuer: String,
+    pub client_id: String,
+    #[serde(skip)]
+    pub client_secret: String,
+    pub enforce_sso: bool,
+}
+
+pub struct UpsertOrganizationSso {
+    pub domain: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub enforce_sso: bool,
+}
+
+pub async fn get_organization_sso<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    executor: E
+) -> Result<Option<OrganizationSso>, sqlx::Error> {
+    query_as!(
+        OrganizationSso,
+        r#"SELECT organization_id, domain, domain_verification_token, domain_verified_at, issuer,
+            client_id, client_secret, enforce_sso
+        FROM organization_sso WHERE organization_id = $1"#,
+        organization_id
+    ).fetch_optional(executor).await
+}
+
+/// The SSO configuration of the organization that has verified the domain.
+pub async fn get_organization_sso_by_domain(
+    domain: &str,
+    ctx: &AppState
+) -> Result<Option<OrganizationSso>, sqlx::Error> {
+    query_as!(
+        OrganizationSso,
+        r#"SELECT organization_id, domain, domain_verification_token, domain_verified_at, issuer,
+            client_id, client_secret, enforce_sso
+        FROM organization_sso WHERE domain = $1 AND domain_verified_at IS NOT NULL"#,
+        domain
+    ).fetch_optional(&ctx.db).await
+}
+
+/// Creates or replaces the SSO configuration. Changing the domain starts a new verification with
+/// the given token, otherwise the current verification is kept.
+pub async fn upsert_organization_sso<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    sso: UpsertOrganizationSso,
+    verification_token: &str,
+    executor: E
+) -> Result<OrganizationSso, sqlx::Error> {
+    query_as!(
+        OrganizationSso,
+        r#"INSERT INTO organization_sso (organization_id, domain, domain_verification_token, issuer,
+            client_id, client_secret, enforce_sso)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (organization_id) DO UPDATE SET
+            domain = EXCLUDED.domain,
+            domain_verification_token = CASE WHEN organization_sso.domain = EXCLUDED.domain
+                THEN organization_sso.domain_verification_token ELSE EXCLUDED.domain_verification_token END,
+            domain_verified_at = CASE WHEN organization_sso.domain = EXCLUDED.domain
+                THEN organization_sso.domain_verified_at ELSE NULL END,
+            issuer = EXCLUDED.issuer,
+            client_id = EXCLUDED.client_id,
+            client_secret = EXCLUDED.client_secret,
+            enforce_sso = EXCLUDED.enforce_sso,
+            updated_at = NOW()
+        RETURNING organization_id, domain, domain_verification_token, domain_verified_at, issuer,
+            client_id, client_secret, enforce_sso"#,
+        organization_id,
+        sso.domain,
+        verification_token,
+        sso.issuer,
+        sso.client_id,
+        sso.client_secret,
+        sso.enforce_sso
+    ).fetch_one(executor).await
+}
+
+pub async fn delete_organization_sso<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    executor: E
+) -> Result<bool, sqlx::Error> {
+    query!(r#"DELETE FROM organization_sso WHERE organization_id = $1"#, organization_id)
+        .execute(executor).await
+        .map(|result| result.rows_affected() > 0)
+}
+
+/// Fails with a unique violation when another organization has already verified the domain.
+pub async fn mark_sso_domain_verified<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    executor: E
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"UPDATE organization_sso SET domain_verified_at = NOW(), updated_at = NOW()
+        WHERE organization_id = $1"#,
+        organization_id
+    )
+        .execute(executor).await
+        .map(|_| ())
+}
+
+/// Whether the user belongs to an organization that only allows logging in through its identity
+/// provider, other than the organization whose provider the login went through. Owners are
+/// exempt so a broken identity provider cannot lock everyone out.
+pub async fn is_sso_enforced_for_user(
+    user_id: &Uuid,
+    sso_organization_id: Option<&Uuid>,
+    ctx: &AppState
+) -> Result<bool, sqlx::Error> {
+    query_scalar!(
+        r#"SELECT EXISTS (
+            SELECT 1 FROM organization_sso
+            JOIN user_organizations ON user_organizations.organization_id = organization_sso.organization_id
+            JOIN organizations ON organizations.id = organization_sso.organization_id
+            WHERE user_organizations.user_id = $1
+                AND organization_sso.enforce_sso
+                AND organization_sso.domain_verified_at IS NOT NULL
+                AND organizations.owner_user_id <> $1
+                AND organization_sso.organization_id IS DISTINCT FROM $2
+        ) AS "enforced!""#,
+        user_id,
+        sso_organization_id
+    ).fetch_one(&ctx.db).await
+}
@@ -0,0 +1,150 @@
+use sqlx::{ query, query_as, PgExecutor };
+use uuid::Uuid;
+
+/// A group of members within an organization, optionally below a top-level team.
+#[derive(serde::Serialize)]
+pub struct Team {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub parent_team_id: Option<Uuid>,
+    pub name: String,
+    pub permissions: Vec<String>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(serde::Serialize)]
+pub struct TeamMember {
+    pub user_id: Uuid,
+    pub email: String,
+    pub joined_at: chrono::NaiveDateTime,
+}
+
+pub async fn get_teams<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    executor: E
+) -> Result<Vec<Team>, sqlx::Error> {
+    query_as!(
+        Team,
+        r#"SELECT id, organization_id, parent_team_id, name, permissions, created_at AS "created_at!"
+        FROM teams WHERE organization_id = $1 ORDER BY name"#,
+        organization_id
+    ).fetch_all(executor).await
+}
+
+pub async fn get_team<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    team_id: &Uuid,
+    executor: E
+) -> Result<Option<Team>, sqlx::Error> {
+    query_as!(
+        Team,
+        r#"SELECT id, organization_id, parent_team_id, name, permissions, created_at AS "created_at!"
+        FROM teams WHERE id = $1 AND organization_id = $2"#,
+        team_id,
+        organization_id
+    ).fetch_optional(executor).await
+}
+
+pub async fn create_team<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    parent_team_id: Option<&Uuid>,
+    name: &str,
+    permissions: &[String],
+    executor: E
+) -> Result<Team, sqlx::Error> {
+    query_as!(
+        Team,
+        r#"INSERT INTO teams (organization_id, parent_team_id, name, permissions)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, organization_id, parent_team_id, name, permissions, created_at AS "created_at!""#,
+        organization_id,
+        parent_team_id,
+        name,
+        permissions
+    ).fetch_one(executor).await
+}
+
+/// Renames the team and/or replaces its permissions, `None` leaves a field unchanged.
+pub async fn update_team<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    team_id: &Uuid,
+    name: Option<&str>,
+    permissions: Option<&[String]>,
+    executor: E
+) -> Result<Option<Team>, sqlx::Error> {
+    query_as!(
+        Team,
+        r#"UPDATE teams
+        SET name = COALESCE($3, name), permissions = COALESCE($4, permissions), updated_at = NOW()
+        WHERE id = $1 AND organization_id = $2
+        RETURNING id, organization_id, parent_team_id, name, permissions, created_at AS "created_at!""#,
+        team_id,
+        organization_id,
+        name,
+        permissions
+    ).fetch_optional(executor).await
+}
+
+/// Deletes the team and its memberships, which fails with a foreign key violation while it has
+/// sub-teams.
+pub async fn delete_team<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    team_id: &Uuid,
+    executor: E
+) -> Result<bool, sqlx::Error> {
+    query!(r#"DELETE FROM teams WHERE id = $1 AND organization_id = $2"#, team_id, organization_id)
+        .execute(executor).await
+        .map(|result| result.rows_affected() > 0)
+}
+
+pub async fn get_team_members<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    team_id: &Uuid,
+    executor: E
+) -> Result<Vec<TeamMember>, sqlx::Error> {
+    query_as!(
+        TeamMember,
+        r#"SELECT users.id AS user_id, users.email, team_members.created_at AS "joined_at!"
+        FROM team_members
+        JOIN users ON users.id = team_members.user_id
+        WHERE team_members.team_id = $1 AND team_members.organization_id = $2
+        ORDER BY team_members.created_at"#,
+        team_id,
+        organization_id
+    ).fetch_all(executor).await
+}
+
+/// Adds a member of the organization to the team, adding someone twice does nothing. Fails with a
+/// foreign key violation if the user is not a member of the organization.
+pub async fn add_team_member<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    team_id: &Uuid,
+    user_id: &Uuid,
+    executor: E
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"INSERT INTO team_members (team_id, organization_id, user_id) VALUES ($1, $2, $3)
+        ON CONFLICT (team_id, user_id) DO NOTHING"#,
+        team_id,
+        organization_id,
+        user_id
+    )
+        .execute(executor).await
+        .map(|_| ())
+}
+
+pub async fn remove_team_member<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    team_id: &Uuid,
+    user_id: &Uuid,
+    executor: E
+) -> Result<bool, sqlx::Error> {
+    query!(
+        r#"DELETE FROM team_members WHERE team_id = $1 AND organization_id = $2 AND user_id = $3"#,
+        team_id,
+        organization_id,
+        user_id
+    )
+        .execute(executor).await
+        .map(|result| result.rows_affected() > 0)
+}
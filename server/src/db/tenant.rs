@@ -0,0 +1,219 @@
+use sqlx::{ query, query_scalar, PgPool, Postgres, Transaction };
+use uuid::Uuid;
+
+/// Starts a transaction that can only see and change the rows of one organization. It switches to
+/// the `tick_tack_tenant` role, which row-level security restricts to the organization in the
+/// transaction-local `app.organization_id` setting, so both end with the transaction.
+pub async fn begin_tenant_transaction(
+    organization_id: &Uuid,
+    db: &PgPool
+) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let mut tx = db.begin().await?;
+
+    query!("SET LOCAL ROLE tick_tack_tenant").execute(&mut *tx).await?;
+    query_scalar!(
+        "SELECT set_config('app.organization_id', $1, true)",
+        organization_id.to_string()
+    ).fetch_one(&mut *tx).await?;
+
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{ query, query_scalar, PgPool };
+    use uuid::Uuid;
+
+    use super::begin_tenant_transaction;
+    use crate::db::{
+        invitation::{ create_invitation, get_pending_invitations },
+        organization::{ attach_user_to_organization, get_members, get_organization_details },
+        project::{ create_project, get_project, get_projects },
+        role::{ create_custom_role, get_custom_role, get_custom_roles },
+        time_entry::{
+            create_time_entry,
+            get_time_entries,
+            get_time_entry,
+            update_time_entry,
+            TimeEntryFields,
+            TimeEntryFilter,
+        },
+    };
+
+    struct Tenant {
+        organization_id: Uuid,
+        user_id: Uuid,
+    }
+
+    async fn create_tenant(name: &str, db: &PgPool) -> Tenant {
+        let user_id = query_scalar!(
+            "INSERT INTO users (email, password) VALUES ($1, 'x') RETURNING id",
+            format!("{}@example.com", name)
+        ).fetch_one(db).await.unwrap();
+        let organization_id = query_scalar!(
+            r#"INSERT INTO organizations (name, slug, owner_user_id) VALUES ($1, $1, $2)
+            RETURNING id"#,
+            name,
+            user_id
+        ).fetch_one(db).await.unwrap();
+        attach_user_to_organization(&user_id, &organization_id, "owner", db).await.unwrap();
+        Tenant { organization_id, user_id }
+    }
+
+    #[sqlx::test]
+    async fn reads_of_another_organization_return_nothing(db: PgPool) {
+        let acme = create_tenant("acme", &db).await;
+        let globex = create_tenant("globex", &db).await;
+        let mut tx = begin_tenant_transaction(&globex.organization_id, &db).await.unwrap();
+        let role = create_custom_role(&globex.organization_id, "auditor", &[], &mut *tx).await;
+        let role = role.unwrap();
+        let email = "invitee@example.com";
+        create_invitation(&globex.organization_id, email, "member", &globex.user_id, &mut tx).await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        // The queries still filter by organization, asking for another one finds nothing.
+        let mut tx = begin_tenant_transaction(&acme.organization_id, &db).await.unwrap();
+        let organization = get_organization_details(&globex.organization_id, &mut *tx).await;
+        assert!(organization.unwrap().is_none());
+        assert!(get_members(&globex.organization_id, &mut *tx).await.unwrap().is_empty());
+        assert!(get_custom_roles(&globex.organization_id, &mut *tx).await.unwrap().is_empty());
+        let role = get_custom_role(&globex.organization_id, &role.id, &mut *tx).await;
+        assert!(role.unwrap().is_none());
+        let invitations = get_pending_invitations(&globex.organization_id, &mut *tx).await;
+        assert!(invitations.unwrap().is_empty());
+
+        let members = get_members(&acme.organization_id, &mut *tx).await.unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].user_id, acme.user_id);
+    }
+
+    fn entry(project_id: Option<Uuid>) -> TimeEntryFields {
+        let started_at = chrono::Utc::now().naive_utc() - chrono::Duration::hours(1);
+        TimeEntryFields {
+            project_id,
+            started_at,
+            ended_at: Some(started_at + chrono::Duration::minutes(30)),
+            description: String::new(),
+            billable: false,
+        }
+    }
+
+    #[sqlx::test]
+    async fn projects_and_time_entries_of_another_organization_are_not_accessible(db: PgPool) {
+        let acme = create_tenant("acme", &db).await;
+        let globex = create_tenant("globex", &db).await;
+        let mut tx = begin_tenant_transaction(&globex.organization_id, &db).await.unwrap();
+        let project = create_project(&globex.organization_id, None, "Rocket", "", &mut *tx).await;
+        let project = project.unwrap();
+        let fields = entry(Some(project.id));
+        let time_entry = create_time_entry(
+            &globex.organization_id,
+            &globex.user_id,
+            &fields,
+            &mut *tx
+        ).await.unwrap();
+        tx.commit().await.unwrap();
+
+        let mut tx = begin_tenant_transaction(&acme.organization_id, &db).await.unwrap();
+        let found = get_project(&globex.organization_id, &project.id, &mut *tx).await;
+        assert!(found.unwrap().is_none());
+        assert!(get_projects(&globex.organization_id, &mut *tx).await.unwrap().is_empty());
+        let found = get_time_entry(&globex.organization_id, &time_entry.id, &mut *tx).await;
+        assert!(found.unwrap().is_none());
+        let filter = TimeEntryFilter::default();
+        let entries = get_time_entries(&globex.organization_id, &filter, &mut *tx).await;
+        assert!(entries.unwrap().is_empty());
+        let projects = query_scalar!("SELECT id FROM projects").fetch_all(&mut *tx).await;
+        assert!(projects.unwrap().is_empty());
+        let entries = query_scalar!("SELECT id FROM time_entries").fetch_all(&mut *tx).await;
+        assert!(entries.unwrap().is_empty());
+
+        let updated = update_time_entry(
+            &globex.organization_id,
+            &time_entry.id,
+            &entry(None),
+            &mut *tx
+        ).await;
+        assert!(updated.unwrap().is_none());
+        drop(tx);
+
+        // Neither their organization nor their projects can be used for new entries.
+        let mut tx = begin_tenant_transaction(&acme.organization_id, &db).await.unwrap();
+        let inserted = create_time_entry(
+            &globex.organization_id,
+            &acme.user_id,
+            &entry(None),
+            &mut *tx
+        ).await;
+        assert!(inserted.is_err());
+        drop(tx);
+
+        let mut tx = begin_tenant_transaction(&acme.organization_id, &db).await.unwrap();
+        let inserted = create_time_entry(
+            &acme.organization_id,
+            &acme.user_id,
+            &fields,
+            &mut *tx
+        ).await;
+        assert!(inserted.is_err());
+    }
+
+    #[sqlx::test]
+    async fn queries_without_a_where_clause_only_see_the_current_organization(db: PgPool) {
+        let acme = create_tenant("acme", &db).await;
+        create_tenant("globex", &db).await;
+
+        let mut tx = begin_tenant_transaction(&acme.organization_id, &db).await.unwrap();
+        let organizations = query_scalar!("SELECT id FROM organizations")
+            .fetch_all(&mut *tx).await.unwrap();
+        assert_eq!(organizations, vec![acme.organization_id]);
+        let users = query_scalar!("SELECT id FROM users").fetch_all(&mut *tx).await.unwrap();
+        assert_eq!(users, vec![acme.user_id]);
+        let memberships = query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM user_organizations"#)
+            .fetch_one(&mut *tx).await.unwrap();
+        assert_eq!(memberships, 1);
+    }
+
+    #[sqlx::test]
+    async fn rows_of_another_organization_cannot_be_written(db: PgPool) {
+        let acme = create_tenant("acme", &db).await;
+        let globex = create_tenant("globex", &db).await;
+
+        let mut tx = begin_tenant_transaction(&acme.organization_id, &db).await.unwrap();
+        let renamed = query!(
+            "UPDATE organizations SET name = 'taken' WHERE id = $1",
+            globex.organization_id
+        ).execute(&mut *tx).await.unwrap();
+        assert_eq!(renamed.rows_affected(), 0);
+        let inserted = create_custom_role(&globex.organization_id, "auditor", &[], &mut *tx).await;
+        assert!(inserted.is_err());
+    }
+
+    #[sqlx::test]
+    async fn other_tables_and_secrets_are_not_accessible(db: PgPool) {
+        let acme = create_tenant("acme", &db).await;
+
+        let mut tx = begin_tenant_transaction(&acme.organization_id, &db).await.unwrap();
+        let passwords = query_scalar!("SELECT password FROM users").fetch_all(&mut *tx).await;
+        assert!(passwords.is_err());
+        drop(tx);
+
+        let mut tx = begin_tenant_transaction(&acme.organization_id, &db).await.unwrap();
+        let sessions = query_scalar!("SELECT id FROM refresh_tokens").fetch_all(&mut *tx).await;
+        assert!(sessions.is_err());
+    }
+
+    #[sqlx::test]
+    async fn the_tenant_ends_with_the_transaction(db: PgPool) {
+        let acme = create_tenant("acme", &db).await;
+        create_tenant("globex", &db).await;
+
+        let tx = begin_tenant_transaction(&acme.organization_id, &db).await.unwrap();
+        tx.commit().await.unwrap();
+
+        let organizations = query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM organizations"#)
+            .fetch_one(&db).await.unwrap();
+        assert_eq!(organizations, 2);
+    }
+}
@@ -0,0 +1,156 @@
+use sqlx::{ query, query_as, PgExecutor };
+use uuid::Uuid;
+
+use crate::models::time_entry::TimeEntry;
+
+/// The fields of an entry its owner can set. Entries without an end are running timers, a user can
+/// only have one of them.
+pub struct TimeEntryFields {
+    pub project_id: Option<Uuid>,
+    pub started_at: chrono::NaiveDateTime,
+    pub ended_at: Option<chrono::NaiveDateTime>,
+    pub description: String,
+    pub billable: bool,
+}
+
+/// Narrows down a listing, `None` does not filter.
+#[derive(Default)]
+pub struct TimeEntryFilter {
+    pub user_id: Option<Uuid>,
+    pub project_id: Option<Uuid>,
+    pub from: Option<chrono::NaiveDateTime>,
+    pub to: Option<chrono::NaiveDateTime>,
+}
+
+/// Entries that started within the filtered period, most recent first.
+pub async fn get_time_entries<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    filter: &TimeEntryFilter,
+    executor: E
+) -> Result<Vec<TimeEntry>, sqlx::Error> {
+    query_as!(
+        TimeEntry,
+        r#"SELECT id, organization_id, user_id, project_id, started_at, ended_at, description,
+            billable, created_at
+        FROM time_entries
+        WHERE organization_id = $1
+            AND ($2::UUID IS NULL OR user_id = $2)
+            AND ($3::UUID IS NULL OR project_id = $3)
+            AND ($4::TIMESTAMP IS NULL OR started_at >= $4)
+            AND ($5::TIMESTAMP IS NULL OR started_at < $5)
+        ORDER BY started_at DESC"#,
+        organization_id,
+        filter.user_id,
+        filter.project_id,
+        filter.from,
+        filter.to
+    ).fetch_all(executor).await
+}
+
+pub async fn get_time_entry<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    entry_id: &Uuid,
+    executor: E
+) -> Result<Option<TimeEntry>, sqlx::Error> {
+    query_as!(
+        TimeEntry,
+        r#"SELECT id, organization_id, user_id, project_id, started_at, ended_at, description,
+            billable, created_at
+        FROM time_entries WHERE id = $1 AND organization_id = $2"#,
+        entry_id,
+        organization_id
+    ).fetch_optional(executor).await
+}
+
+/// Fails with a foreign key violation if the project is not one of the organization, and with a
+/// unique violation when starting a second timer for the user.
+pub async fn create_time_entry<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    user_id: &Uuid,
+    entry: &TimeEntryFields,
+    executor: E
+) -> Result<TimeEntry, sqlx::Error> {
+    query_as!(
+        TimeEntry,
+        r#"INSERT INTO time_entries
+            (organization_id, user_id, project_id, started_at, ended_at, description, billable)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, organization_id, user_id, project_id, started_at, ended_at, description,
+            billable, created_at"#,
+        organization_id,
+        user_id,
+        entry.project_id,
+        entry.started_at,
+        entry.ended_at,
+        entry.description,
+        entry.billable
+    ).fetch_one(executor).await
+}
+
+pub async fn update_time_entry<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    entry_id: &Uuid,
+    entry: &TimeEntryFields,
+    executor: E
+) -> Result<Option<TimeEntry>, sqlx::Error> {
+    query_as!(
+        TimeEntry,
+        r#"UPDATE time_entries SET project_id = $3, started_at = $4, ended_at = $5,
+            description = $6, billable = $7, updated_at = NOW()
+        WHERE id = $1 AND organization_id = $2
+        RETURNING id, organization_id, user_id, project_id, started_at, ended_at, description,
+            billable, created_at"#,
+        entry_id,
+        organization_id,
+        entry.project_id,
+        entry.started_at,
+        entry.ended_at,
+        entry.description,
+        entry.billable
+    ).fetch_optional(executor).await
+}
+
+pub async fn delete_time_entry<'e, E: PgExecutor<'e>>(
+    organization_id: &Uuid,
+    entry_id: &Uuid,
+    executor: E
+) -> Result<bool, sqlx::Error> {
+    query!(
+        r#"DELETE FROM time_entries WHERE id = $1 AND organization_id = $2"#,
+        entry_id,
+        organization_id
+    )
+        .execute(executor).await
+        .map(|result| result.rows_affected() > 0)
+}
+
+/// The user's running timer, in whichever organization it was started.
+pub async fn get_running_time_entry<'e, E: PgExecutor<'e>>(
+    user_id: &Uuid,
+    executor: E
+) -> Result<Option<TimeEntry>, sqlx::Error> {
+    query_as!(
+        TimeEntry,
+        r#"SELECT id, organization_id, user_id, project_id, started_at, ended_at, description,
+            billable, created_at
+        FROM time_entries WHERE user_id = $1 AND ended_at IS NULL"#,
+        user_id
+    ).fetch_optional(executor).await
+}
+
+/// Stops the user's running timer, if there is one.
+pub async fn stop_running_time_entry<'e, E: PgExecutor<'e>>(
+    user_id: &Uuid,
+    ended_at: chrono::NaiveDateTime,
+    executor: E
+) -> Result<Option<TimeEntry>, sqlx::Error> {
+    query_as!(
+        TimeEntry,
+        r#"UPDATE time_entries SET ended_at = $2, updated_at = NOW()
+        WHERE user_id = $1 AND ended_at IS NULL
+        RETURNING id, organization_id, user_id, project_id, started_at, ended_at, description,
+            billable, created_at"#,
+        user_id,
+        ended_at
+    ).fetch_optional(executor).await
+}
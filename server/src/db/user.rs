@@ -1,27 +1,29 @@
-use sqlx::{ query, query_scalar };
+use sqlx::{ query, query_scalar, PgExecutor };
 use uuid::Uuid;
 
 use crate::{ models::user::User, state::AppState };
 
-use super::auth::create_refresh_token;
-
 pub async fn get_user_by_email(email: &str, ctx: &AppState) -> Result<User, sqlx::Error> {
-    query!(r#"SELECT id, email, password FROM users WHERE email = $1"#, &email)
+    query!(r#"SELECT id, email, password, email_verified_at, totp_enabled_at FROM users WHERE email = $1"#, &email)
         .fetch_one(&ctx.db).await
         .map(|record| User {
             id: record.id,
             email: record.email,
             password: record.password,
+            email_verified_at: record.email_verified_at,
+            totp_enabled_at: record.totp_enabled_at,
         })
 }
 
 pub async fn get_user_by_id(id: &Uuid, ctx: &AppState) -> Result<User, sqlx::Error> {
-    query!(r#"SELECT id, email, password FROM users WHERE id = $1"#, &id)
+    query!(r#"SELECT id, email, password, email_verified_at, totp_enabled_at FROM users WHERE id = $1"#, &id)
         .fetch_one(&ctx.db).await
         .map(|record| User {
             id: record.id,
             email: record.email,
             password: record.password,
+            email_verified_at: record.email_verified_at,
+            totp_enabled_at: record.totp_enabled_at,
         })
 }
 
@@ -40,7 +42,26 @@ pub async fn create_user(
     {
         Ok(id) => Ok(id),
         Err(e) => {
-            return Err(e);
+            Err(e)
         }
     }
 }
+
+pub async fn update_user_password<'e, E: PgExecutor<'e>>(
+    id: &Uuid,
+    hashed_password: &str,
+    executor: E
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"UPDATE users SET password = $2, updated_at = NOW() WHERE id = $1"#,
+        id,
+        hashed_password
+    )
+        .execute(executor).await
+        .map(|_| ())
+}
+
+pub async fn email_exists(email: &str, ctx: &AppState) -> Result<bool, sqlx::Error> {
+    query_scalar!(r#"SELECT EXISTS (SELECT 1 FROM users WHERE email = $1) AS "exists!""#, email)
+        .fetch_one(&ctx.db).await
+}
@@ -0,0 +1,29 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use chrono::Utc;
+
+use super::{ Email, Mailer };
+
+/// Writes every email to its own `.eml` file in a directory, for local development.
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> Result<(), String> {
+        tokio::fs::create_dir_all(&self.dir).await.map_err(|e| e.to_string())?;
+
+        let file_name = format!("{}-{}.eml", Utc::now().format("%Y%m%dT%H%M%S%.f"), email.to);
+        let contents = format!("To: {}\nSubject: {}\n\n{}\n", email.to, email.subject, email.body);
+
+        tokio::fs::write(self.dir.join(file_name), contents).await.map_err(|e| e.to_string())
+    }
+}
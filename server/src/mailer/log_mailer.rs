@@ -0,0 +1,15 @@
+use async_trait::async_trait;
+use log::info;
+
+use super::{ Email, Mailer };
+
+/// Logs emails instead of sending them, for local development.
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: Email) -> Result<(), String> {
+        info!("Email to {}: {}\n{}", email.to, email.subject, email.body);
+        Ok(())
+    }
+}
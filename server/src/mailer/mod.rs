@@ -0,0 +1,42 @@
+use std::{ env, path::PathBuf, sync::Arc };
+
+use async_trait::async_trait;
+use log::{ info, warn };
+
+pub mod file;
+pub mod log_mailer;
+
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), String>;
+}
+
+/// Picks the mailer from `MAILER`, `file` writes messages to `MAILER_DIR` and `log` logs them.
+/// Emails contain reset and invitation links, so there is no default that could end up logging
+/// them in production.
+pub fn mailer_from_env() -> Result<Arc<dyn Mailer>, String> {
+    match env::var("MAILER").unwrap_or_default().as_str() {
+        "file" => {
+            let dir = env::var("MAILER_DIR").unwrap_or_else(|_| "./mails".to_string());
+            info!("Writing emails to {}", dir);
+            Ok(Arc::new(file::FileMailer::new(PathBuf::from(dir))))
+        }
+        "log" => {
+            warn!("Logging emails, including the links they contain");
+            Ok(Arc::new(log_mailer::LogMailer))
+        }
+        "" => Err("MAILER is not set, expected file or log".to_string()),
+        mailer => Err(format!("Unknown MAILER {}, expected file or log", mailer)),
+    }
+}
+
+/// Base URL of the frontend, used for links in emails.
+pub fn app_url() -> String {
+    env::var("APP_URL").unwrap_or_else(|_| "http://localhost:3000".to_string())
+}
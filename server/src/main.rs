@@ -3,18 +3,29 @@ mod routers;
 mod state;
 mod auth;
 mod db;
+mod mailer;
+mod realtime;
+mod billing;
+#[cfg(test)]
+mod test_support;
 
-use axum::{ response::IntoResponse, routing::get, Router, Extension };
-use fastwebsockets::upgrade;
-use fastwebsockets::OpCode;
-use fastwebsockets::WebSocketError;
+use axum::{ routing::get, Router, Extension };
 use log::{ error, info };
 use sqlx::postgres::PgPoolOptions;
 use dotenv::dotenv;
+use auth::{
+    keys::Keys,
+    login_throttle,
+    oidc::OidcProviders,
+    passkey::webauthn_from_env,
+    revocation::{ spawn_revocation_cleanup, RevocationCache },
+};
 use state::AppState;
 use tower_http::cors::CorsLayer;
 
 use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
 
 #[tokio::main]
 async fn main() {
@@ -38,44 +49,30 @@ async fn main() {
         }
     };
 
-    let shared_state = AppState { db: pool };
+    let shared_state = AppState {
+        db: pool,
+        keys: Arc::new(
+            Keys::from_env().unwrap_or_else(|e| panic!("Invalid JWT key configuration: {}", e))
+        ),
+        revoked_access_tokens: RevocationCache::default(),
+        mailer: mailer
+            ::mailer_from_env()
+            .unwrap_or_else(|e| panic!("Invalid mailer configuration: {}", e)),
+        login_attempts: login_throttle
+            ::attempt_store_from_env().await
+            .unwrap_or_else(|e| panic!("Invalid login throttle configuration: {}", e)),
+        oidc: Arc::new(OidcProviders::from_env()),
+        webauthn: webauthn_from_env(),
+        events: realtime::event_bus_from_env().await,
+    };
+    spawn_revocation_cleanup(shared_state.clone());
     let app = Router::new()
-        .route("/ws", get(ws_handler))
+        .route("/ws", get(realtime::socket::ws_handler))
         .nest("/api", routers::router())
+        .nest("/.well-known", routers::well_known::router())
         .layer(CorsLayer::permissive())
         .layer(Extension(shared_state));
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3001").await.unwrap();
-    axum::serve(listener, app.into_make_service()).await.unwrap();
-}
-
-async fn handle_client(fut: upgrade::UpgradeFut) -> Result<(), WebSocketError> {
-    let mut ws = fastwebsockets::FragmentCollector::new(fut.await?);
-    loop {
-        let frame = ws.read_frame().await?;
-        match frame.opcode {
-            OpCode::Close => {
-                break;
-            }
-            OpCode::Text | OpCode::Binary => {
-                info!("Received message: {:?}", frame.payload);
-                ws.write_frame(frame).await?;
-            }
-            _ => {}
-        }
-    }
-
-    Ok(())
-}
-
-async fn ws_handler(ws: upgrade::IncomingUpgrade) -> impl IntoResponse {
-    let (response, fut) = ws.upgrade().unwrap();
-
-    tokio::task::spawn(async move {
-        if let Err(e) = handle_client(fut).await {
-            eprintln!("Error in websocket connection: {}", e);
-        }
-    });
-
-    response
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
 }
@@ -0,0 +1,11 @@
+use uuid::Uuid;
+use sqlx::FromRow;
+
+/// Who an organization works for, projects can belong to one.
+#[derive(FromRow, serde::Serialize, Clone)]
+pub struct Client {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub name: String,
+    pub created_at: chrono::NaiveDateTime,
+}
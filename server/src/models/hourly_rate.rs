@@ -0,0 +1,18 @@
+use uuid::Uuid;
+use sqlx::FromRow;
+
+/// What an hour is billed at from `effective_from` on. The ids that are set decide what the rate
+/// applies to, none of them means the whole organization.
+#[derive(FromRow, serde::Serialize, Clone)]
+pub struct HourlyRate {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub client_id: Option<Uuid>,
+    pub project_id: Option<Uuid>,
+    pub user_id: Option<Uuid>,
+    /// In the minor unit of the currency, like cents.
+    pub amount: i64,
+    pub currency: String,
+    pub effective_from: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+}
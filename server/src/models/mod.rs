@@ -1 +1,5 @@
 pub mod user;
+pub mod project;
+pub mod time_entry;
+pub mod client;
+pub mod hourly_rate;
@@ -0,0 +1,13 @@
+use uuid::Uuid;
+use sqlx::FromRow;
+
+#[derive(FromRow, serde::Serialize, Clone)]
+pub struct Project {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub client_id: Option<Uuid>,
+    pub name: String,
+    pub description: String,
+    pub archived_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+}
@@ -0,0 +1,17 @@
+use uuid::Uuid;
+use sqlx::FromRow;
+
+/// Time a member spent, optionally on a project of the organization.
+#[derive(FromRow, serde::Serialize, serde::Deserialize, Clone)]
+pub struct TimeEntry {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub user_id: Uuid,
+    pub project_id: Option<Uuid>,
+    pub started_at: chrono::NaiveDateTime,
+    /// `None` while the timer is running.
+    pub ended_at: Option<chrono::NaiveDateTime>,
+    pub description: String,
+    pub billable: bool,
+    pub created_at: chrono::NaiveDateTime,
+}
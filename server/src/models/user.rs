@@ -5,5 +5,15 @@ use sqlx::FromRow;
 pub struct User {
     pub id: Uuid,
     pub email: String,
-    pub password: String,
+    /// `None` for accounts created through a social login.
+    pub password: Option<String>,
+    pub email_verified_at: Option<chrono::NaiveDateTime>,
+    pub totp_enabled_at: Option<chrono::NaiveDateTime>,
+}
+
+impl User {
+    /// Checks a password against the stored hash, accounts without a password never match.
+    pub fn verify_password(&self, password: &str) -> bool {
+        self.password.as_deref().is_some_and(|hash| pwhash::bcrypt::verify(password, hash))
+    }
 }
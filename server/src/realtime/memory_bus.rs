@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::sync::broadcast;
+
+use super::{ Event, EventBus, EVENT_BUFFER };
+
+/// Hands events to the sockets of this process, only suitable for a single instance.
+pub struct MemoryEventBus {
+    sender: broadcast::Sender<Arc<Event>>,
+}
+
+impl Default for MemoryEventBus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUFFER);
+        Self { sender }
+    }
+}
+
+#[async_trait]
+impl EventBus for MemoryEventBus {
+    async fn publish(&self, event: Event) -> Result<(), String> {
+        // Sending only fails when no socket is connected, then nobody misses the event.
+        let _ = self.sender.send(Arc::new(event));
+        Ok(())
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Arc<Event>> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    use super::MemoryEventBus;
+    use crate::models::time_entry::TimeEntry;
+    use crate::realtime::{ Event, EventBus };
+
+    fn time_entry() -> TimeEntry {
+        let now = Utc::now().naive_utc();
+        TimeEntry {
+            id: Uuid::new_v4(),
+            organization_id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            project_id: None,
+            started_at: now,
+            ended_at: None,
+            description: "Writing tests".to_string(),
+            billable: true,
+            created_at: now,
+        }
+    }
+
+    #[tokio::test]
+    async fn every_subscriber_receives_published_events() {
+        let bus = MemoryEventBus::default();
+        let mut first = bus.subscribe();
+        let mut second = bus.subscribe();
+        let entry = time_entry();
+
+        bus.publish(Event::TimerStarted { time_entry: entry.clone() }).await.unwrap();
+
+        for receiver in [&mut first, &mut second] {
+            let event = receiver.recv().await.unwrap();
+            assert!(matches!(*event, Event::TimerStarted { .. }));
+            assert_eq!(event.time_entry().id, entry.id);
+        }
+    }
+
+    #[tokio::test]
+    async fn publishing_without_subscribers_succeeds() {
+        let bus = MemoryEventBus::default();
+        let result = bus.publish(Event::TimerStopped { time_entry: time_entry() }).await;
+        assert!(result.is_ok());
+    }
+}
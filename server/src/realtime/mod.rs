@@ -0,0 +1,114 @@
+use std::{ env, sync::Arc };
+
+use async_trait::async_trait;
+use log::{ error, info };
+use tokio::sync::broadcast;
+
+use crate::{ models::time_entry::TimeEntry, state::AppState };
+
+pub mod memory_bus;
+pub mod redis_bus;
+pub mod socket;
+
+/// Events that are not delivered within this many newer ones are dropped for slow sockets.
+const EVENT_BUFFER: usize = 1024;
+
+/// Something that changed and that connected clients get pushed.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    TimerStarted {
+        time_entry: TimeEntry,
+    },
+    TimerStopped {
+        time_entry: TimeEntry,
+    },
+    TimeEntryCreated {
+        time_entry: TimeEntry,
+    },
+    TimeEntryUpdated {
+        time_entry: TimeEntry,
+    },
+    TimeEntryDeleted {
+        time_entry: TimeEntry,
+    },
+}
+
+impl Event {
+    /// The entry the event is about, which decides who may see it.
+    pub fn time_entry(&self) -> &TimeEntry {
+        match self {
+            Event::TimerStarted { time_entry }
+            | Event::TimerStopped { time_entry }
+            | Event::TimeEntryCreated { time_entry }
+            | Event::TimeEntryUpdated { time_entry }
+            | Event::TimeEntryDeleted { time_entry } => time_entry,
+        }
+    }
+}
+
+/// Carries events to the sockets. The in-memory bus is enough for a single instance, multiple
+/// instances have to share events through Redis so sockets get them wherever they are connected.
+#[async_trait]
+pub trait EventBus: Send + Sync {
+    async fn publish(&self, event: Event) -> Result<(), String>;
+    /// Events published by any instance, including this one.
+    fn subscribe(&self) -> broadcast::Receiver<Arc<Event>>;
+}
+
+/// Picks the bus from `EVENT_BUS`, `redis` uses `REDIS_URL`, anything else keeps events in
+/// memory.
+pub async fn event_bus_from_env() -> Arc<dyn EventBus> {
+    match env::var("EVENT_BUS").as_deref() {
+        Ok("redis") => {
+            let url = env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
+            info!("Publishing events through Redis");
+            let bus = redis_bus::RedisEventBus
+                ::connect(&url).await
+                .expect("Failed to connect to the Redis server of REDIS_URL");
+            Arc::new(bus)
+        }
+        _ => Arc::new(memory_bus::MemoryEventBus::default()),
+    }
+}
+
+/// Publishes the event after the change was committed. Errors are logged, clients then only see
+/// the change when they fetch it.
+pub async fn publish_event(event: Event, ctx: &AppState) {
+    if let Err(e) = ctx.events.publish(event).await {
+        error!("Failed to publish event: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    use super::Event;
+    use crate::models::time_entry::TimeEntry;
+
+    /// Other instances receive events as JSON through Redis.
+    #[test]
+    fn events_keep_their_content_as_json() {
+        let now = Utc::now().naive_utc();
+        let time_entry = TimeEntry {
+            id: Uuid::new_v4(),
+            organization_id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            project_id: None,
+            started_at: now,
+            ended_at: None,
+            description: "Writing tests".to_string(),
+            billable: true,
+            created_at: now,
+        };
+        let event = Event::TimeEntryUpdated { time_entry };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "time_entry_updated");
+
+        let decoded: Event = serde_json::from_value(json.clone()).unwrap();
+        assert!(matches!(decoded, Event::TimeEntryUpdated { .. }));
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), json);
+    }
+}
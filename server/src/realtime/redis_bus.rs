@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use log::{ error, warn };
+use redis::aio::{ ConnectionManager, PubSub };
+use tokio::sync::broadcast;
+use tokio::time::{ sleep, Duration };
+
+use super::{ Event, EventBus, EVENT_BUFFER };
+
+/// Every instance publishes to and listens on this channel.
+const CHANNEL: &str = "tick_tack:events";
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Fans events out to the sockets of every instance through Redis pub/sub. Events published while
+/// an instance is disconnected from Redis do not reach its sockets.
+pub struct RedisEventBus {
+    /// Reconnects on its own after Redis was unavailable.
+    connection: ConnectionManager,
+    sender: broadcast::Sender<Arc<Event>>,
+}
+
+impl RedisEventBus {
+    /// Connects for publishing and starts listening for the events of all instances.
+    pub async fn connect(url: &str) -> Result<Self, redis::RedisError> {
+        let client = redis::Client::open(url)?;
+        let connection = ConnectionManager::new(client.clone()).await?;
+        let pubsub = subscribe(&client).await?;
+
+        let (sender, _) = broadcast::channel(EVENT_BUFFER);
+        tokio::spawn(listen(client, pubsub, sender.clone()));
+        Ok(Self { connection, sender })
+    }
+}
+
+async fn subscribe(client: &redis::Client) -> Result<PubSub, redis::RedisError> {
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.subscribe(CHANNEL).await?;
+    Ok(pubsub)
+}
+
+/// Hands the events from Redis to the local sockets, subscribing again whenever the connection
+/// drops.
+async fn listen(client: redis::Client, mut pubsub: PubSub, sender: broadcast::Sender<Arc<Event>>) {
+    loop {
+        let mut messages = pubsub.into_on_message();
+        while let Some(message) = messages.next().await {
+            match serde_json::from_slice::<Event>(message.get_payload_bytes()) {
+                Ok(event) => {
+                    let _ = sender.send(Arc::new(event));
+                }
+                Err(e) => error!("Ignoring an invalid event: {:?}", e),
+            }
+        }
+
+        warn!("Lost the Redis event subscription, reconnecting");
+        pubsub = loop {
+            sleep(RECONNECT_DELAY).await;
+            match subscribe(&client).await {
+                Ok(pubsub) => {
+                    break pubsub;
+                }
+                Err(e) => error!("Failed to subscribe to events: {}", e),
+            }
+        };
+    }
+}
+
+impl RedisEventBus {
+    async fn publish_payload(&self, payload: &str) -> Result<(), redis::RedisError> {
+        let mut connection = self.connection.clone();
+        redis::cmd("PUBLISH").arg(CHANNEL).arg(payload).query_async::<()>(&mut connection).await
+    }
+}
+
+#[async_trait]
+impl EventBus for RedisEventBus {
+    async fn publish(&self, event: Event) -> Result<(), String> {
+        let payload = serde_json::to_string(&event).map_err(|e| e.to_string())?;
+        // A lost connection is only noticed by the command that fails on it, the retry goes
+        // through the new one.
+        match self.publish_payload(&payload).await {
+            Err(e) if e.is_unrecoverable_error() => self.publish_payload(&payload).await,
+            result => result,
+        }.map_err(|e| e.to_string())
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Arc<Event>> {
+        self.sender.subscribe()
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+
+use axum::extract::Query;
+use axum::http::StatusCode;
+use axum::response::{ IntoResponse, Response };
+use axum::Extension;
+use chrono::Utc;
+use fastwebsockets::{ upgrade, FragmentCollectorRead, Frame, OpCode, Payload, WebSocketError };
+use fastwebsockets::WebSocketWrite;
+use log::{ error, info, warn };
+use tokio::io::{ AsyncRead, AsyncWrite };
+use tokio::sync::{ broadcast, mpsc };
+use tokio::time::{ sleep_until, timeout, Duration, Instant };
+use uuid::Uuid;
+
+use crate::auth::authorization_middleware::{ authenticate_access_token, AuthExtension };
+use crate::auth::organization_access::{ OrganizationMember, Permission };
+use crate::AppState;
+
+use super::Event;
+
+/// Sockets that do not authenticate within this time are closed.
+const AUTHENTICATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Close code for sockets whose credentials are missing, invalid or expired.
+const POLICY_VIOLATION: u16 = 1008;
+
+/// Frames waiting to be written to a socket. Clients that fall this far behind are disconnected
+/// rather than buffering without limit.
+const OUTGOING_BUFFER: usize = 256;
+
+/// How long the access of the user to an organization is trusted before it is loaded again.
+/// Events are checked against it in memory, so removed members stop receiving them within this
+/// time.
+const ACCESS_TTL: Duration = Duration::from_secs(30);
+
+/// A stream of events a socket can subscribe to.
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Topic {
+    /// The user's own time entries in every organization.
+    User,
+    /// The time entries of an organization. Entries of other members need `time:manage`.
+    Organization {
+        organization_id: Uuid,
+    },
+}
+
+#[derive(serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// Has to be the first message unless the token is in the `token` query parameter. Access
+    /// tokens are short-lived, so clients send a fresh one before the previous expires to keep the
+    /// socket open.
+    Authenticate {
+        token: String,
+    },
+    Subscribe {
+        topic: Topic,
+    },
+    Unsubscribe {
+        topic: Topic,
+    },
+}
+
+#[derive(serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    Authenticated {
+        user_id: Uuid,
+    },
+    Subscribed {
+        topic: Topic,
+    },
+    Unsubscribed {
+        topic: Topic,
+    },
+    Event {
+        topic: Topic,
+        event: &'a Event,
+    },
+    Error {
+        message: &'a str,
+    },
+}
+
+/// What the user may see of the time entries of an organization.
+#[derive(Clone, Copy)]
+struct Access {
+    /// Whether the entries of other members are visible as well.
+    all_members: bool,
+}
+
+impl Access {
+    /// `None` without the organization:read permission.
+    fn of(member: &OrganizationMember) -> Option<Access> {
+        member
+            .has_permission(Permission::OrganizationRead)
+            .then(|| Access { all_members: member.has_permission(Permission::TimeManage) })
+    }
+}
+
+/// Whether the event belongs to the topic, given the access to the organization of its entry.
+fn matches(topic: &Topic, user_id: &Uuid, event: &Event, access: Access) -> bool {
+    let entry = event.time_entry();
+    match topic {
+        Topic::User => entry.user_id == *user_id,
+        Topic::Organization { organization_id } => {
+            entry.organization_id == *organization_id &&
+                (access.all_members || entry.user_id == *user_id)
+        }
+    }
+}
+
+/// When the access token of the socket expires.
+fn token_expiry(auth: &AuthExtension) -> Instant {
+    let exp = auth.session().map(|session| session.exp as i64).unwrap_or_default();
+    let remaining = (exp - Utc::now().timestamp()).max(0) as u64;
+    Instant::now() + Duration::from_secs(remaining)
+}
+
+/// The server side of an authenticated socket.
+struct Session {
+    ctx: AppState,
+    auth: AuthExtension,
+    expires_at: Instant,
+    subscriptions: Vec<Topic>,
+    /// The access of the user by organization, `None` where it was lost. Loaded when an
+    /// organization is first needed and trusted until `access_expires_at`.
+    access: HashMap<Uuid, Option<Access>>,
+    access_expires_at: Instant,
+    outgoing: mpsc::Sender<Frame<'static>>,
+    /// Set when the client did not keep up and frames had to be dropped, the socket is closed.
+    overflowed: bool,
+}
+
+impl Session {
+    fn send_frame(&mut self, frame: Frame<'static>) {
+        if let Err(mpsc::error::TrySendError::Full(_)) = self.outgoing.try_send(frame) {
+            self.overflowed = true;
+        }
+    }
+
+    fn send(&mut self, message: &ServerMessage) {
+        match serde_json::to_vec(message) {
+            Ok(payload) => self.send_frame(Frame::text(Payload::Owned(payload))),
+            Err(e) => error!("Failed to serialize socket message: {:?}", e),
+        }
+    }
+
+    fn send_error(&mut self, message: &str) {
+        self.send(&ServerMessage::Error { message });
+    }
+
+    fn close(&mut self, reason: &str) {
+        self.send_error(reason);
+        self.send_frame(Frame::close(POLICY_VIOLATION, reason.as_bytes()));
+    }
+
+    /// The access of the user to the organization, only loaded when it is not cached.
+    async fn access(&mut self, organization_id: Uuid) -> Result<Option<Access>, sqlx::Error> {
+        if let Some(access) = self.access.get(&organization_id) {
+            return Ok(*access);
+        }
+        let member = OrganizationMember::find(self.auth.clone(), organization_id, &self.ctx).await?;
+        let access = member.as_ref().and_then(Access::of);
+        self.access.insert(organization_id, access);
+        Ok(access)
+    }
+
+    /// Loads the cached access again, when it expires and whenever the socket authenticates
+    /// again, and drops the subscriptions to organizations the user lost access to.
+    async fn refresh_access(&mut self) {
+        let organization_ids: Vec<Uuid> = self.access.drain().map(|(id, _)| id).collect();
+        for organization_id in organization_ids {
+            // Left out of the cache on errors, so it is loaded again when it is needed.
+            if let Err(e) = self.access(organization_id).await {
+                error!("Failed to check access to organization {}: {:?}", organization_id, e);
+            }
+        }
+        self.access_expires_at = Instant::now() + ACCESS_TTL;
+        self.drop_lost_subscriptions();
+    }
+
+    fn drop_lost_subscriptions(&mut self) {
+        let mut lost = Vec::new();
+        self.subscriptions.retain(|topic| {
+            let Topic::Organization { organization_id } = topic else {
+                return true;
+            };
+            let keep = !matches!(self.access.get(organization_id), Some(None));
+            if !keep {
+                lost.push(*topic);
+            }
+            keep
+        });
+        for topic in lost {
+            self.send(&ServerMessage::Unsubscribed { topic });
+        }
+    }
+
+    async fn subscribe(&mut self, topic: Topic) -> Result<(), &'static str> {
+        if let Topic::Organization { organization_id } = topic {
+            let member = OrganizationMember::find(self.auth.clone(), organization_id, &self.ctx)
+                .await
+                .map_err(|_e| "Failed to fetch organization")?
+                .ok_or("Organization not found")?;
+            let access = Access::of(&member);
+            self.access.insert(organization_id, access);
+            access.ok_or("Missing the organization:read permission")?;
+        }
+        self.subscriptions.push(topic);
+        Ok(())
+    }
+
+    /// Sends the event once, for the first subscription it belongs to. The access to the entry's
+    /// organization comes from the cache, so events only cause a query for organizations it has
+    /// not seen yet.
+    async fn deliver(&mut self, event: &Event) {
+        let user_id = self.auth.user.id;
+        // Without any subscription the event could belong to, the access does not matter.
+        let full_access = Access { all_members: true };
+        if !self.subscriptions.iter().any(|topic| matches(topic, &user_id, event, full_access)) {
+            return;
+        }
+
+        let organization_id = event.time_entry().organization_id;
+        let access = match self.access(organization_id).await {
+            Ok(Some(access)) => access,
+            Ok(None) => {
+                return self.drop_lost_subscriptions();
+            }
+            Err(e) => {
+                error!("Failed to check access to organization {}: {:?}", organization_id, e);
+                return;
+            }
+        };
+
+        let topic = self.subscriptions.iter().find(|topic| matches(topic, &user_id, event, access));
+        if let Some(topic) = topic.copied() {
+            self.send(&ServerMessage::Event { topic, event });
+        }
+    }
+
+    async fn handle(&mut self, message: ClientMessage) {
+        match message {
+            ClientMessage::Authenticate { token } => self.reauthenticate(&token).await,
+            ClientMessage::Subscribe { topic } => {
+                if !self.subscriptions.contains(&topic) {
+                    if let Err(message) = self.subscribe(topic).await {
+                        return self.send_error(message);
+                    }
+                }
+                self.send(&ServerMessage::Subscribed { topic });
+            }
+            ClientMessage::Unsubscribe { topic } => {
+                self.subscriptions.retain(|t| *t != topic);
+                self.send(&ServerMessage::Unsubscribed { topic });
+            }
+        }
+    }
+
+    /// Extends the socket with a fresh token of the same user and checks the subscriptions again,
+    /// dropping those the user lost access to in the meantime.
+    async fn reauthenticate(&mut self, token: &str) {
+        let auth = match authenticate_access_token(token, &self.ctx).await {
+            Ok(auth) if auth.user.id == self.auth.user.id => auth,
+            Ok(_) => {
+                return self.send_error("The token belongs to another user");
+            }
+            Err(_) => {
+                return self.send_error("Invalid access token");
+            }
+        };
+
+        self.expires_at = token_expiry(&auth);
+        self.auth = auth;
+        self.refresh_access().await;
+        self.send(&ServerMessage::Authenticated { user_id: self.auth.user.id });
+    }
+}
+
+type Incoming = Result<ClientMessage, String>;
+
+/// Parses the messages of the client until it closes the socket. Pongs and the reply to a close
+/// frame go out through the writer.
+async fn read_messages<S: AsyncRead + Unpin>(
+    mut read: FragmentCollectorRead<S>,
+    incoming: mpsc::Sender<Incoming>,
+    outgoing: mpsc::Sender<Frame<'static>>
+) -> Result<(), WebSocketError> {
+    let mut send_obligated = |frame: Frame<'static>| {
+        let outgoing = outgoing.clone();
+        async move { outgoing.send(frame).await.map_err(|_e| "The socket is closed") }
+    };
+
+    loop {
+        let frame = read.read_frame(&mut send_obligated).await?;
+        let message = match frame.opcode {
+            OpCode::Close => {
+                break;
+            }
+            OpCode::Text => serde_json::from_slice(&frame.payload).map_err(|e| e.to_string()),
+            OpCode::Binary => Err("Messages have to be JSON text".to_string()),
+            _ => {
+                continue;
+            }
+        };
+        if incoming.send(message).await.is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+async fn write_frames<S: AsyncWrite + Unpin>(
+    mut write: WebSocketWrite<S>,
+    mut outgoing: mpsc::Receiver<Frame<'static>>
+) -> Result<(), WebSocketError> {
+    while let Some(frame) = outgoing.recv().await {
+        let closing = frame.opcode == OpCode::Close;
+        write.write_frame(frame).await?;
+        if closing {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Waits for the first message to authenticate the socket.
+async fn authenticate_first_message(
+    incoming: &mut mpsc::Receiver<Incoming>,
+    ctx: &AppState
+) -> Result<AuthExtension, &'static str> {
+    match timeout(AUTHENTICATION_TIMEOUT, incoming.recv()).await {
+        Ok(Some(Ok(ClientMessage::Authenticate { token }))) =>
+            authenticate_access_token(&token, ctx).await.map_err(|_e| "Invalid access token"),
+        Ok(_) => Err("Authenticate first"),
+        Err(_) => Err("Authentication timed out"),
+    }
+}
+
+async fn handle_client(
+    fut: upgrade::UpgradeFut,
+    ctx: AppState,
+    auth: Option<AuthExtension>
+) -> Result<(), WebSocketError> {
+    let (read, write) = fut.await?.split(tokio::io::split);
+    let (outgoing, outgoing_rx) = mpsc::channel(OUTGOING_BUFFER);
+    let (incoming_tx, mut incoming) = mpsc::channel(16);
+    let writer = tokio::spawn(write_frames(write, outgoing_rx));
+    let reader = tokio::spawn(
+        read_messages(FragmentCollectorRead::new(read), incoming_tx, outgoing.clone())
+    );
+
+    let auth = match auth {
+        Some(auth) => Ok(auth),
+        None => authenticate_first_message(&mut incoming, &ctx).await,
+    };
+    let auth = match auth {
+        Ok(auth) => auth,
+        Err(reason) => {
+            let _ = outgoing.try_send(Frame::close(POLICY_VIOLATION, reason.as_bytes()));
+            reader.abort();
+            return writer.await.unwrap_or(Ok(()));
+        }
+    };
+    let mut session = Session {
+        ctx: ctx.clone(),
+        expires_at: token_expiry(&auth),
+        auth,
+        subscriptions: Vec::new(),
+        access: HashMap::new(),
+        access_expires_at: Instant::now() + ACCESS_TTL,
+        outgoing,
+        overflowed: false,
+    };
+    info!("User {} connected to the socket", session.auth.user.id);
+    session.send(&ServerMessage::Authenticated { user_id: session.auth.user.id });
+
+    let mut events = ctx.events.subscribe();
+    loop {
+        tokio::select! {
+            message = incoming.recv() => match message {
+                Some(Ok(message)) => session.handle(message).await,
+                Some(Err(e)) => session.send_error(&format!("Invalid message: {}", e)),
+                None => break,
+            },
+            event = events.recv() => match event {
+                Ok(event) => session.deliver(&event).await,
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    session.send_error("Events were dropped, fetch the current state again");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = sleep_until(session.expires_at) => {
+                session.close("Access token expired");
+                break;
+            }
+            _ = sleep_until(session.access_expires_at) => session.refresh_access().await,
+        }
+        if session.overflowed {
+            break;
+        }
+    }
+
+    reader.abort();
+    if session.overflowed {
+        // The client is not reading, so there is no point in flushing the queue or saying goodbye.
+        warn!("Closing the socket of user {}, it did not keep up", session.auth.user.id);
+        writer.abort();
+        return Ok(());
+    }
+    drop(session);
+    writer.await.unwrap_or(Ok(()))
+}
+
+#[derive(serde::Deserialize)]
+pub struct SocketQuery {
+    token: Option<String>,
+}
+
+/// Pushes changes to time entries to clients. The access token of a login session goes either in
+/// the `token` query parameter or in an `authenticate` message right after connecting, then the
+/// client subscribes to topics.
+pub async fn ws_handler(
+    ctx: Extension<AppState>,
+    Query(query): Query<SocketQuery>,
+    ws: upgrade::IncomingUpgrade
+) -> Result<Response, StatusCode> {
+    // A token in the query is checked before upgrading, so it is refused with a plain status.
+    let auth = match &query.token {
+        Some(token) => Some(authenticate_access_token(token, &ctx).await?),
+        None => None,
+    };
+    let (response, fut) = ws.upgrade().map_err(|_e| StatusCode::BAD_REQUEST)?;
+
+    let ctx = ctx.0.clone();
+    tokio::task::spawn(async move {
+        if let Err(e) = handle_client(fut, ctx, auth).await {
+            error!("Error in websocket connection: {}", e);
+        }
+    });
+
+    Ok(response.into_response())
+}
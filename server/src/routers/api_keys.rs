@@ -0,0 +1,275 @@
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::routing::{ delete, get };
+use axum::{ middleware, Extension, Json };
+use axum::{ response::IntoResponse, Router };
+use log::error;
+use uuid::Uuid;
+
+use crate::auth::api_key::Scope;
+use crate::auth::authorization_middleware::{ auth_session, AuthExtension };
+use crate::db::api_key::{ create_api_key, get_api_keys_by_user_id, revoke_api_key, CreateApiKey };
+use crate::db::organization::is_user_in_organization;
+use crate::AppState;
+
+const MAX_EXPIRY_DAYS: i64 = 365;
+
+fn bad_request(message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": message,
+    });
+    (StatusCode::BAD_REQUEST, Json(error_response))
+}
+
+#[axum::debug_handler]
+async fn get_api_keys(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let api_keys = get_api_keys_by_user_id(&auth.user.id, &ctx).await.map_err(|_e| {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Failed to fetch API keys",
+        });
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "api_keys": api_keys,
+    })
+        )
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct NewApiKey {
+    name: String,
+    scopes: Vec<Scope>,
+    organization_id: Option<Uuid>,
+    expires_in_days: Option<i64>,
+}
+
+/// Creates an API key. The key is only returned here, afterwards only its metadata can be read.
+#[axum::debug_handler]
+async fn post_api_key(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>,
+    Json(req): Json<NewApiKey>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let name = req.name.trim();
+    if name.is_empty() || name.len() > 255 {
+        return Err(bad_request("Name must be between 1 and 255 characters"));
+    }
+    if req.scopes.is_empty() {
+        return Err(bad_request("At least one scope is required"));
+    }
+
+    let expires_at = match req.expires_in_days {
+        Some(days) if !(1..=MAX_EXPIRY_DAYS).contains(&days) => {
+            return Err(bad_request("Expiry must be between 1 and 365 days"));
+        }
+        Some(days) => Some((chrono::Utc::now() + chrono::Duration::days(days)).naive_utc()),
+        None => None,
+    };
+
+    if let Some(organization_id) = &req.organization_id {
+        match is_user_in_organization(&auth.user.id, organization_id, &ctx).await {
+            Ok(true) => {}
+            Ok(false) => {
+                let error_response =
+                    serde_json::json!({
+                    "status": "error",
+                    "message": "Organization not found",
+                });
+                return Err((StatusCode::NOT_FOUND, Json(error_response)));
+            }
+            Err(_e) => {
+                let error_response =
+                    serde_json::json!({
+                    "status": "error",
+                    "message": "Failed to fetch organization",
+                });
+                return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)));
+            }
+        }
+    }
+
+    let mut scopes: Vec<String> = req.scopes
+        .iter()
+        .map(|scope| scope.as_str().to_string())
+        .collect();
+    scopes.sort();
+    scopes.dedup();
+
+    let (api_key, key) = create_api_key(
+        CreateApiKey {
+            user_id: auth.user.id,
+            organization_id: req.organization_id,
+            name: name.to_string(),
+            scopes,
+            expires_at,
+        },
+        &ctx
+    ).await.map_err(|e| {
+        error!("Failed to create API key: {}", e);
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Failed to create API key",
+        });
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "api_key": api_key,
+        "key": key,
+    })
+        ),
+    ))
+}
+
+#[axum::debug_handler]
+async fn delete_api_key(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>,
+    Path(api_key_id): Path<Uuid>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    match revoke_api_key(&auth.user.id, &api_key_id, &ctx).await {
+        Ok(true) => Ok(Json(serde_json::json!({ "status": "ok" }))),
+        Ok(false) => {
+            let error_response =
+                serde_json::json!({
+                "status": "error",
+                "message": "API key not found",
+            });
+            Err((StatusCode::NOT_FOUND, Json(error_response)))
+        }
+        Err(_e) => {
+            let error_response =
+                serde_json::json!({
+                "status": "error",
+                "message": "Failed to revoke API key",
+            });
+            Err((StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)))
+        }
+    }
+}
+
+/// API keys can only be managed from a login session, a leaked key cannot mint new ones.
+pub fn router() -> Router {
+    Router::new()
+        .route("/", get(get_api_keys).post(post_api_key))
+        .route("/:api_key_id", delete(delete_api_key))
+        .layer(middleware::from_fn(auth_session))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{ Method, StatusCode };
+    use axum::Router;
+    use sqlx::{ query, query_scalar, PgPool };
+
+    use crate::auth::api_key::parse_api_key;
+    use crate::test_support::{ app, app_state, create_organization, request, send, sign_up };
+
+    async fn create_key(app: &Router, token: &str, body: serde_json::Value) -> serde_json::Value {
+        let create = request(Method::POST, "/api/api-keys", Some(token));
+        let (status, created) = send(app, create, Some(body)).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", created);
+        created
+    }
+
+    async fn me(app: &Router, key: &str) -> StatusCode {
+        send(app, request(Method::GET, "/api/users/me", Some(key)), None).await.0
+    }
+
+    #[sqlx::test]
+    async fn authenticates_with_the_key_within_its_scopes(db: PgPool) {
+        let ctx = app_state(db);
+        let app = app(&ctx);
+        let (token, _) = sign_up(&app, "jane@example.com").await;
+        let body = serde_json::json!({ "name": "CI", "scopes": ["user:read"] });
+        let created = create_key(&app, &token, body).await;
+        let key = created["key"].as_str().unwrap();
+        assert!(key.starts_with("tt_"));
+
+        assert_eq!(me(&app, key).await, StatusCode::OK);
+        let current = request(Method::GET, "/api/timers/current", Some(key));
+        assert_eq!(send(&app, current, None).await.0, StatusCode::FORBIDDEN);
+        // Keys cannot be used to manage keys.
+        let (status, _) = send(&app, request(Method::GET, "/api/api-keys", Some(key)), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Only a hash is stored and the key is never returned again.
+        let stored = query_scalar!("SELECT token_hash FROM api_keys").fetch_one(&ctx.db).await;
+        let stored = stored.unwrap();
+        assert_ne!(stored, key.as_bytes());
+        assert!(parse_api_key(key).unwrap().verify(&stored));
+        let list = request(Method::GET, "/api/api-keys", Some(&token));
+        let (_, listed) = send(&app, list, None).await;
+        assert_eq!(listed["api_keys"][0]["name"], "CI");
+        assert!(!listed.to_string().contains(key));
+    }
+
+    #[sqlx::test]
+    async fn rejects_expired_revoked_and_unknown_keys(db: PgPool) {
+        let ctx = app_state(db);
+        let app = app(&ctx);
+        let (token, _) = sign_up(&app, "jane@example.com").await;
+        let body = serde_json::json!({
+            "name": "Expiring",
+            "scopes": ["user:read"],
+            "expires_in_days": 1,
+        });
+        let expiring = create_key(&app, &token, body).await;
+        let body = serde_json::json!({ "name": "Revoked", "scopes": ["user:read"] });
+        let revoked = create_key(&app, &token, body).await;
+
+        query!(
+            "UPDATE api_keys SET expires_at = NOW() - INTERVAL '1 minute' WHERE name = $1",
+            "Expiring"
+        ).execute(&ctx.db).await.unwrap();
+        assert_eq!(me(&app, expiring["key"].as_str().unwrap()).await, StatusCode::UNAUTHORIZED);
+
+        let revoke = format!("/api/api-keys/{}", revoked["api_key"]["id"].as_str().unwrap());
+        let (status, _) = send(&app, request(Method::DELETE, &revoke, Some(&token)), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(me(&app, revoked["key"].as_str().unwrap()).await, StatusCode::UNAUTHORIZED);
+
+        assert_eq!(me(&app, "tt_not-a-key").await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn rejects_invalid_keys(db: PgPool) {
+        let app = app(&app_state(db));
+        let (token, _) = sign_up(&app, "jane@example.com").await;
+        let (john, _) = sign_up(&app, "john@example.com").await;
+        let organization_id = create_organization(&app, &john, "Globex").await;
+        let create = || request(Method::POST, "/api/api-keys", Some(&token));
+
+        let body = serde_json::json!({ "name": "CI", "scopes": [] });
+        assert_eq!(send(&app, create(), Some(body)).await.0, StatusCode::BAD_REQUEST);
+        let body = serde_json::json!({
+            "name": "CI",
+            "scopes": ["user:read"],
+            "expires_in_days": 0,
+        });
+        assert_eq!(send(&app, create(), Some(body)).await.0, StatusCode::BAD_REQUEST);
+        let body = serde_json::json!({
+            "name": "CI",
+            "scopes": ["user:read"],
+            "organization_id": organization_id,
+        });
+        assert_eq!(send(&app, create(), Some(body)).await.0, StatusCode::NOT_FOUND);
+    }
+}
@@ -1,16 +1,31 @@
+use axum::extract::Path;
 use axum::http::StatusCode;
-use axum::routing::post;
+use axum::routing::{ delete, get, post };
 use axum::{ middleware, Extension, Json };
-use axum::{ response::IntoResponse, Router };
-use log::debug;
+use axum::{ response::{ IntoResponse, Response }, Router };
+use log::{ debug, error };
 use pwhash::bcrypt;
 
 use crate::auth::access_token::encode_jwt;
-use crate::auth::authorization_middleware::auth;
-use crate::db::auth::create_refresh_token;
+use crate::auth::login_throttle::{ begin_login_attempt, too_many_attempts, ThrottleKey };
+use crate::auth::mfa::encode_mfa_token;
+use crate::auth::session::{ require_sso, start_session, SessionError };
+use crate::auth::authorization_middleware::{ auth_allow_unverified, auth_session, AuthExtension };
+use crate::auth::client_info::ClientInfo;
+use crate::auth::revocation::revoke_access_token;
+use crate::db::auth::{
+    get_sessions_by_user_id,
+    revoke_other_sessions,
+    revoke_refresh_token_family,
+    revoke_session,
+    rotate_refresh_token,
+    RotatedRefreshToken,
+};
+use crate::db::password_reset::{ create_password_reset_token, reset_password_with_token };
 use crate::db::user::get_user_by_email;
+use crate::mailer::{ app_url, Email };
 use crate::AppState;
-use sqlx::query;
+use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
 struct LoginUser {
@@ -21,64 +36,84 @@ struct LoginUser {
 #[axum::debug_handler]
 async fn login(
     ctx: Extension<AppState>,
+    client: ClientInfo,
     Json(req): Json<LoginUser>
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let user = get_user_by_email(&req.email, &ctx).await.map_err(|_e| {
-        debug!("User logged in: {}", _e);
-        bcrypt::verify("req.password", "&user.password");
-        let error_response =
-            serde_json::json!({
-            "status": "error",
-            "message": "Invalid email or password",
-        });
-        (StatusCode::UNAUTHORIZED, Json(error_response))
-    })?;
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let mut throttle_keys = vec![ThrottleKey::Account(req.email.clone())];
+    if let Some(ip_address) = &client.ip_address {
+        throttle_keys.push(ThrottleKey::Ip(ip_address.clone()));
+    }
 
-    debug!("User logged in: {}", user.email);
+    let attempt = match begin_login_attempt(throttle_keys, None, &ctx).await {
+        Ok(attempt) => attempt,
+        Err(retry_after) => {
+            return Ok(too_many_attempts(retry_after));
+        }
+    };
 
-    if bcrypt::verify(req.password, &user.password) {
-        let refresh_token = match create_refresh_token(user.id, &ctx).await {
-            Ok(token) => token,
-            Err(e) => {
-                let error_response =
-                    serde_json::json!({
+    let user = match get_user_by_email(&req.email, &ctx).await {
+        Ok(user) => user,
+        Err(_e) => {
+            debug!("User logged in: {}", _e);
+            bcrypt::verify("req.password", "&user.password");
+            attempt.failed(None, &ctx).await;
+            let error_response =
+                serde_json::json!({
                 "status": "error",
-                "message": format!("Failed to create refresh token: {}", e),
+                "message": "Invalid email or password",
             });
-                debug!("User logged in: {}", error_response);
+            return Err((StatusCode::UNAUTHORIZED, Json(error_response)));
+        }
+    };
 
-                return Ok((StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)));
-            }
-        };
+    debug!("User logged in: {}", user.email);
 
-        debug!("User logged in: {}", refresh_token);
+    if user.verify_password(&req.password) {
+        attempt.succeeded(&ctx).await;
 
-        encode_jwt(user.id.to_string(), user.email)
-            .map(|access_token| {
-                debug!("User logged in: {}", access_token);
-                let response =
-                    serde_json::json!({
-            "status": "success",
-            "accessToken": access_token,
-            "refreshToken": refresh_token,
+        // Checked before the second factor too, so blocked users are not asked for it in vain.
+        require_sso(&user.id, None, &ctx).await.map_err(|e| e.response())?;
+
+        if user.totp_enabled_at.is_some() {
+            return encode_mfa_token(&ctx.keys, &user.id, None)
+                .map(|mfa_token| {
+                    let response =
+                        serde_json::json!({
+            "status": "mfa_required",
+            "mfaToken": mfa_token,
         });
-                (StatusCode::OK, Json(response))
-            })
-            .map_err(|e| {
-                let error_response =
-                    serde_json::json!({
+                    (StatusCode::OK, Json(response)).into_response()
+                })
+                .map_err(|e| {
+                    let error_response =
+                        serde_json::json!({
         "status": "error",
         "message": format!("JWT error: {}", e),
     });
-                (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-            })
+                    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+                });
+        }
+
+        match start_session(&user.id, &user.email, &client, None, &ctx).await {
+            Ok(tokens) => {
+                let response =
+                    serde_json::json!({
+            "status": "success",
+            "accessToken": tokens.access_token,
+            "refreshToken": tokens.refresh_token,
+        });
+                Ok((StatusCode::OK, Json(response)).into_response())
+            }
+            Err(e) => Err(e.response()),
+        }
     } else {
+        attempt.failed(Some(user.id), &ctx).await;
         let error_response =
             serde_json::json!({
         "status": "error",
         "message": "Invalid email or password",
     });
-        Ok((StatusCode::UNAUTHORIZED, Json(error_response)))
+        Ok((StatusCode::UNAUTHORIZED, Json(error_response)).into_response())
     }
 }
 
@@ -91,18 +126,23 @@ struct GetAccessTokenRequest {
 #[axum::debug_handler]
 async fn get_access_token(
     ctx: Extension<AppState>,
+    client: ClientInfo,
     Json(req): Json<GetAccessTokenRequest>
 ) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let user = match
-        query!(
-            r#"SELECT users.id, email FROM refresh_tokens
-        JOIN users ON refresh_tokens.user_id = users.id
-         WHERE token = $1 AND expires_at > NOW()"#,
-            &req.refreshToken
-        ).fetch_one(&ctx.db).await
+    let (user_id, email, refresh_token) = match
+        rotate_refresh_token(&req.refreshToken, &client, &ctx).await
     {
-        Ok(user) => user,
-        Err(_e) => {
+        Ok(RotatedRefreshToken::Rotated { user_id, email, refresh_token }) =>
+            (user_id, email, refresh_token),
+        Ok(RotatedRefreshToken::Reused) => {
+            let error_response =
+                serde_json::json!({
+                    "status": "error",
+                    "message": "Refresh token reuse detected, all sessions have been revoked",
+                });
+            return Ok((StatusCode::UNAUTHORIZED, Json(error_response)));
+        }
+        Ok(RotatedRefreshToken::Invalid) => {
             let error_response =
                 serde_json::json!({
                     "status": "error",
@@ -110,14 +150,33 @@ async fn get_access_token(
                 });
             return Ok((StatusCode::UNAUTHORIZED, Json(error_response)));
         }
+        Err(_e) => {
+            let error_response =
+                serde_json::json!({
+                    "status": "error",
+                    "message": "Failed to rotate refresh token",
+                });
+            return Ok((StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)));
+        }
     };
 
-    match encode_jwt(user.id.to_string(), user.email) {
+    // Enforcement may have been turned on after the session started.
+    if let Err(e) = require_sso(&user_id, refresh_token.sso_organization_id.as_ref(), &ctx).await {
+        if matches!(e, SessionError::SsoRequired) {
+            if let Err(e) = revoke_session(&user_id, &refresh_token.session_id, &ctx).await {
+                error!("Failed to revoke session without single sign-on: {}", e);
+            }
+        }
+        return Ok(e.response());
+    }
+
+    match encode_jwt(&ctx.keys, user_id.to_string(), email, refresh_token.session_id.to_string()) {
         Ok(access_token) => {
             let response =
                 serde_json::json!({
                     "status": "success",
                     "accessToken": access_token,
+                    "refreshToken": refresh_token.token,
                 });
             Ok((StatusCode::OK, Json(response)))
         }
@@ -140,15 +199,25 @@ struct LogoutRequest {
 #[axum::debug_handler]
 async fn logout(
     ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>,
     Json(req): Json<LogoutRequest>
 ) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    query!("DELETE FROM refresh_tokens WHERE token = $1", &req.refresh_token)
-        .execute(&ctx.db).await
+    let session = auth.session()?;
+    revoke_access_token(&session.jti, &auth.user.id, session.exp, &ctx).await.map_err(|_e| {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Failed to revoke access token",
+        });
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })?;
+
+    revoke_refresh_token_family(&req.refresh_token, &ctx).await
         .map_err(|_e| {
             let error_response =
                 serde_json::json!({
             "status": "error",
-            "message": "Failed to delete refresh token",
+            "message": "Failed to revoke refresh token",
         });
             (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
         })?;
@@ -161,9 +230,374 @@ async fn logout(
     Ok((StatusCode::OK, Json(response)))
 }
 
+#[derive(serde::Deserialize)]
+struct ForgotPasswordRequest {
+    email: String,
+}
+
+/// Emails a password reset link. Always succeeds so it cannot be used to find registered emails.
+#[axum::debug_handler]
+async fn forgot_password(
+    ctx: Extension<AppState>,
+    Json(req): Json<ForgotPasswordRequest>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let response =
+        serde_json::json!({
+        "status": "success",
+        "message": "If the email is registered a reset link has been sent",
+    });
+
+    let Ok(user) = get_user_by_email(&req.email, &ctx).await else {
+        return Ok(Json(response));
+    };
+
+    // Failures are only logged, an error response would reveal that the account exists.
+    let token = match create_password_reset_token(&user.id, &ctx).await {
+        Ok(token) => token,
+        Err(e) => {
+            error!("Failed to create password reset token: {:?}", e);
+            return Ok(Json(response));
+        }
+    };
+
+    let link = format!("{}/reset-password?token={}", app_url(), token);
+    let email = Email {
+        to: user.email,
+        subject: "Reset your tick-tack password".to_string(),
+        body: format!(
+            "Use the link below to choose a new password, it is valid for one hour.\n\n{}",
+            link
+        ),
+    };
+    if let Err(e) = ctx.mailer.send(email).await {
+        error!("Failed to send password reset email: {}", e);
+    }
+
+    Ok(Json(response))
+}
+
+#[derive(serde::Deserialize)]
+struct ResetPasswordRequest {
+    token: String,
+    password: String,
+}
+
+#[axum::debug_handler]
+async fn reset_password(
+    ctx: Extension<AppState>,
+    Json(req): Json<ResetPasswordRequest>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let hashed_password = bcrypt::hash(req.password).map_err(|_e| {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Failed to hash password",
+        });
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })?;
+
+    match reset_password_with_token(&req.token, &hashed_password, &ctx).await {
+        Ok(Some(_)) =>
+            Ok(
+                Json(
+                    serde_json::json!({
+                "status": "success",
+                "message": "Password has been reset",
+            })
+                )
+            ),
+        Ok(None) => {
+            let error_response =
+                serde_json::json!({
+                "status": "error",
+                "message": "Invalid or expired reset token",
+            });
+            Err((StatusCode::BAD_REQUEST, Json(error_response)))
+        }
+        Err(_e) => {
+            let error_response =
+                serde_json::json!({
+                "status": "error",
+                "message": "Failed to reset password",
+            });
+            Err((StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)))
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn get_sessions(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let current_session = auth.session()?;
+    let sessions = get_sessions_by_user_id(&auth.user.id, &ctx).await.map_err(|_e| {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Failed to fetch sessions",
+        });
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })?;
+
+    let sessions: Vec<serde_json::Value> = sessions
+        .into_iter()
+        .map(|session| {
+            let current = session.id == current_session.session_id;
+            let mut session = serde_json::json!(session);
+            session["current"] = serde_json::json!(current);
+            session
+        })
+        .collect();
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "count": sessions.len(),
+        "sessions": sessions,
+    })
+        )
+    )
+}
+
+#[axum::debug_handler]
+async fn delete_session(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>,
+    Path(session_id): Path<Uuid>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    match revoke_session(&auth.user.id, &session_id, &ctx).await {
+        Ok(true) => Ok(Json(serde_json::json!({ "status": "ok" }))),
+        Ok(false) => {
+            let error_response =
+                serde_json::json!({
+                "status": "error",
+                "message": "Session not found",
+            });
+            Err((StatusCode::NOT_FOUND, Json(error_response)))
+        }
+        Err(_e) => {
+            let error_response =
+                serde_json::json!({
+                "status": "error",
+                "message": "Failed to revoke session",
+            });
+            Err((StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)))
+        }
+    }
+}
+
+/// Revokes every session except the one the request was made from.
+#[axum::debug_handler]
+async fn delete_other_sessions(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let session = auth.session()?;
+    let revoked = revoke_other_sessions(&auth.user.id, &session.session_id, &ctx.db).await.map_err(
+        |_e| {
+            let error_response =
+                serde_json::json!({
+            "status": "error",
+            "message": "Failed to revoke sessions",
+        });
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+        }
+    )?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "revoked": revoked,
+    })
+        )
+    )
+}
+
 pub fn router() -> Router {
     Router::new()
         .route("/login", post(login))
         .route("/access_token", post(get_access_token))
-        .route("/logout", post(logout).layer(middleware::from_fn(auth)))
+        .route("/logout", post(logout).layer(middleware::from_fn(auth_allow_unverified)))
+        .route("/password/forgot", post(forgot_password))
+        .route("/password/reset", post(reset_password))
+        .route("/sessions", get(get_sessions).layer(middleware::from_fn(auth_session)))
+        .route(
+            "/sessions/others",
+            delete(delete_other_sessions).layer(middleware::from_fn(auth_session))
+        )
+        .route(
+            "/sessions/:session_id",
+            delete(delete_session).layer(middleware::from_fn(auth_session))
+        )
+        .nest("/mfa", super::mfa::router())
+        .nest("/oidc", super::oidc::router())
+        .nest("/sso", super::sso::router())
+        .nest("/passkeys", super::passkey::router())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{ header, Method, StatusCode };
+    use axum::Router;
+    use sqlx::{ query, PgPool };
+
+    use crate::test_support::{
+        app,
+        app_state,
+        app_state_with_mailer,
+        log_in,
+        log_in_with,
+        refresh,
+        request,
+        send,
+        sign_up,
+        PASSWORD,
+    };
+
+    async fn sessions(app: &Router, token: &str) -> serde_json::Value {
+        let (status, sessions) = send(
+            app,
+            request(Method::GET, "/api/auth/sessions", Some(token)),
+            None
+        ).await;
+        assert_eq!(status, StatusCode::OK, "{}", sessions);
+        sessions
+    }
+
+    /// The id of the session the access token belongs to.
+    async fn session_id(app: &Router, token: &str) -> String {
+        let sessions = sessions(app, token).await;
+        let current = sessions["sessions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|session| session["current"] == true)
+            .unwrap();
+        current["id"].as_str().unwrap().to_string()
+    }
+
+    async fn reset_password(app: &Router, token: &str, password: &str) -> StatusCode {
+        let body = serde_json::json!({ "token": token, "password": password });
+        send(app, request(Method::POST, "/api/auth/password/reset", None), Some(body)).await.0
+    }
+
+    async fn forgot_password(app: &Router, email: &str) {
+        let body = serde_json::json!({ "email": email });
+        let forgot = request(Method::POST, "/api/auth/password/forgot", None);
+        assert_eq!(send(app, forgot, Some(body)).await.0, StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn lists_the_sessions_of_the_user(db: PgPool) {
+        let app = app(&app_state(db));
+        sign_up(&app, "jane@example.com").await;
+        sign_up(&app, "john@example.com").await;
+        let login = request(Method::POST, "/api/auth/login", None).header(
+            header::USER_AGENT,
+            "Phone"
+        );
+        let body = serde_json::json!({ "email": "jane@example.com", "password": PASSWORD });
+        let (_, phone) = send(&app, login, Some(body)).await;
+
+        let sessions = sessions(&app, phone["accessToken"].as_str().unwrap()).await;
+
+        assert_eq!(sessions["count"], 2);
+        let sessions = sessions["sessions"].as_array().unwrap();
+        let current: Vec<_> = sessions
+            .iter()
+            .filter(|session| session["current"] == true)
+            .collect();
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0]["user_agent"], "Phone");
+    }
+
+    #[sqlx::test]
+    async fn revokes_sessions_of_the_user(db: PgPool) {
+        let app = app(&app_state(db));
+        let (laptop, laptop_refresh) = sign_up(&app, "jane@example.com").await;
+        let (phone, phone_refresh) = log_in(&app, "jane@example.com").await;
+        let (_, tablet_refresh) = log_in(&app, "jane@example.com").await;
+        let (john, _) = sign_up(&app, "john@example.com").await;
+
+        let revoke = format!("/api/auth/sessions/{}", session_id(&app, &laptop).await);
+        let (status, _) = send(&app, request(Method::DELETE, &revoke, Some(&phone)), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(refresh(&app, &laptop_refresh).await, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(&app, request(Method::DELETE, &revoke, Some(&phone)), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Sessions of other users look like they do not exist.
+        let revoke = format!("/api/auth/sessions/{}", session_id(&app, &phone).await);
+        let (status, _) = send(&app, request(Method::DELETE, &revoke, Some(&john)), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let others = request(Method::DELETE, "/api/auth/sessions/others", Some(&phone));
+        let (status, revoked) = send(&app, others, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(revoked["revoked"], 1);
+        assert_eq!(refresh(&app, &tablet_refresh).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(refresh(&app, &phone_refresh).await, StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn logging_out_revokes_the_access_token(db: PgPool) {
+        // Instances with their own cache have to find the revocation in the database.
+        let other_instance = app(&app_state(db.clone()));
+        let app = app(&app_state(db));
+        let (token, refresh_token) = sign_up(&app, "jane@example.com").await;
+        let (other_token, _) = log_in(&app, "jane@example.com").await;
+        let me = |token: &str| request(Method::GET, "/api/users/me", Some(token));
+        assert_eq!(send(&app, me(&token), None).await.0, StatusCode::OK);
+
+        let body = serde_json::json!({ "refresh_token": refresh_token });
+        let logout = request(Method::POST, "/api/auth/logout", Some(&token));
+        let (status, _) = send(&app, logout, Some(body)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        assert_eq!(send(&app, me(&token), None).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(refresh(&app, &refresh_token).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(send(&app, me(&other_token), None).await.0, StatusCode::OK);
+        let (status, _) = send(&other_instance, me(&token), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn resets_the_password_once_with_the_emailed_token(db: PgPool) {
+        let (ctx, mailer) = app_state_with_mailer(db);
+        let app = app(&ctx);
+        let (_, refresh_token) = sign_up(&app, "jane@example.com").await;
+
+        forgot_password(&app, "jane@example.com").await;
+        let token = mailer.last_token("jane@example.com").unwrap();
+        assert_eq!(reset_password(&app, &token, "new password").await, StatusCode::OK);
+
+        assert_eq!(log_in_with(&app, "jane@example.com", "new password").await, StatusCode::OK);
+        let old_password = log_in_with(&app, "jane@example.com", PASSWORD).await;
+        assert_eq!(old_password, StatusCode::UNAUTHORIZED);
+        assert_eq!(refresh(&app, &refresh_token).await, StatusCode::UNAUTHORIZED);
+        let reused = reset_password(&app, &token, "another password").await;
+        assert_eq!(reused, StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn rejects_expired_and_unknown_reset_tokens(db: PgPool) {
+        let (ctx, mailer) = app_state_with_mailer(db);
+        let app = app(&ctx);
+        sign_up(&app, "jane@example.com").await;
+
+        forgot_password(&app, "nobody@example.com").await;
+        assert!(mailer.last_token("nobody@example.com").is_none());
+        forgot_password(&app, "jane@example.com").await;
+        let token = mailer.last_token("jane@example.com").unwrap();
+        query!("UPDATE password_reset_tokens SET expires_at = NOW() - INTERVAL '1 minute'")
+            .execute(&ctx.db).await.unwrap();
+
+        let expired = reset_password(&app, &token, "new password").await;
+        assert_eq!(expired, StatusCode::BAD_REQUEST);
+        let unknown = reset_password(&app, "not-a-token", "new password").await;
+        assert_eq!(unknown, StatusCode::BAD_REQUEST);
+        assert_eq!(log_in_with(&app, "jane@example.com", PASSWORD).await, StatusCode::OK);
+    }
 }
@@ -0,0 +1,179 @@
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{ middleware, Extension, Json };
+use axum::{ response::IntoResponse, Router };
+use log::error;
+use uuid::Uuid;
+
+use crate::auth::authorization_middleware::auth;
+use crate::auth::organization_access::{ OrganizationMember, Permission };
+use crate::db::client::{ create_client, delete_client, get_client, get_clients, rename_client };
+use crate::AppState;
+
+const MAX_NAME_LENGTH: usize = 255;
+
+fn error(status: StatusCode, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": message,
+    });
+    (status, Json(error_response))
+}
+
+fn client_not_found() -> (StatusCode, Json<serde_json::Value>) {
+    error(StatusCode::NOT_FOUND, "Client not found")
+}
+
+fn validate_name(name: &str) -> Result<&str, (StatusCode, Json<serde_json::Value>)> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err(error(StatusCode::BAD_REQUEST, "Invalid client name"));
+    }
+    Ok(name)
+}
+
+fn client_error(e: sqlx::Error, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    match e.as_database_error().and_then(|db_error| db_error.code()).as_deref() {
+        Some("23505") => error(StatusCode::CONFLICT, "A client with this name already exists"),
+        Some("23503") => error(StatusCode::CONFLICT, "Clients with projects cannot be deleted"),
+        _ => {
+            error!("{}: {:?}", message, e);
+            error(StatusCode::INTERNAL_SERVER_ERROR, message)
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn get_clients_handler(
+    ctx: Extension<AppState>,
+    member: OrganizationMember
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::OrganizationRead)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let clients = get_clients(&member.organization_id, &mut *tx).await.map_err(|_e|
+        error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch clients")
+    )?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "clients": clients,
+    })
+        )
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct ClientName {
+    name: String,
+}
+
+#[axum::debug_handler]
+async fn post_client(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Json(req): Json<ClientName>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::ProjectsManage)?;
+    let name = validate_name(&req.name)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let client = create_client(&member.organization_id, name, &mut *tx).await.map_err(|e|
+        client_error(e, "Failed to create client")
+    )?;
+    tx.commit().await.map_err(|e| client_error(e, "Failed to create client"))?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "client": client,
+    })
+        ),
+    ))
+}
+
+#[axum::debug_handler]
+async fn get_client_handler(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, client_id)): Path<(String, Uuid)>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::OrganizationRead)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let client = get_client(&member.organization_id, &client_id, &mut *tx).await
+        .map_err(|_e| error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch client"))?
+        .ok_or_else(client_not_found)?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "client": client,
+    })
+        )
+    )
+}
+
+#[axum::debug_handler]
+async fn patch_client(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, client_id)): Path<(String, Uuid)>,
+    Json(req): Json<ClientName>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::ProjectsManage)?;
+    let name = validate_name(&req.name)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let client = rename_client(&member.organization_id, &client_id, name, &mut *tx).await
+        .map_err(|e| client_error(e, "Failed to update client"))?
+        .ok_or_else(client_not_found)?;
+    tx.commit().await.map_err(|e| client_error(e, "Failed to update client"))?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "client": client,
+    })
+        )
+    )
+}
+
+/// Deletes a client together with its rates, its projects have to be moved or deleted first.
+#[axum::debug_handler]
+async fn delete_client_handler(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, client_id)): Path<(String, Uuid)>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::ProjectsManage)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    match delete_client(&member.organization_id, &client_id, &mut *tx).await {
+        Ok(true) => {
+            tx.commit().await.map_err(|e| client_error(e, "Failed to delete client"))?;
+            Ok(Json(serde_json::json!({ "status": "ok" })))
+        }
+        Ok(false) => Err(client_not_found()),
+        Err(e) => Err(client_error(e, "Failed to delete client")),
+    }
+}
+
+/// Nested under `/organizations/:organization_id/clients`.
+pub fn router() -> Router {
+    Router::new()
+        .route("/", get(get_clients_handler).post(post_client))
+        .route(
+            "/:client_id",
+            get(get_client_handler).patch(patch_client).delete(delete_client_handler)
+        )
+        .layer(middleware::from_fn(auth))
+}
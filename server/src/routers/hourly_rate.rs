@@ -0,0 +1,163 @@
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::routing::{ delete, get };
+use axum::{ middleware, Extension, Json };
+use axum::{ response::IntoResponse, Router };
+use chrono::{ DateTime, Utc };
+use log::error;
+use uuid::Uuid;
+
+use crate::auth::authorization_middleware::auth;
+use crate::auth::organization_access::{ OrganizationMember, Permission };
+use crate::db::hourly_rate::{
+    create_hourly_rate,
+    delete_hourly_rate,
+    get_hourly_rates,
+    HourlyRateFields,
+};
+use crate::db::role::get_membership;
+use crate::AppState;
+
+fn error(status: StatusCode, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": message,
+    });
+    (status, Json(error_response))
+}
+
+fn rate_error(e: sqlx::Error, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    match e.as_database_error().and_then(|db_error| db_error.code()).as_deref() {
+        Some("23505") =>
+            error(StatusCode::CONFLICT, "A rate for this target already starts at that time"),
+        Some("23503") => error(StatusCode::NOT_FOUND, "Client or project not found"),
+        _ => {
+            error!("{}: {:?}", message, e);
+            error(StatusCode::INTERNAL_SERVER_ERROR, message)
+        }
+    }
+}
+
+/// Currencies are ISO 4217 codes like `EUR`.
+fn validate_currency(currency: &str) -> Result<String, (StatusCode, Json<serde_json::Value>)> {
+    let currency = currency.trim().to_ascii_uppercase();
+    if currency.len() != 3 || !currency.bytes().all(|byte| byte.is_ascii_uppercase()) {
+        return Err(error(StatusCode::BAD_REQUEST, "Invalid currency"));
+    }
+    Ok(currency)
+}
+
+/// Every rate of the organization, the most recent first. Rates are never changed, a new rate
+/// with a later `effective_from` takes over from the previous one.
+#[axum::debug_handler]
+async fn get_rates_handler(
+    ctx: Extension<AppState>,
+    member: OrganizationMember
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::RatesManage)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let rates = get_hourly_rates(&member.organization_id, &mut *tx).await.map_err(|_e|
+        error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch rates")
+    )?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "rates": rates,
+    })
+        )
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct NewRate {
+    client_id: Option<Uuid>,
+    project_id: Option<Uuid>,
+    user_id: Option<Uuid>,
+    /// In the minor unit of the currency, like cents.
+    amount: i64,
+    currency: String,
+    effective_from: Option<DateTime<Utc>>,
+}
+
+/// Sets the rate of the organization, a client, a project, a member or a member on a project,
+/// starting now unless `effective_from` says otherwise.
+#[axum::debug_handler]
+async fn post_rate(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Json(req): Json<NewRate>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::RatesManage)?;
+    if req.amount < 0 {
+        return Err(error(StatusCode::BAD_REQUEST, "Invalid amount"));
+    }
+    if req.client_id.is_some() && (req.project_id.is_some() || req.user_id.is_some()) {
+        return Err(
+            error(StatusCode::BAD_REQUEST, "Client rates cannot be limited to a project or member")
+        );
+    }
+    let rate = HourlyRateFields {
+        client_id: req.client_id,
+        project_id: req.project_id,
+        user_id: req.user_id,
+        amount: req.amount,
+        currency: validate_currency(&req.currency)?,
+        effective_from: req.effective_from.map_or_else(
+            || Utc::now().naive_utc(),
+            |effective_from| effective_from.naive_utc()
+        ),
+    };
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    if let Some(user_id) = &rate.user_id {
+        get_membership(user_id, &member.organization_id, &mut *tx).await
+            .map_err(|_e| error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch member"))?
+            .ok_or_else(|| error(StatusCode::NOT_FOUND, "Member not found"))?;
+    }
+    let rate = create_hourly_rate(&member.organization_id, &rate, &mut *tx).await.map_err(|e|
+        rate_error(e, "Failed to create rate")
+    )?;
+    tx.commit().await.map_err(|e| rate_error(e, "Failed to create rate"))?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "rate": rate,
+    })
+        ),
+    ))
+}
+
+/// Deletes a rate, entries it applied to fall back to the previous or a less specific one.
+#[axum::debug_handler]
+async fn delete_rate_handler(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, rate_id)): Path<(String, Uuid)>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::RatesManage)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    match delete_hourly_rate(&member.organization_id, &rate_id, &mut *tx).await {
+        Ok(true) => {
+            tx.commit().await.map_err(|e| rate_error(e, "Failed to delete rate"))?;
+            Ok(Json(serde_json::json!({ "status": "ok" })))
+        }
+        Ok(false) => Err(error(StatusCode::NOT_FOUND, "Rate not found")),
+        Err(e) => Err(rate_error(e, "Failed to delete rate")),
+    }
+}
+
+/// Nested under `/organizations/:organization_id/rates`.
+pub fn router() -> Router {
+    Router::new()
+        .route("/", get(get_rates_handler).post(post_rate))
+        .route("/:rate_id", delete(delete_rate_handler))
+        .layer(middleware::from_fn(auth))
+}
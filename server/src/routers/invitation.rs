@@ -0,0 +1,367 @@
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::routing::{ delete, get, post };
+use axum::{ middleware, Extension, Json };
+use axum::{ response::IntoResponse, Router };
+use log::error;
+use uuid::Uuid;
+
+use crate::auth::authorization_middleware::{ auth, auth_allow_unverified, AuthExtension };
+use crate::auth::organization_access::{ OrganizationMember, Permission, Role };
+use crate::db::invitation::{
+    accept_invitation,
+    create_invitation,
+    decline_invitation,
+    get_pending_invitation_by_token,
+    get_pending_invitations,
+    revoke_invitation,
+    PendingInvitation,
+};
+use crate::db::organization::{ get_organization_by_id, is_email_in_organization };
+use crate::mailer::{ app_url, Email };
+use crate::AppState;
+
+fn internal_error(message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": message,
+    });
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+fn not_found(message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": message,
+    });
+    (StatusCode::NOT_FOUND, Json(error_response))
+}
+
+fn invalid_invitation() -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": "Invalid or expired invitation",
+    });
+    (StatusCode::BAD_REQUEST, Json(error_response))
+}
+
+#[axum::debug_handler]
+async fn get_invitations(
+    ctx: Extension<AppState>,
+    member: OrganizationMember
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::MembersManage)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let invitations = get_pending_invitations(&member.organization_id, &mut *tx).await.map_err(|_e|
+        internal_error("Failed to fetch invitations")
+    )?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "invitations": invitations,
+    })
+        )
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct NewInvitation {
+    email: String,
+    role: Role,
+}
+
+/// Invites a colleague by email. The link in the email works for existing accounts as well as
+/// for registering a new one.
+#[axum::debug_handler]
+async fn post_invitation(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Json(req): Json<NewInvitation>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::MembersManage)?;
+    member.require_grantable(req.role.permissions())?;
+    let organization_id = member.organization_id;
+
+    let email = req.email.trim();
+    if !email.contains('@') || req.role == Role::Owner {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "A valid email and one of the roles admin, member or viewer are required",
+        });
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let organization = get_organization_by_id(&organization_id, &mut *tx).await
+        .map_err(|_e| internal_error("Failed to fetch organization"))?
+        .ok_or_else(|| not_found("Organization not found"))?;
+
+    let is_member = is_email_in_organization(email, &organization_id, &mut *tx).await.map_err(
+        |_e| internal_error("Failed to create invitation")
+    )?;
+    if is_member {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "User is already a member of the organization",
+        });
+        return Err((StatusCode::CONFLICT, Json(error_response)));
+    }
+
+    let (invitation, token) = create_invitation(
+        &organization_id,
+        email,
+        req.role.as_str(),
+        &member.auth.user.id,
+        &mut tx
+    ).await.map_err(|e| {
+        error!("Failed to create invitation: {:?}", e);
+        internal_error("Failed to create invitation")
+    })?;
+    tx.commit().await.map_err(|_e| internal_error("Failed to create invitation"))?;
+
+    let link = format!("{}/invitations/accept?token={}", app_url(), token);
+    let sent = ctx.mailer.send(Email {
+        to: invitation.email.clone(),
+        subject: format!("You have been invited to {} on tick-tack", organization.name),
+        body: format!(
+            "{} invited you to join {} as {}. Use the link below to accept or decline, \
+            it expires in 7 days.\n\n{}",
+            member.auth.user.email,
+            organization.name,
+            invitation.role,
+            link
+        ),
+    }).await;
+    if let Err(e) = sent {
+        error!("Failed to send invitation email: {}", e);
+        return Err(internal_error("Failed to send invitation email"));
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "invitation": invitation,
+    })
+        ),
+    ))
+}
+
+#[axum::debug_handler]
+async fn delete_invitation(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, invitation_id)): Path<(String, Uuid)>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::MembersManage)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    match revoke_invitation(&member.organization_id, &invitation_id, &mut *tx).await {
+        Ok(true) => {
+            tx.commit().await.map_err(|_e| internal_error("Failed to revoke invitation"))?;
+            Ok(Json(serde_json::json!({ "status": "ok" })))
+        }
+        Ok(false) => Err(not_found("Invitation not found")),
+        Err(_e) => Err(internal_error("Failed to revoke invitation")),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct InvitationTokenRequest {
+    token: String,
+}
+
+async fn find_invitation(
+    token: &str,
+    ctx: &AppState
+) -> Result<PendingInvitation, (StatusCode, Json<serde_json::Value>)> {
+    get_pending_invitation_by_token(token, ctx).await
+        .map_err(|_e| internal_error("Failed to fetch invitation"))?
+        .ok_or_else(invalid_invitation)
+}
+
+/// Looks up the pending invitation behind the token and checks that it was sent to `email`.
+pub async fn find_invitation_for_email(
+    token: &str,
+    email: &str,
+    ctx: &AppState
+) -> Result<PendingInvitation, (StatusCode, Json<serde_json::Value>)> {
+    let invitation = find_invitation(token, ctx).await?;
+    if !invitation.email.eq_ignore_ascii_case(email) {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "The invitation was sent to a different email",
+        });
+        return Err((StatusCode::FORBIDDEN, Json(error_response)));
+    }
+    Ok(invitation)
+}
+
+/// Adds the user to the organization of the invitation. Returns the organization id.
+pub async fn accept(
+    invitation: &PendingInvitation,
+    user_id: &Uuid,
+    ctx: &AppState
+) -> Result<Uuid, (StatusCode, Json<serde_json::Value>)> {
+    match accept_invitation(&invitation.id, user_id, ctx).await {
+        Ok(true) => Ok(invitation.organization_id),
+        Ok(false) => Err(invalid_invitation()),
+        Err(sqlx::Error::Database(db_err)) if db_err.code().as_deref() == Some("23505") => {
+            let error_response =
+                serde_json::json!({
+                "status": "error",
+                "message": "User is already a member of the organization",
+            });
+            Err((StatusCode::CONFLICT, Json(error_response)))
+        }
+        Err(e) => {
+            error!("Failed to accept invitation: {:?}", e);
+            Err(internal_error("Failed to accept invitation"))
+        }
+    }
+}
+
+/// Accepts an invitation for the logged in user, whose email has to be the invited one.
+#[axum::debug_handler]
+async fn accept_invitation_handler(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>,
+    Json(req): Json<InvitationTokenRequest>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    auth.session()?;
+    let invitation = find_invitation_for_email(&req.token, &auth.user.email, &ctx).await?;
+    let organization_id = accept(&invitation, &auth.user.id, &ctx).await?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "organization_id": organization_id,
+        "role": invitation.role,
+    })
+        )
+    )
+}
+
+/// Declines an invitation. Holding the token is enough, so the invitee needs no account.
+#[axum::debug_handler]
+async fn decline_invitation_handler(
+    ctx: Extension<AppState>,
+    Json(req): Json<InvitationTokenRequest>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let invitation = find_invitation(&req.token, &ctx).await?;
+
+    match decline_invitation(&invitation.id, &ctx).await {
+        Ok(true) => Ok(Json(serde_json::json!({ "status": "ok" }))),
+        Ok(false) => Err(invalid_invitation()),
+        Err(_e) => Err(internal_error("Failed to decline invitation")),
+    }
+}
+
+/// Accept and decline, nested under `/organizations/invitations`.
+pub fn router() -> Router {
+    Router::new()
+        .route(
+            "/accept",
+            post(accept_invitation_handler).layer(middleware::from_fn(auth_allow_unverified))
+        )
+        .route("/decline", post(decline_invitation_handler))
+}
+
+/// Invitation management, nested under `/organizations/:organization_id/invitations`.
+pub fn organization_router() -> Router {
+    Router::new()
+        .route("/", get(get_invitations).post(post_invitation))
+        .route("/:invitation_id", delete(delete_invitation))
+        .layer(middleware::from_fn(auth))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{ Method, StatusCode };
+    use axum::Router;
+    use sqlx::PgPool;
+
+    use crate::test_support::{
+        app,
+        app_state_with_mailer,
+        create_organization,
+        request,
+        send,
+        sign_up,
+        PASSWORD,
+    };
+
+    async fn invite(app: &Router, token: &str, organization_id: &str, email: &str) {
+        let uri = format!("/api/organizations/{}/invitations", organization_id);
+        let body = serde_json::json!({ "email": email, "role": "member" });
+        let (status, invitation) = send(app, request(Method::POST, &uri, Some(token)), Some(body))
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{}", invitation);
+    }
+
+    async fn accept(app: &Router, token: &str, invitation_token: &str) -> StatusCode {
+        let accept = request(Method::POST, "/api/organizations/invitations/accept", Some(token));
+        let body = serde_json::json!({ "token": invitation_token });
+        send(app, accept, Some(body)).await.0
+    }
+
+    async fn members(app: &Router, token: &str, organization_id: &str) -> StatusCode {
+        let uri = format!("/api/organizations/{}/members", organization_id);
+        send(app, request(Method::GET, &uri, Some(token)), None).await.0
+    }
+
+    #[sqlx::test]
+    async fn accepts_the_invitation_sent_to_the_email(db: PgPool) {
+        let (ctx, mailer) = app_state_with_mailer(db);
+        let app = app(&ctx);
+        let (owner, _) = sign_up(&app, "jane@example.com").await;
+        let organization_id = create_organization(&app, &owner, "Acme").await;
+        invite(&app, &owner, &organization_id, "bob@example.com").await;
+        let invitation_token = mailer.last_token("bob@example.com").unwrap();
+
+        let (bob, _) = sign_up(&app, "bob@example.com").await;
+        assert_eq!(members(&app, &bob, &organization_id).await, StatusCode::NOT_FOUND);
+        assert_eq!(accept(&app, &bob, &invitation_token).await, StatusCode::OK);
+        assert_eq!(members(&app, &bob, &organization_id).await, StatusCode::OK);
+
+        // The invitation is used up.
+        assert_eq!(accept(&app, &bob, &invitation_token).await, StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn rejects_invitations_sent_to_a_different_email(db: PgPool) {
+        let (ctx, mailer) = app_state_with_mailer(db);
+        let app = app(&ctx);
+        let (owner, _) = sign_up(&app, "jane@example.com").await;
+        let organization_id = create_organization(&app, &owner, "Acme").await;
+        invite(&app, &owner, &organization_id, "bob@example.com").await;
+        let invitation_token = mailer.last_token("bob@example.com").unwrap();
+
+        let (eve, _) = sign_up(&app, "eve@example.com").await;
+        assert_eq!(accept(&app, &eve, &invitation_token).await, StatusCode::FORBIDDEN);
+        assert_eq!(members(&app, &eve, &organization_id).await, StatusCode::NOT_FOUND);
+
+        let body = serde_json::json!({
+            "email": "mallory@example.com",
+            "password": PASSWORD,
+            "invitation_token": invitation_token,
+        });
+        let register = request(Method::POST, "/api/users", None);
+        assert_eq!(send(&app, register, Some(body)).await.0, StatusCode::FORBIDDEN);
+
+        // The invitation is still pending for the invited email.
+        let (bob, _) = sign_up(&app, "bob@example.com").await;
+        assert_eq!(accept(&app, &bob, &invitation_token).await, StatusCode::OK);
+    }
+}
@@ -0,0 +1,186 @@
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::routing::{ delete, get, put };
+use axum::{ middleware, Extension, Json };
+use axum::{ response::IntoResponse, Router };
+use log::error;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::authorization_middleware::auth;
+use crate::auth::organization_access::{
+    parse_permissions,
+    MemberRole,
+    OrganizationMember,
+    Permission,
+    Role,
+};
+use crate::db::organization::{ get_members, remove_member };
+use crate::db::role::{ get_custom_role, get_membership, set_member_role };
+use crate::AppState;
+
+fn error(status: StatusCode, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": message,
+    });
+    (status, Json(error_response))
+}
+
+#[axum::debug_handler]
+async fn get_members_handler(
+    ctx: Extension<AppState>,
+    member: OrganizationMember
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::OrganizationRead)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let members = get_members(&member.organization_id, &mut *tx).await.map_err(|_e|
+        error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch members")
+    )?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "members": members,
+    })
+        )
+    )
+}
+
+/// Checks that the caller may manage another member. The owner can never be managed, and
+/// nobody can manage members with permissions they lack themselves.
+async fn require_manageable_member(
+    member: &OrganizationMember,
+    user_id: &Uuid,
+    tx: &mut PgConnection
+) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    let membership = get_membership(user_id, &member.organization_id, tx).await
+        .map_err(|_e| error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch member"))?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "Member not found"))?;
+    let team_permissions = parse_permissions(&membership.team_permissions);
+    let target = MemberRole::from_membership(membership).ok_or_else(||
+        error(StatusCode::NOT_FOUND, "Member not found")
+    )?;
+    if target.is_owner() {
+        return Err(error(StatusCode::BAD_REQUEST, "The owner cannot be changed or removed"));
+    }
+    let mut permissions = target.permissions().iter().chain(&team_permissions);
+    if !permissions.all(|permission| member.has_permission(*permission)) {
+        return Err(
+            error(StatusCode::FORBIDDEN, "Cannot manage a member with more permissions")
+        );
+    }
+    Ok(())
+}
+
+/// Removes a member, which also revokes the member's API keys of the organization.
+#[axum::debug_handler]
+async fn delete_member(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, user_id)): Path<(String, Uuid)>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::MembersManage)?;
+    let mut tx = member.begin_tenant(&ctx).await?;
+    require_manageable_member(&member, &user_id, &mut tx).await?;
+
+    let removed = match remove_member(&user_id, &member.organization_id, &mut tx).await {
+        Ok(removed) => tx.commit().await.map(|_| removed),
+        Err(e) => Err(e),
+    };
+    match removed {
+        Ok(true) => Ok(Json(serde_json::json!({ "status": "ok" }))),
+        Ok(false) => Err(error(StatusCode::NOT_FOUND, "Member not found")),
+        Err(e) => {
+            error!("Failed to remove member: {:?}", e);
+            Err(error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to remove member"))
+        }
+    }
+}
+
+/// Either a built-in role or the id of a custom role of the organization.
+#[derive(serde::Deserialize)]
+struct RoleAssignment {
+    role: Option<Role>,
+    custom_role_id: Option<Uuid>,
+}
+
+/// Changes the role of a member, only to roles within the caller's own permissions. Ownership is
+/// never assigned here but transferred.
+#[axum::debug_handler]
+async fn put_member_role(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, user_id)): Path<(String, Uuid)>,
+    Json(req): Json<RoleAssignment>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::MembersManage)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    require_manageable_member(&member, &user_id, &mut tx).await?;
+
+    let role = match (req.role, req.custom_role_id) {
+        (Some(Role::Owner), None) => {
+            return Err(error(StatusCode::BAD_REQUEST, "Ownership cannot be assigned"));
+        }
+        (Some(role), None) => MemberRole::BuiltIn(role),
+        (None, Some(custom_role_id)) => {
+            let custom_role = get_custom_role(&member.organization_id, &custom_role_id, &mut *tx)
+                .await
+                .map_err(|_e| error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch role"))?
+                .ok_or_else(|| error(StatusCode::NOT_FOUND, "Role not found"))?;
+            MemberRole::Custom {
+                permissions: parse_permissions(&custom_role.permissions),
+                name: custom_role.name,
+            }
+        }
+        _ => {
+            return Err(
+                error(StatusCode::BAD_REQUEST, "Either role or custom_role_id has to be set")
+            );
+        }
+    };
+    member.require_grantable(role.permissions())?;
+
+    let updated = set_member_role(
+        &user_id,
+        &member.organization_id,
+        req.role.map(|role| role.as_str()),
+        req.custom_role_id.as_ref(),
+        &mut *tx
+    ).await;
+    let updated = match updated {
+        Ok(updated) => tx.commit().await.map(|_| updated),
+        Err(e) => Err(e),
+    };
+    match updated {
+        Ok(true) =>
+            Ok(
+                Json(
+                    serde_json::json!({
+                "status": "ok",
+                "user_id": user_id,
+                "role": role.name(),
+                "permissions": role.permissions(),
+            })
+                )
+            ),
+        Ok(false) => Err(error(StatusCode::NOT_FOUND, "Member not found")),
+        Err(e) => {
+            error!("Failed to change member role: {:?}", e);
+            Err(error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to change member role"))
+        }
+    }
+}
+
+/// Nested under `/organizations/:organization_id/members`.
+pub fn router() -> Router {
+    Router::new()
+        .route("/", get(get_members_handler))
+        .route("/:user_id", delete(delete_member))
+        .route("/:user_id/role", put(put_member_role))
+        .layer(middleware::from_fn(auth))
+}
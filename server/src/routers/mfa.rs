@@ -0,0 +1,345 @@
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::{ middleware, Extension, Json };
+use axum::{ response::{ IntoResponse, Response }, Router };
+
+use crate::auth::authorization_middleware::{ auth_session, AuthExtension };
+use crate::auth::client_info::ClientInfo;
+use crate::auth::login_throttle::{ begin_login_attempt, too_many_attempts, ThrottleKey };
+use crate::auth::mfa::{
+    decode_mfa_token,
+    generate_recovery_codes,
+    generate_totp_secret,
+    normalize_recovery_code,
+    totp_provisioning_uri,
+    verify_second_factor,
+    verify_totp_code,
+};
+use crate::auth::session::start_session;
+use crate::db::mfa::{
+    disable_totp,
+    enable_totp,
+    get_totp_state,
+    set_pending_totp_secret,
+};
+use crate::db::user::get_user_by_id;
+use crate::AppState;
+
+fn internal_error(message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": message,
+    });
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+fn invalid_code() -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": "Invalid code",
+    });
+    (StatusCode::UNAUTHORIZED, Json(error_response))
+}
+
+/// Starts enrolment by generating a new secret. It only takes effect once confirmed.
+#[axum::debug_handler]
+async fn enroll_totp(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    if auth.user.totp_enabled_at.is_some() {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Two-factor authentication is already enabled",
+        });
+        return Err((StatusCode::CONFLICT, Json(error_response)));
+    }
+
+    let secret = generate_totp_secret();
+    let provisioning_uri = totp_provisioning_uri(&secret, &auth.user.email).ok_or_else(||
+        internal_error("Failed to create TOTP secret")
+    )?;
+
+    set_pending_totp_secret(&auth.user.id, &secret, &ctx).await.map_err(|_e|
+        internal_error("Failed to store TOTP secret")
+    )?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "success",
+        "secret": secret,
+        "provisioningUri": provisioning_uri,
+    })
+        )
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct ConfirmTotpRequest {
+    code: String,
+}
+
+/// Enables TOTP once the first code from the authenticator app checks out and returns the
+/// recovery codes, which are only ever shown here.
+#[axum::debug_handler]
+async fn confirm_totp(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>,
+    Json(req): Json<ConfirmTotpRequest>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let state = get_totp_state(&auth.user.id, &ctx).await.map_err(|_e|
+        internal_error("Failed to fetch TOTP state")
+    )?;
+
+    let (Some(secret), None) = (state.secret, state.enabled_at) else {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "No pending two-factor enrolment",
+        });
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    };
+
+    let step = verify_totp_code(&secret, &auth.user.email, req.code.trim()).ok_or_else(
+        invalid_code
+    )?;
+
+    let recovery_codes = generate_recovery_codes();
+    let normalized: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| normalize_recovery_code(code))
+        .collect();
+
+    enable_totp(&auth.user.id, step, &normalized, &ctx).await.map_err(|_e|
+        internal_error("Failed to enable two-factor authentication")
+    )?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "success",
+        "recoveryCodes": recovery_codes,
+    })
+        )
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct DisableTotpRequest {
+    password: String,
+}
+
+#[axum::debug_handler]
+async fn disable(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>,
+    Json(req): Json<DisableTotpRequest>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    if !auth.user.verify_password(&req.password) {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Invalid password",
+        });
+        return Err((StatusCode::UNAUTHORIZED, Json(error_response)));
+    }
+
+    disable_totp(&auth.user.id, &ctx).await.map_err(|_e|
+        internal_error("Failed to disable two-factor authentication")
+    )?;
+
+    Ok(Json(serde_json::json!({ "status": "success" })))
+}
+
+#[allow(non_snake_case)]
+#[derive(serde::Deserialize)]
+struct MfaLoginRequest {
+    mfaToken: String,
+    code: Option<String>,
+    recoveryCode: Option<String>,
+}
+
+/// Second step of the login of users with TOTP enabled, exchanges the token returned by
+/// `/auth/login` and a TOTP or recovery code for an access and refresh token.
+#[axum::debug_handler]
+async fn login(
+    ctx: Extension<AppState>,
+    client: ClientInfo,
+    Json(req): Json<MfaLoginRequest>
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let challenge = decode_mfa_token(&ctx.keys, &req.mfaToken).map_err(|status| {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Invalid or expired MFA token",
+        });
+        (status, Json(error_response))
+    })?;
+    let user_id = challenge.user_id;
+
+    let throttle_keys = vec![ThrottleKey::Mfa(user_id)];
+    let attempt = match begin_login_attempt(throttle_keys, Some(user_id), &ctx).await {
+        Ok(attempt) => attempt,
+        Err(retry_after) => {
+            return Ok(too_many_attempts(retry_after));
+        }
+    };
+
+    let user = get_user_by_id(&user_id, &ctx).await.map_err(|_e| invalid_code())?;
+    let verified = verify_second_factor(
+        &user,
+        req.code.as_deref(),
+        req.recoveryCode.as_deref(),
+        &ctx
+    ).await.map_err(|_e| internal_error("Failed to verify code"))?;
+
+    if !verified {
+        attempt.failed(Some(user.id), &ctx).await;
+        return Err(invalid_code());
+    }
+    attempt.succeeded(&ctx).await;
+
+    let tokens = start_session(
+        &user.id,
+        &user.email,
+        &client,
+        challenge.sso_organization_id.as_ref(),
+        &ctx
+    ).await.map_err(|e| e.response())?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "success",
+        "accessToken": tokens.access_token,
+        "refreshToken": tokens.refresh_token,
+    })
+        ).into_response()
+    )
+}
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/login", post(login))
+        .route("/totp/enroll", post(enroll_totp).layer(middleware::from_fn(auth_session)))
+        .route("/totp/confirm", post(confirm_totp).layer(middleware::from_fn(auth_session)))
+        .route("/totp/disable", post(disable).layer(middleware::from_fn(auth_session)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{ Method, StatusCode };
+    use axum::Router;
+    use chrono::Utc;
+    use sqlx::PgPool;
+    use totp_rs::{ Algorithm, Secret, TOTP };
+
+    use crate::test_support::{ app, app_state, request, send, sign_up, PASSWORD };
+
+    const EMAIL: &str = "jane@example.com";
+
+    /// The code of the authenticator app, `steps` time steps from now.
+    fn totp_code(secret: &str, steps: i64) -> String {
+        let secret = Secret::Encoded(secret.to_string()).to_bytes().unwrap();
+        let totp = TOTP::new(Algorithm::SHA1, 6, 1, 30, secret, None, EMAIL.to_string()).unwrap();
+        totp.generate((Utc::now().timestamp() + steps * 30) as u64)
+    }
+
+    /// Enables TOTP for the user of the token, returning the secret and the recovery codes.
+    async fn enable_totp(app: &Router, token: &str) -> (String, Vec<String>) {
+        let enroll = request(Method::POST, "/api/auth/mfa/totp/enroll", Some(token));
+        let (status, enrolment) = send(app, enroll, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let secret = enrolment["secret"].as_str().unwrap().to_string();
+
+        let body = serde_json::json!({ "code": totp_code(&secret, 0) });
+        let confirm = request(Method::POST, "/api/auth/mfa/totp/confirm", Some(token));
+        let (status, confirmed) = send(app, confirm, Some(body)).await;
+        assert_eq!(status, StatusCode::OK, "{}", confirmed);
+        let recovery_codes = confirmed["recoveryCodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|code| code.as_str().unwrap().to_string())
+            .collect();
+        (secret, recovery_codes)
+    }
+
+    /// Checks the password, returning the token for the second step.
+    async fn mfa_token(app: &Router) -> String {
+        let body = serde_json::json!({ "email": EMAIL, "password": PASSWORD });
+        let login = request(Method::POST, "/api/auth/login", None);
+        let (_, login) = send(app, login, Some(body)).await;
+        assert_eq!(login["status"], "mfa_required");
+        login["mfaToken"].as_str().unwrap().to_string()
+    }
+
+    async fn mfa_login(app: &Router, body: serde_json::Value) -> (StatusCode, serde_json::Value) {
+        send(app, request(Method::POST, "/api/auth/mfa/login", None), Some(body)).await
+    }
+
+    #[sqlx::test]
+    async fn logs_in_with_each_totp_code_once(db: PgPool) {
+        let app = app(&app_state(db));
+        let (token, _) = sign_up(&app, EMAIL).await;
+        let (secret, recovery_codes) = enable_totp(&app, &token).await;
+        assert_eq!(recovery_codes.len(), 10);
+
+        // The current code was used up by the confirmation, the next one is accepted as well.
+        let code = totp_code(&secret, 1);
+        let body = serde_json::json!({ "mfaToken": mfa_token(&app).await, "code": code });
+        let (status, tokens) = mfa_login(&app, body).await;
+        assert_eq!(status, StatusCode::OK);
+        let me = request(Method::GET, "/api/users/me", tokens["accessToken"].as_str());
+        assert_eq!(send(&app, me, None).await.0, StatusCode::OK);
+
+        let body = serde_json::json!({ "mfaToken": mfa_token(&app).await, "code": code });
+        assert_eq!(mfa_login(&app, body).await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn logs_in_with_each_recovery_code_once(db: PgPool) {
+        let app = app(&app_state(db));
+        let (token, _) = sign_up(&app, EMAIL).await;
+        let (_, recovery_codes) = enable_totp(&app, &token).await;
+
+        // Typed the way users do, without caring about case.
+        let recovery_code = recovery_codes[3].to_uppercase();
+        let body = serde_json::json!({
+            "mfaToken": mfa_token(&app).await,
+            "recoveryCode": recovery_code,
+        });
+        assert_eq!(mfa_login(&app, body).await.0, StatusCode::OK);
+
+        let body = serde_json::json!({
+            "mfaToken": mfa_token(&app).await,
+            "recoveryCode": recovery_code,
+        });
+        assert_eq!(mfa_login(&app, body).await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn rejects_wrong_codes_and_other_tokens(db: PgPool) {
+        let app = app(&app_state(db));
+        let (token, _) = sign_up(&app, EMAIL).await;
+        let enroll = request(Method::POST, "/api/auth/mfa/totp/enroll", Some(&token));
+        send(&app, enroll, None).await;
+        let body = serde_json::json!({ "code": "000000" });
+        let confirm = request(Method::POST, "/api/auth/mfa/totp/confirm", Some(&token));
+        assert_eq!(send(&app, confirm, Some(body)).await.0, StatusCode::UNAUTHORIZED);
+        let (secret, _) = enable_totp(&app, &token).await;
+
+        let mfa_token = mfa_token(&app).await;
+        let body = serde_json::json!({ "mfaToken": mfa_token, "recoveryCode": "aaaaa-aaaaa" });
+        assert_eq!(mfa_login(&app, body).await.0, StatusCode::UNAUTHORIZED);
+        // Neither token can stand in for the other.
+        let body = serde_json::json!({ "mfaToken": token, "code": totp_code(&secret, 1) });
+        assert_eq!(mfa_login(&app, body).await.0, StatusCode::UNAUTHORIZED);
+        let me = request(Method::GET, "/api/users/me", Some(&mfa_token));
+        assert_eq!(send(&app, me, None).await.0, StatusCode::UNAUTHORIZED);
+    }
+}
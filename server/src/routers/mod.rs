@@ -2,11 +2,28 @@ use axum::Router;
 
 pub mod users;
 pub mod auth;
+pub mod mfa;
+pub mod oidc;
+pub mod passkey;
+pub mod sso;
+pub mod api_keys;
 pub mod organization;
+pub mod invitation;
+pub mod member;
+pub mod role;
+pub mod team;
+pub mod project;
+pub mod time_entry;
+pub mod client;
+pub mod hourly_rate;
+pub mod timer;
+pub mod well_known;
 
 pub fn router() -> Router {
     Router::new()
         .nest("/users", users::router())
         .nest("/auth", auth::router())
         .nest("/organizations", organization::router())
+        .nest("/api-keys", api_keys::router())
+        .nest("/timers", timer::router())
 }
@@ -1,16 +1,44 @@
 use axum::http::StatusCode;
-use axum::routing::post;
+use axum::routing::{ get, post };
 use axum::{ middleware, Extension, Json };
 use axum::{ response::IntoResponse, Router };
-use log::debug;
+use log::error;
+use uuid::Uuid;
 
+use crate::auth::api_key::Scope;
 use crate::auth::authorization_middleware::auth;
+use crate::auth::organization_access::{ OrganizationMember, Permission };
 use crate::{
     auth::authorization_middleware::AuthExtension,
-    db::organization::{ create_organization, CreateOrganization },
+    db::organization::{
+        create_organization,
+        delete_organization,
+        get_organization_details,
+        is_unique_violation,
+        is_valid_slug,
+        remove_member,
+        transfer_ownership,
+        update_organization,
+        CreateOrganization,
+        OWNER_NAME_CONSTRAINT,
+        SLUG_CONSTRAINT,
+    },
     state::AppState,
 };
 
+fn error(status: StatusCode, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": message,
+    });
+    (status, Json(error_response))
+}
+
+fn organization_not_found() -> (StatusCode, Json<serde_json::Value>) {
+    error(StatusCode::NOT_FOUND, "Organization not found")
+}
+
 #[derive(serde::Deserialize)]
 struct NewOrganization {
     name: String,
@@ -22,6 +50,16 @@ async fn post_organization(
     auth: Extension<AuthExtension>,
     Json(req): Json<NewOrganization>
 ) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    auth.require_scope(Scope::OrganizationsWrite)?;
+    if auth.is_organization_key() {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Organization API keys cannot create organizations",
+        });
+        return Err((StatusCode::FORBIDDEN, Json(error_response)));
+    }
+
     match
         create_organization(
             {
@@ -34,11 +72,12 @@ async fn post_organization(
             &ctx
         ).await
     {
-        Ok(org_id) => {
+        Ok(organization) => {
             let json_response =
                 serde_json::json!({
                 "status": "ok",
-                "organization_id": org_id,
+                "organization_id": organization.id,
+                "slug": organization.slug,
             });
             Ok(Json(json_response))
         }
@@ -73,11 +112,330 @@ async fn post_organization(
                     ),
                 )
             };
-            return Err(error_response);
+            Err(error_response)
+        }
+    }
+}
+
+/// The organization together with the role and permissions of the caller in it.
+#[axum::debug_handler]
+async fn get_organization(
+    ctx: Extension<AppState>,
+    member: OrganizationMember
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::OrganizationRead)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let organization = get_organization_details(&member.organization_id, &mut *tx).await
+        .map_err(|_e| error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch organization"))?
+        .ok_or_else(organization_not_found)?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "organization": organization,
+        "role": member.role.name(),
+        "permissions": member.permissions(),
+    })
+        )
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct OrganizationUpdate {
+    name: Option<String>,
+    slug: Option<String>,
+}
+
+#[axum::debug_handler]
+async fn patch_organization(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Json(req): Json<OrganizationUpdate>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::OrganizationUpdate)?;
+
+    let name = req.name.as_deref().map(str::trim);
+    if name.is_some_and(str::is_empty) {
+        return Err(error(StatusCode::BAD_REQUEST, "Name cannot be empty"));
+    }
+    if req.slug.as_deref().is_some_and(|slug| !is_valid_slug(slug)) {
+        return Err(
+            error(
+                StatusCode::BAD_REQUEST,
+                "Slugs consist of lowercase letters and digits separated by single hyphens"
+            )
+        );
+    }
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let updated = update_organization(&member.organization_id, name, req.slug.as_deref(), &mut *tx)
+        .await;
+    let updated = match updated {
+        Ok(updated) => tx.commit().await.map(|_| updated),
+        Err(e) => Err(e),
+    };
+    match updated {
+        Ok(Some(organization)) =>
+            Ok(
+                Json(
+                    serde_json::json!({
+                "status": "ok",
+                "organization": organization,
+            })
+                )
+            ),
+        Ok(None) => Err(organization_not_found()),
+        Err(e) if is_unique_violation(&e, SLUG_CONSTRAINT) => {
+            Err(error(StatusCode::CONFLICT, "Slug is already taken"))
+        }
+        Err(sqlx::Error::Database(db_err)) if db_err.code().as_deref() == Some("23505") => {
+            Err(error(StatusCode::CONFLICT, "Organization already exists"))
+        }
+        Err(e) => {
+            error!("Failed to update organization: {:?}", e);
+            Err(error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to update organization"))
+        }
+    }
+}
+
+/// Deletes the organization and everything that belongs to it. Only possible from a login
+/// session, API keys cannot delete organizations.
+#[axum::debug_handler]
+async fn delete_organization_handler(
+    ctx: Extension<AppState>,
+    member: OrganizationMember
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.auth.session()?;
+    member.require(Permission::OrganizationDelete)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let deleted = match delete_organization(&member.organization_id, &mut *tx).await {
+        Ok(deleted) => tx.commit().await.map(|_| deleted),
+        Err(e) => Err(e),
+    };
+    match deleted {
+        Ok(true) => Ok(Json(serde_json::json!({ "status": "ok" }))),
+        Ok(false) => Err(organization_not_found()),
+        Err(e) => {
+            error!("Failed to delete organization: {:?}", e);
+            Err(error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete organization"))
+        }
+    }
+}
+
+/// Leaves the organization. The owner has to transfer ownership first.
+#[axum::debug_handler]
+async fn leave_organization(
+    ctx: Extension<AppState>,
+    member: OrganizationMember
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.auth.session()?;
+    if member.role.is_owner() {
+        return Err(
+            error(StatusCode::BAD_REQUEST, "The owner has to transfer ownership before leaving")
+        );
+    }
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let user_id = member.auth.user.id;
+    let removed = match remove_member(&user_id, &member.organization_id, &mut tx).await {
+        Ok(removed) => tx.commit().await.map(|_| removed),
+        Err(e) => Err(e),
+    };
+    match removed {
+        Ok(true) => Ok(Json(serde_json::json!({ "status": "ok" }))),
+        Ok(false) => Err(organization_not_found()),
+        Err(_e) => Err(error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to leave organization")),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct OwnershipTransfer {
+    user_id: Uuid,
+}
+
+/// Hands the organization over to another member, the owner becomes an admin.
+#[axum::debug_handler]
+async fn post_transfer_ownership(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Json(req): Json<OwnershipTransfer>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.auth.session()?;
+    if !member.role.is_owner() {
+        return Err(error(StatusCode::FORBIDDEN, "Only the owner can transfer ownership"));
+    }
+    if req.user_id == member.auth.user.id {
+        return Err(error(StatusCode::BAD_REQUEST, "You already own the organization"));
+    }
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let transferred = transfer_ownership(
+        &member.organization_id,
+        &member.auth.user.id,
+        &req.user_id,
+        &mut tx
+    ).await;
+    let transferred = match transferred {
+        Ok(transferred) => tx.commit().await.map(|_| transferred),
+        Err(e) => Err(e),
+    };
+    match transferred {
+        Ok(true) =>
+            Ok(
+                Json(
+                    serde_json::json!({
+                "status": "ok",
+                "owner_user_id": req.user_id,
+            })
+                )
+            ),
+        Ok(false) => Err(error(StatusCode::NOT_FOUND, "Member not found")),
+        Err(e) if is_unique_violation(&e, OWNER_NAME_CONSTRAINT) => {
+            Err(
+                error(
+                    StatusCode::CONFLICT,
+                    "The new owner already owns an organization with this name"
+                )
+            )
+        }
+        Err(e) => {
+            error!("Failed to transfer ownership: {:?}", e);
+            Err(error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to transfer ownership"))
         }
     }
 }
 
 pub fn router() -> Router {
-    Router::new().route("/", post(post_organization).layer(middleware::from_fn(auth)))
+    Router::new()
+        .route("/", post(post_organization).layer(middleware::from_fn(auth)))
+        .route(
+            "/:organization_id",
+            get(get_organization)
+                .patch(patch_organization)
+                .delete(delete_organization_handler)
+                .layer(middleware::from_fn(auth))
+        )
+        .route("/:organization_id/leave", post(leave_organization).layer(middleware::from_fn(auth)))
+        .route(
+            "/:organization_id/transfer-ownership",
+            post(post_transfer_ownership).layer(middleware::from_fn(auth))
+        )
+        .nest("/invitations", super::invitation::router())
+        .nest("/:organization_id/invitations", super::invitation::organization_router())
+        .nest("/:organization_id/members", super::member::router())
+        .nest("/:organization_id/roles", super::role::router())
+        .nest("/:organization_id/sso", super::sso::organization_router())
+        .nest("/:organization_id/teams", super::team::router())
+        .nest("/:organization_id/clients", super::client::router())
+        .nest("/:organization_id/projects", super::project::router())
+        .nest("/:organization_id/time-entries", super::time_entry::router())
+        .nest("/:organization_id/rates", super::hourly_rate::router())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{ Method, StatusCode };
+    use axum::Router;
+    use sqlx::PgPool;
+
+    use crate::test_support::{
+        app,
+        app_state_with_mailer,
+        create_organization,
+        request,
+        send,
+        sign_up,
+        RecordingMailer,
+    };
+
+    /// Invites the user to the organization as admin and accepts, returning its access token.
+    async fn join_as_admin(
+        app: &Router,
+        mailer: &RecordingMailer,
+        owner: &str,
+        organization_id: &str,
+        email: &str
+    ) -> String {
+        let uri = format!("/api/organizations/{}/invitations", organization_id);
+        let body = serde_json::json!({ "email": email, "role": "admin" });
+        send(app, request(Method::POST, &uri, Some(owner)), Some(body)).await;
+        let invitation_token = mailer.last_token(email).unwrap();
+
+        let (token, _) = sign_up(app, email).await;
+        let accept = request(Method::POST, "/api/organizations/invitations/accept", Some(&token));
+        let body = serde_json::json!({ "token": invitation_token });
+        let (status, accepted) = send(app, accept, Some(body)).await;
+        assert_eq!(status, StatusCode::OK, "{}", accepted);
+        token
+    }
+
+    async fn user_id(app: &Router, token: &str) -> String {
+        let (_, me) = send(app, request(Method::GET, "/api/users/me", Some(token)), None).await;
+        me["id"].as_str().unwrap().to_string()
+    }
+
+    async fn role(app: &Router, token: &str, organization_id: &str) -> serde_json::Value {
+        let uri = format!("/api/organizations/{}", organization_id);
+        let (_, organization) = send(app, request(Method::GET, &uri, Some(token)), None).await;
+        organization["role"].clone()
+    }
+
+    async fn delete(app: &Router, token: &str, organization_id: &str) -> StatusCode {
+        let uri = format!("/api/organizations/{}", organization_id);
+        send(app, request(Method::DELETE, &uri, Some(token)), None).await.0
+    }
+
+    async fn transfer(app: &Router, token: &str, organization_id: &str, to: &str) -> StatusCode {
+        let uri = format!("/api/organizations/{}/transfer-ownership", organization_id);
+        let body = serde_json::json!({ "user_id": to });
+        send(app, request(Method::POST, &uri, Some(token)), Some(body)).await.0
+    }
+
+    #[sqlx::test]
+    async fn only_the_owner_deletes_the_organization(db: PgPool) {
+        let (ctx, mailer) = app_state_with_mailer(db);
+        let app = app(&ctx);
+        let (owner, _) = sign_up(&app, "jane@example.com").await;
+        let organization_id = create_organization(&app, &owner, "Acme").await;
+        let admin = join_as_admin(&app, &mailer, &owner, &organization_id, "bob@example.com")
+            .await;
+
+        assert_eq!(delete(&app, &admin, &organization_id).await, StatusCode::FORBIDDEN);
+        assert_eq!(role(&app, &owner, &organization_id).await, "owner");
+
+        assert_eq!(delete(&app, &owner, &organization_id).await, StatusCode::OK);
+        let uri = format!("/api/organizations/{}", organization_id);
+        let get = request(Method::GET, &uri, Some(&owner));
+        assert_eq!(send(&app, get, None).await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn only_the_owner_transfers_ownership_to_a_member(db: PgPool) {
+        let (ctx, mailer) = app_state_with_mailer(db);
+        let app = app(&ctx);
+        let (owner, _) = sign_up(&app, "jane@example.com").await;
+        let organization_id = create_organization(&app, &owner, "Acme").await;
+        let admin = join_as_admin(&app, &mailer, &owner, &organization_id, "bob@example.com")
+            .await;
+        let (outsider, _) = sign_up(&app, "eve@example.com").await;
+        let (owner_id, admin_id) = (user_id(&app, &owner).await, user_id(&app, &admin).await);
+        let outsider_id = user_id(&app, &outsider).await;
+
+        let status = transfer(&app, &admin, &organization_id, &admin_id).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let status = transfer(&app, &owner, &organization_id, &owner_id).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let status = transfer(&app, &owner, &organization_id, &outsider_id).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(role(&app, &owner, &organization_id).await, "owner");
+
+        assert_eq!(transfer(&app, &owner, &organization_id, &admin_id).await, StatusCode::OK);
+        assert_eq!(role(&app, &owner, &organization_id).await, "admin");
+        assert_eq!(role(&app, &admin, &organization_id).await, "owner");
+        assert_eq!(delete(&app, &owner, &organization_id).await, StatusCode::FORBIDDEN);
+        assert_eq!(delete(&app, &admin, &organization_id).await, StatusCode::OK);
+    }
 }
@@ -0,0 +1,618 @@
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::routing::{ delete, get, post };
+use axum::{ middleware, Extension, Json };
+use axum::{ response::IntoResponse, Router };
+use log::{ error, warn };
+use serde::{ de::DeserializeOwned, Serialize };
+use uuid::Uuid;
+use webauthn_rs::prelude::{
+    CredentialID,
+    DiscoverableAuthentication,
+    DiscoverableKey,
+    Passkey,
+    PasskeyRegistration,
+    PublicKeyCredential,
+    RegisterPublicKeyCredential,
+};
+
+use crate::auth::authorization_middleware::{ auth_session, AuthExtension };
+use crate::auth::client_info::ClientInfo;
+use crate::auth::mfa::verify_second_factor;
+use crate::auth::session::start_session;
+use crate::db::audit::insert_audit_event;
+use crate::db::passkey::{
+    create_passkey,
+    create_webauthn_challenge,
+    delete_passkey,
+    get_credential_ids_by_user_id,
+    get_passkey_by_credential_id,
+    get_passkeys_by_user_id,
+    record_passkey_use,
+    take_webauthn_challenge,
+    AUTHENTICATION_CHALLENGE,
+    REGISTRATION_CHALLENGE,
+};
+use crate::db::user::get_user_by_id;
+use crate::AppState;
+
+fn internal_error(message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": message,
+    });
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+fn invalid_passkey() -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": "Passkey verification failed",
+    });
+    (StatusCode::UNAUTHORIZED, Json(error_response))
+}
+
+fn to_json<T: Serialize>(
+    value: &T
+) -> Result<serde_json::Value, (StatusCode, Json<serde_json::Value>)> {
+    serde_json::to_value(value).map_err(|e| {
+        error!("Failed to serialize passkey state: {}", e);
+        internal_error("Failed to store passkey state")
+    })
+}
+
+fn from_json<T: DeserializeOwned>(
+    value: serde_json::Value
+) -> Result<T, (StatusCode, Json<serde_json::Value>)> {
+    serde_json::from_value(value).map_err(|e| {
+        error!("Failed to deserialize passkey state: {}", e);
+        internal_error("Failed to load passkey state")
+    })
+}
+
+/// The options for `navigator.credentials.create()` or `.get()` together with the id of the
+/// challenge, which has to be sent back with the response of the authenticator.
+fn challenge_response<T: Serialize>(
+    challenge_id: Uuid,
+    options: &T
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let mut response = to_json(options)?;
+    response["challengeId"] = serde_json::json!(challenge_id);
+    Ok(Json(response))
+}
+
+#[axum::debug_handler]
+async fn get_passkeys(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let passkeys = get_passkeys_by_user_id(&auth.user.id, &ctx).await.map_err(|_e|
+        internal_error("Failed to fetch passkeys")
+    )?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "passkeys": passkeys,
+    })
+        )
+    )
+}
+
+#[allow(non_snake_case)]
+#[derive(serde::Deserialize)]
+struct StartRegistrationRequest {
+    password: String,
+    code: Option<String>,
+    recoveryCode: Option<String>,
+}
+
+/// Passkeys count as both factors when logging in, so adding one requires the password and, when
+/// enabled, a TOTP or recovery code. An access token alone is not enough.
+async fn reauthenticate(
+    auth: &AuthExtension,
+    req: &StartRegistrationRequest,
+    ctx: &AppState
+) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    if !auth.user.verify_password(&req.password) {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Invalid password",
+        });
+        return Err((StatusCode::UNAUTHORIZED, Json(error_response)));
+    }
+
+    if auth.user.totp_enabled_at.is_some() {
+        let verified = verify_second_factor(
+            &auth.user,
+            req.code.as_deref(),
+            req.recoveryCode.as_deref(),
+            ctx
+        ).await.map_err(|_e| internal_error("Failed to verify code"))?;
+        if !verified {
+            let error_response =
+                serde_json::json!({
+                "status": "error",
+                "message": "Invalid code",
+            });
+            return Err((StatusCode::UNAUTHORIZED, Json(error_response)));
+        }
+    }
+    Ok(())
+}
+
+/// Starts adding a passkey, the returned challenge is the only way to finish it.
+#[axum::debug_handler]
+async fn start_registration(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>,
+    Json(req): Json<StartRegistrationRequest>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    reauthenticate(&auth, &req, &ctx).await?;
+
+    let existing: Vec<CredentialID> = get_credential_ids_by_user_id(&auth.user.id, &ctx).await
+        .map_err(|_e| internal_error("Failed to fetch passkeys"))?
+        .into_iter()
+        .map(CredentialID::from)
+        .collect();
+
+    let (options, registration) = ctx.webauthn
+        .start_passkey_registration(
+            auth.user.id,
+            &auth.user.email,
+            &auth.user.email,
+            Some(existing)
+        )
+        .map_err(|e| {
+            error!("Failed to start passkey registration: {}", e);
+            internal_error("Failed to start passkey registration")
+        })?;
+
+    let challenge_id = create_webauthn_challenge(
+        Some(&auth.user.id),
+        REGISTRATION_CHALLENGE,
+        to_json(&registration)?,
+        &ctx
+    ).await.map_err(|_e| internal_error("Failed to store passkey state"))?;
+
+    challenge_response(challenge_id, &options)
+}
+
+#[allow(non_snake_case)]
+#[derive(serde::Deserialize)]
+struct FinishRegistrationRequest {
+    challengeId: Uuid,
+    name: Option<String>,
+    credential: RegisterPublicKeyCredential,
+}
+
+#[axum::debug_handler]
+async fn finish_registration(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>,
+    Json(req): Json<FinishRegistrationRequest>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let state = take_webauthn_challenge(
+        &req.challengeId,
+        Some(&auth.user.id),
+        REGISTRATION_CHALLENGE,
+        &ctx
+    ).await
+        .map_err(|_e| internal_error("Failed to load passkey state"))?
+        .ok_or_else(invalid_passkey)?;
+    let registration: PasskeyRegistration = from_json(state)?;
+
+    let passkey = ctx.webauthn.finish_passkey_registration(&req.credential, &registration).map_err(
+        |e| {
+            warn!("Passkey registration failed: {}", e);
+            invalid_passkey()
+        }
+    )?;
+
+    let name = req.name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "Passkey".to_string());
+
+    let passkey = create_passkey(
+        &auth.user.id,
+        &name,
+        passkey.cred_id().as_ref(),
+        to_json(&passkey)?,
+        &ctx
+    ).await.map_err(|e| {
+        error!("Failed to store passkey: {}", e);
+        internal_error("Failed to store passkey")
+    })?;
+    let subject = passkey.id.to_string();
+    if let Err(e) = insert_audit_event("passkey_added", Some(&auth.user.id), &subject, &ctx).await {
+        error!("Failed to audit the passkey of user {}: {}", auth.user.id, e);
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "passkey": passkey,
+    })
+        ),
+    ))
+}
+
+#[axum::debug_handler]
+async fn delete_passkey_by_id(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>,
+    Path(passkey_id): Path<Uuid>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    match delete_passkey(&auth.user.id, &passkey_id, &ctx).await {
+        Ok(true) => Ok(Json(serde_json::json!({ "status": "ok" }))),
+        Ok(false) => {
+            let error_response =
+                serde_json::json!({
+                "status": "error",
+                "message": "Passkey not found",
+            });
+            Err((StatusCode::NOT_FOUND, Json(error_response)))
+        }
+        Err(_e) => Err(internal_error("Failed to delete passkey")),
+    }
+}
+
+/// Starts a login with a discoverable passkey. No email is needed, the authenticator tells which
+/// user it belongs to, so the response does not reveal who has passkeys.
+#[axum::debug_handler]
+async fn start_login(
+    ctx: Extension<AppState>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let (options, authentication) = ctx.webauthn.start_discoverable_authentication().map_err(|e| {
+        error!("Failed to start passkey login: {}", e);
+        internal_error("Failed to start passkey login")
+    })?;
+
+    let challenge_id = create_webauthn_challenge(
+        None,
+        AUTHENTICATION_CHALLENGE,
+        to_json(&authentication)?,
+        &ctx
+    ).await.map_err(|_e| internal_error("Failed to store passkey state"))?;
+
+    challenge_response(challenge_id, &options)
+}
+
+#[allow(non_snake_case)]
+#[derive(serde::Deserialize)]
+struct FinishLoginRequest {
+    challengeId: Uuid,
+    credential: PublicKeyCredential,
+}
+
+/// Finishes a passkey login. Passkeys verify the user on the device, so they count as both
+/// factors and the same tokens as a completed `/auth/login` are issued.
+#[axum::debug_handler]
+async fn finish_login(
+    ctx: Extension<AppState>,
+    client: ClientInfo,
+    Json(req): Json<FinishLoginRequest>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let state = take_webauthn_challenge(&req.challengeId, None, AUTHENTICATION_CHALLENGE, &ctx)
+        .await
+        .map_err(|_e| internal_error("Failed to load passkey state"))?
+        .ok_or_else(invalid_passkey)?;
+    let authentication: DiscoverableAuthentication = from_json(state)?;
+
+    let (user_id, credential_id) = ctx.webauthn
+        .identify_discoverable_authentication(&req.credential)
+        .map_err(|_e| invalid_passkey())?;
+
+    let stored = get_passkey_by_credential_id(&user_id, credential_id, &ctx).await
+        .map_err(|_e| internal_error("Failed to fetch passkey"))?
+        .ok_or_else(invalid_passkey)?;
+    let mut passkey: Passkey = from_json(stored.passkey)?;
+
+    let result = ctx.webauthn
+        .finish_discoverable_authentication(
+            &req.credential,
+            authentication,
+            &[DiscoverableKey::from(&passkey)]
+        )
+        .map_err(|e| {
+            warn!("Passkey login failed for user {}: {}", user_id, e);
+            invalid_passkey()
+        })?;
+
+    let updated = match passkey.update_credential(&result) {
+        Some(true) => Some(to_json(&passkey)?),
+        _ => None,
+    };
+    record_passkey_use(&stored.id, updated, &ctx).await.map_err(|_e|
+        internal_error("Failed to update passkey")
+    )?;
+
+    let user = get_user_by_id(&user_id, &ctx).await.map_err(|_e| invalid_passkey())?;
+    let tokens = start_session(&user.id, &user.email, &client, None, &ctx).await.map_err(|e|
+        e.response()
+    )?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "success",
+        "accessToken": tokens.access_token,
+        "refreshToken": tokens.refresh_token,
+    })
+        )
+    )
+}
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/", get(get_passkeys).layer(middleware::from_fn(auth_session)))
+        .route(
+            "/register/start",
+            post(start_registration).layer(middleware::from_fn(auth_session))
+        )
+        .route(
+            "/register/finish",
+            post(finish_registration).layer(middleware::from_fn(auth_session))
+        )
+        .route(
+            "/:passkey_id",
+            delete(delete_passkey_by_id).layer(middleware::from_fn(auth_session))
+        )
+        .route("/login/start", post(start_login))
+        .route("/login/finish", post(finish_login))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::http::{ Method, StatusCode };
+    use axum::Router;
+    use base64::{ engine::general_purpose::URL_SAFE_NO_PAD, Engine };
+    use ring::rand::SystemRandom;
+    use ring::signature::{ EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING };
+    use sha2::{ Digest, Sha256 };
+    use sqlx::{ query, query_scalar, PgPool };
+    use uuid::Uuid;
+    use webauthn_rs::prelude::Url;
+    use webauthn_rs::WebauthnBuilder;
+
+    use crate::test_support::{ app, app_state, request, send, sign_up, PASSWORD };
+    use crate::AppState;
+
+    const RP_ID: &str = "localhost";
+    const ORIGIN: &str = "http://localhost:3000";
+
+    /// A platform authenticator that verified the user, with a P-256 key and no attestation.
+    struct SoftAuthenticator {
+        key: EcdsaKeyPair,
+        credential_id: [u8; 16],
+    }
+
+    /// The head of a CBOR byte or text string.
+    fn cbor_head(major_type: u8, length: usize) -> Vec<u8> {
+        match length {
+            0..=23 => vec![(major_type << 5) | (length as u8)],
+            24..=255 => vec![(major_type << 5) | 24, length as u8],
+            _ => [vec![(major_type << 5) | 25], (length as u16).to_be_bytes().to_vec()].concat(),
+        }
+    }
+
+    fn cbor_bytes(bytes: &[u8]) -> Vec<u8> {
+        [cbor_head(2, bytes.len()), bytes.to_vec()].concat()
+    }
+
+    fn cbor_text(text: &str) -> Vec<u8> {
+        [cbor_head(3, text.len()), text.as_bytes().to_vec()].concat()
+    }
+
+    fn client_data(kind: &str, options: &serde_json::Value) -> Vec<u8> {
+        serde_json::json!({
+            "type": kind,
+            "challenge": options["publicKey"]["challenge"],
+            "origin": ORIGIN,
+            "crossOrigin": false,
+        })
+            .to_string()
+            .into_bytes()
+    }
+
+    impl SoftAuthenticator {
+        fn new() -> Self {
+            let rng = SystemRandom::new();
+            let algorithm = &ECDSA_P256_SHA256_ASN1_SIGNING;
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(algorithm, &rng).unwrap();
+            let key = EcdsaKeyPair::from_pkcs8(algorithm, pkcs8.as_ref(), &rng).unwrap();
+            Self { key, credential_id: *Uuid::new_v4().as_bytes() }
+        }
+
+        /// Authenticator data with the user present and verified flags and the signature counter.
+        fn authenticator_data(&self, flags: u8, counter: u32) -> Vec<u8> {
+            [
+                Sha256::digest(RP_ID).to_vec(),
+                vec![0x05 | flags],
+                counter.to_be_bytes().to_vec(),
+            ].concat()
+        }
+
+        /// The response of `navigator.credentials.create()` to the registration options.
+        fn register(&self, options: &serde_json::Value) -> serde_json::Value {
+            // The public key as COSE key of type EC2 on P-256 for ES256.
+            let point = self.key.public_key().as_ref();
+            let cose_key = [
+                vec![0xa5, 0x01, 0x02, 0x03, 0x26, 0x20, 0x01, 0x21],
+                cbor_bytes(&point[1..33]),
+                vec![0x22],
+                cbor_bytes(&point[33..]),
+            ].concat();
+            let authenticator_data = [
+                self.authenticator_data(0x40, 0),
+                vec![0; 16],
+                (self.credential_id.len() as u16).to_be_bytes().to_vec(),
+                self.credential_id.to_vec(),
+                cose_key,
+            ].concat();
+            let attestation_object = [
+                vec![0xa3],
+                cbor_text("fmt"),
+                cbor_text("none"),
+                cbor_text("attStmt"),
+                vec![0xa0],
+                cbor_text("authData"),
+                cbor_bytes(&authenticator_data),
+            ].concat();
+            let client_data = client_data("webauthn.create", options);
+
+            serde_json::json!({
+                "id": URL_SAFE_NO_PAD.encode(self.credential_id),
+                "rawId": URL_SAFE_NO_PAD.encode(self.credential_id),
+                "type": "public-key",
+                "response": {
+                    "attestationObject": URL_SAFE_NO_PAD.encode(attestation_object),
+                    "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+                },
+            })
+        }
+
+        /// The response of `navigator.credentials.get()` to the login options.
+        fn assert(&self, options: &serde_json::Value, user_id: &Uuid) -> serde_json::Value {
+            let authenticator_data = self.authenticator_data(0, 1);
+            let client_data = client_data("webauthn.get", options);
+            let client_data_hash = Sha256::digest(&client_data).to_vec();
+            let signed = [authenticator_data.clone(), client_data_hash].concat();
+            let signature = self.key.sign(&SystemRandom::new(), &signed).unwrap();
+
+            serde_json::json!({
+                "id": URL_SAFE_NO_PAD.encode(self.credential_id),
+                "rawId": URL_SAFE_NO_PAD.encode(self.credential_id),
+                "type": "public-key",
+                "response": {
+                    "authenticatorData": URL_SAFE_NO_PAD.encode(authenticator_data),
+                    "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+                    "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+                    "userHandle": URL_SAFE_NO_PAD.encode(user_id.as_bytes()),
+                },
+            })
+        }
+    }
+
+    fn passkey_app(db: PgPool) -> Router {
+        let origin = Url::parse(ORIGIN).unwrap();
+        let webauthn = WebauthnBuilder::new(RP_ID, &origin).unwrap().build().unwrap();
+        app(&(AppState { webauthn: Arc::new(webauthn), ..app_state(db) }))
+    }
+
+    async fn user_id(app: &Router, token: &str) -> Uuid {
+        let (_, me) = send(app, request(Method::GET, "/api/users/me", Some(token)), None).await;
+        Uuid::parse_str(me["id"].as_str().unwrap()).unwrap()
+    }
+
+    async fn start_registration(
+        app: &Router,
+        token: &str,
+        body: serde_json::Value
+    ) -> (StatusCode, serde_json::Value) {
+        let start = request(Method::POST, "/api/auth/passkeys/register/start", Some(token));
+        send(app, start, Some(body)).await
+    }
+
+    async fn add_passkey(app: &Router, token: &str, authenticator: &SoftAuthenticator) {
+        let body = serde_json::json!({ "password": PASSWORD });
+        let (status, options) = start_registration(app, token, body).await;
+        assert_eq!(status, StatusCode::OK, "{}", options);
+
+        let body = serde_json::json!({
+            "challengeId": options["challengeId"],
+            "name": "Laptop",
+            "credential": authenticator.register(&options),
+        });
+        let finish = request(Method::POST, "/api/auth/passkeys/register/finish", Some(token));
+        let (status, added) = send(app, finish, Some(body)).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", added);
+    }
+
+    async fn start_login(app: &Router) -> serde_json::Value {
+        let start = request(Method::POST, "/api/auth/passkeys/login/start", None);
+        let (status, options) = send(app, start, None).await;
+        assert_eq!(status, StatusCode::OK, "{}", options);
+        options
+    }
+
+    async fn finish_login(
+        app: &Router,
+        options: &serde_json::Value,
+        credential: serde_json::Value
+    ) -> (StatusCode, serde_json::Value) {
+        let body = serde_json::json!({
+            "challengeId": options["challengeId"],
+            "credential": credential,
+        });
+        let finish = request(Method::POST, "/api/auth/passkeys/login/finish", None);
+        send(app, finish, Some(body)).await
+    }
+
+    #[sqlx::test]
+    async fn registers_and_logs_in_with_a_passkey(db: PgPool) {
+        let app = passkey_app(db.clone());
+        let (token, _) = sign_up(&app, "jane@example.com").await;
+        let user_id = user_id(&app, &token).await;
+        let authenticator = SoftAuthenticator::new();
+
+        add_passkey(&app, &token, &authenticator).await;
+        let list = request(Method::GET, "/api/auth/passkeys", Some(&token));
+        let (_, passkeys) = send(&app, list, None).await;
+        assert_eq!(passkeys["passkeys"][0]["name"], "Laptop");
+        let audited = query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM auth_audit_log WHERE event = 'passkey_added'"#
+        ).fetch_one(&db).await.unwrap();
+        assert_eq!(audited, 1);
+
+        let options = start_login(&app).await;
+        let credential = authenticator.assert(&options, &user_id);
+        let (status, tokens) = finish_login(&app, &options, credential).await;
+        assert_eq!(status, StatusCode::OK, "{}", tokens);
+        let me = request(Method::GET, "/api/users/me", tokens["accessToken"].as_str());
+        assert_eq!(send(&app, me, None).await.0, StatusCode::OK);
+
+        // Each challenge can only be answered once.
+        let credential = authenticator.assert(&options, &user_id);
+        assert_eq!(finish_login(&app, &options, credential).await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn rejects_unknown_passkeys(db: PgPool) {
+        let app = passkey_app(db);
+        let (token, _) = sign_up(&app, "jane@example.com").await;
+        let user_id = user_id(&app, &token).await;
+        add_passkey(&app, &token, &SoftAuthenticator::new()).await;
+
+        let options = start_login(&app).await;
+        let credential = SoftAuthenticator::new().assert(&options, &user_id);
+        assert_eq!(finish_login(&app, &options, credential).await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn adding_a_passkey_requires_the_password_and_second_factor(db: PgPool) {
+        let app = passkey_app(db.clone());
+        let (token, _) = sign_up(&app, "jane@example.com").await;
+
+        let body = serde_json::json!({ "password": "wrong password" });
+        assert_eq!(start_registration(&app, &token, body).await.0, StatusCode::UNAUTHORIZED);
+
+        query!(
+            "UPDATE users SET totp_secret = $1, totp_enabled_at = NOW()",
+            "JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP"
+        ).execute(&db).await.unwrap();
+        let body = serde_json::json!({ "password": PASSWORD });
+        assert_eq!(start_registration(&app, &token, body).await.0, StatusCode::UNAUTHORIZED);
+        let body = serde_json::json!({ "password": PASSWORD, "code": "000000" });
+        assert_eq!(start_registration(&app, &token, body).await.0, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,224 @@
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{ middleware, Extension, Json };
+use axum::{ response::IntoResponse, Router };
+use log::error;
+use serde::{ Deserialize, Deserializer };
+use uuid::Uuid;
+
+use crate::auth::authorization_middleware::auth;
+use crate::auth::organization_access::{ OrganizationMember, Permission };
+use crate::db::project::{
+    create_project,
+    delete_project,
+    get_project,
+    get_projects,
+    update_project,
+};
+use crate::AppState;
+
+const MAX_NAME_LENGTH: usize = 255;
+
+fn error(status: StatusCode, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": message,
+    });
+    (status, Json(error_response))
+}
+
+fn project_not_found() -> (StatusCode, Json<serde_json::Value>) {
+    error(StatusCode::NOT_FOUND, "Project not found")
+}
+
+fn validate_name(name: &str) -> Result<&str, (StatusCode, Json<serde_json::Value>)> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err(error(StatusCode::BAD_REQUEST, "Invalid project name"));
+    }
+    Ok(name)
+}
+
+/// Tells a `null` apart from a missing field, `Some(None)` clears the value.
+pub fn deserialize_nullable<'de, D: Deserializer<'de>>(
+    deserializer: D
+) -> Result<Option<Option<Uuid>>, D::Error> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+fn project_error(e: sqlx::Error, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let db_error = e.as_database_error();
+    if db_error.and_then(|db_error| db_error.constraint()) == Some("project_client_fkey") {
+        return error(StatusCode::NOT_FOUND, "Client not found");
+    }
+    match db_error.and_then(|db_error| db_error.code()).as_deref() {
+        Some("23505") => error(StatusCode::CONFLICT, "A project with this name already exists"),
+        Some("23503") =>
+            error(StatusCode::CONFLICT, "Projects with time entries can only be archived"),
+        _ => {
+            error!("{}: {:?}", message, e);
+            error(StatusCode::INTERNAL_SERVER_ERROR, message)
+        }
+    }
+}
+
+/// All projects of the organization, including archived ones.
+#[axum::debug_handler]
+async fn get_projects_handler(
+    ctx: Extension<AppState>,
+    member: OrganizationMember
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::OrganizationRead)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let projects = get_projects(&member.organization_id, &mut *tx).await.map_err(|_e|
+        error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch projects")
+    )?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "projects": projects,
+    })
+        )
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct NewProject {
+    client_id: Option<Uuid>,
+    name: String,
+    #[serde(default)]
+    description: String,
+}
+
+#[axum::debug_handler]
+async fn post_project(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Json(req): Json<NewProject>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::ProjectsManage)?;
+    let name = validate_name(&req.name)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let project = create_project(
+        &member.organization_id,
+        req.client_id.as_ref(),
+        name,
+        req.description.trim(),
+        &mut *tx
+    ).await.map_err(|e| project_error(e, "Failed to create project"))?;
+    tx.commit().await.map_err(|e| project_error(e, "Failed to create project"))?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "project": project,
+    })
+        ),
+    ))
+}
+
+#[axum::debug_handler]
+async fn get_project_handler(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, project_id)): Path<(String, Uuid)>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::OrganizationRead)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let project = get_project(&member.organization_id, &project_id, &mut *tx).await
+        .map_err(|_e| error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch project"))?
+        .ok_or_else(project_not_found)?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "project": project,
+    })
+        )
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct ProjectUpdate {
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    client_id: Option<Option<Uuid>>,
+    name: Option<String>,
+    description: Option<String>,
+    archived: Option<bool>,
+}
+
+/// Renames, describes, archives or restores a project, or moves it to another client.
+#[axum::debug_handler]
+async fn patch_project(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, project_id)): Path<(String, Uuid)>,
+    Json(req): Json<ProjectUpdate>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::ProjectsManage)?;
+    let name = req.name.as_deref().map(validate_name).transpose()?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let project = update_project(
+        &member.organization_id,
+        &project_id,
+        req.client_id.as_ref().map(Option::as_ref),
+        name,
+        req.description.as_deref().map(str::trim),
+        req.archived,
+        &mut *tx
+    ).await
+        .map_err(|e| project_error(e, "Failed to update project"))?
+        .ok_or_else(project_not_found)?;
+    tx.commit().await.map_err(|e| project_error(e, "Failed to update project"))?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "project": project,
+    })
+        )
+    )
+}
+
+/// Deletes a project nobody has tracked time on yet, others have to be archived.
+#[axum::debug_handler]
+async fn delete_project_handler(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, project_id)): Path<(String, Uuid)>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::ProjectsManage)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    match delete_project(&member.organization_id, &project_id, &mut *tx).await {
+        Ok(true) => {
+            tx.commit().await.map_err(|e| project_error(e, "Failed to delete project"))?;
+            Ok(Json(serde_json::json!({ "status": "ok" })))
+        }
+        Ok(false) => Err(project_not_found()),
+        Err(e) => Err(project_error(e, "Failed to delete project")),
+    }
+}
+
+/// Nested under `/organizations/:organization_id/projects`.
+pub fn router() -> Router {
+    Router::new()
+        .route("/", get(get_projects_handler).post(post_project))
+        .route(
+            "/:project_id",
+            get(get_project_handler).patch(patch_project).delete(delete_project_handler)
+        )
+        .layer(middleware::from_fn(auth))
+}
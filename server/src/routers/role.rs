@@ -0,0 +1,229 @@
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::routing::{ get, patch };
+use axum::{ middleware, Extension, Json };
+use axum::{ response::IntoResponse, Router };
+use log::error;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::authorization_middleware::auth;
+use crate::auth::organization_access::{
+    parse_permissions,
+    OrganizationMember,
+    Permission,
+    Role,
+};
+use crate::db::role::{
+    create_custom_role,
+    delete_custom_role,
+    get_custom_role,
+    get_custom_roles,
+    update_custom_role,
+    CustomRole,
+};
+use crate::AppState;
+
+const MAX_NAME_LENGTH: usize = 64;
+
+fn error(status: StatusCode, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": message,
+    });
+    (status, Json(error_response))
+}
+
+fn role_not_found() -> (StatusCode, Json<serde_json::Value>) {
+    error(StatusCode::NOT_FOUND, "Role not found")
+}
+
+/// Custom roles cannot take the name of a built-in role, so role names stay unambiguous.
+fn validate_name(name: &str) -> Result<&str, (StatusCode, Json<serde_json::Value>)> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH || Role::parse(name).is_some() {
+        return Err(error(StatusCode::BAD_REQUEST, "Invalid role name"));
+    }
+    Ok(name)
+}
+
+fn permission_names(permissions: &[Permission]) -> Vec<String> {
+    let mut names: Vec<String> = permissions
+        .iter()
+        .map(|permission| permission.as_str().to_string())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn role_error(e: sqlx::Error, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    match e.as_database_error().and_then(|db_error| db_error.code()).as_deref() {
+        Some("23505") => error(StatusCode::CONFLICT, "A role with this name already exists"),
+        Some("23503") => error(StatusCode::CONFLICT, "Role is still assigned to members"),
+        _ => {
+            error!("{}: {:?}", message, e);
+            error(StatusCode::INTERNAL_SERVER_ERROR, message)
+        }
+    }
+}
+
+/// Loads a custom role the member is allowed to change, which are the ones that do not have
+/// more permissions than the member.
+async fn find_manageable_role(
+    member: &OrganizationMember,
+    role_id: &Uuid,
+    tx: &mut PgConnection
+) -> Result<CustomRole, (StatusCode, Json<serde_json::Value>)> {
+    let role = get_custom_role(&member.organization_id, role_id, tx).await
+        .map_err(|_e| error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch role"))?
+        .ok_or_else(role_not_found)?;
+    member.require_grantable(&parse_permissions(&role.permissions))?;
+    Ok(role)
+}
+
+/// Lists the built-in roles and the custom roles of the organization.
+#[axum::debug_handler]
+async fn get_roles(
+    ctx: Extension<AppState>,
+    member: OrganizationMember
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::OrganizationRead)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let custom_roles = get_custom_roles(&member.organization_id, &mut *tx).await.map_err(|_e|
+        error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch roles")
+    )?;
+    let built_in_roles: Vec<serde_json::Value> = Role::ALL.iter()
+        .map(|role| {
+            serde_json::json!({
+            "name": role.as_str(),
+            "permissions": role.permissions(),
+        })
+        })
+        .collect();
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "built_in_roles": built_in_roles,
+        "custom_roles": custom_roles,
+        "permissions": Permission::ALL,
+    })
+        )
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct NewRole {
+    name: String,
+    permissions: Vec<Permission>,
+}
+
+#[axum::debug_handler]
+async fn post_role(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Json(req): Json<NewRole>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::RolesManage)?;
+    member.require_grantable(&req.permissions)?;
+    let name = validate_name(&req.name)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let role = create_custom_role(
+        &member.organization_id,
+        name,
+        &permission_names(&req.permissions),
+        &mut *tx
+    ).await.map_err(|e| role_error(e, "Failed to create role"))?;
+    tx.commit().await.map_err(|e| role_error(e, "Failed to create role"))?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "role": role,
+    })
+        ),
+    ))
+}
+
+#[derive(serde::Deserialize)]
+struct RoleUpdate {
+    name: Option<String>,
+    permissions: Option<Vec<Permission>>,
+}
+
+/// Renames a custom role or replaces its permissions, which applies to every member with it.
+#[axum::debug_handler]
+async fn patch_role(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, role_id)): Path<(String, Uuid)>,
+    Json(req): Json<RoleUpdate>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::RolesManage)?;
+    let mut tx = member.begin_tenant(&ctx).await?;
+    find_manageable_role(&member, &role_id, &mut tx).await?;
+
+    let name = req.name.as_deref().map(validate_name).transpose()?;
+    let permissions = match &req.permissions {
+        Some(permissions) => {
+            member.require_grantable(permissions)?;
+            Some(permission_names(permissions))
+        }
+        None => None,
+    };
+
+    let role = update_custom_role(
+        &member.organization_id,
+        &role_id,
+        name,
+        permissions.as_deref(),
+        &mut *tx
+    ).await
+        .map_err(|e| role_error(e, "Failed to update role"))?
+        .ok_or_else(role_not_found)?;
+    tx.commit().await.map_err(|e| role_error(e, "Failed to update role"))?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "role": role,
+    })
+        )
+    )
+}
+
+#[axum::debug_handler]
+async fn delete_role(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, role_id)): Path<(String, Uuid)>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::RolesManage)?;
+    let mut tx = member.begin_tenant(&ctx).await?;
+    find_manageable_role(&member, &role_id, &mut tx).await?;
+
+    match delete_custom_role(&member.organization_id, &role_id, &mut *tx).await {
+        Ok(true) => {
+            tx.commit().await.map_err(|e| role_error(e, "Failed to delete role"))?;
+            Ok(Json(serde_json::json!({ "status": "ok" })))
+        }
+        Ok(false) => Err(role_not_found()),
+        Err(e) => Err(role_error(e, "Failed to delete role")),
+    }
+}
+
+/// Nested under `/organizations/:organization_id/roles`.
+pub fn router() -> Router {
+    Router::new()
+        .route("/", get(get_roles).post(post_role))
+        .route("/:role_id", patch(patch_role).delete(delete_role))
+        .layer(middleware::from_fn(auth))
+}
@@ -0,0 +1,371 @@
+use axum::extract::{ Path, Query };
+use axum::http::{ HeaderMap, StatusCode };
+use axum::response::{ IntoResponse, Response };
+use axum::routing::{ get, post };
+use axum::{ middleware, Extension, Json, Router };
+use log::{ error, warn };
+use uuid::Uuid;
+
+use crate::auth::authorization_middleware::auth_session;
+use crate::auth::organization_access::{ OrganizationMember, Permission, Role };
+use crate::auth::client_info::ClientInfo;
+use crate::auth::oidc::{
+    public_network::{ check_public_url, SSO_ALLOW_PRIVATE_NETWORKS },
+    Provider,
+};
+use crate::auth::opaque_token::random_string;
+use crate::auth::sso::{
+    email_domain,
+    has_verification_record,
+    normalize_domain,
+    sso_provider,
+    sso_redirect_uri,
+    verification_record_name,
+    verification_record_value,
+};
+use crate::db::audit::insert_audit_event;
+use crate::db::auth::revoke_sessions_without_sso;
+use crate::db::oidc::get_user_id_by_identity;
+use crate::db::organization::{ attach_user_to_organization, is_user_in_organization };
+use crate::db::sso::{
+    delete_organization_sso,
+    get_organization_sso,
+    get_organization_sso_by_domain,
+    mark_sso_domain_verified,
+    upsert_organization_sso,
+    OrganizationSso,
+    UpsertOrganizationSso,
+};
+use crate::AppState;
+
+use super::oidc::{
+    exchange_callback,
+    finish_login,
+    frontend_redirect,
+    link_or_create_user,
+    redirect_to_provider,
+    server_error,
+    CallbackQuery,
+};
+
+fn internal_error(message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": message,
+    });
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+fn sso_not_found() -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": "Single sign-on is not configured",
+    });
+    (StatusCode::NOT_FOUND, Json(error_response))
+}
+
+#[derive(serde::Deserialize)]
+struct StartQuery {
+    email: String,
+}
+
+/// Starts a login through the identity provider of the organization owning the email domain.
+#[axum::debug_handler]
+async fn start(
+    ctx: Extension<AppState>,
+    Query(query): Query<StartQuery>
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let Some(domain) = email_domain(&query.email) else {
+        return Err(sso_not_found());
+    };
+
+    let sso = get_organization_sso_by_domain(&domain, &ctx).await
+        .map_err(|_e| internal_error("Failed to fetch single sign-on settings"))?
+        .ok_or_else(sso_not_found)?;
+
+    redirect_to_provider(&sso_provider(&sso), &sso_redirect_uri(&sso.organization_id), &ctx).await
+}
+
+/// Logs in through the organization's identity provider. It is trusted for the verified domain
+/// only, new identities must have an email in it and are provisioned into the organization.
+async fn complete_login(
+    ctx: &AppState,
+    client: &ClientInfo,
+    organization_id: &Uuid,
+    headers: &HeaderMap,
+    query: CallbackQuery
+) -> Result<String, &'static str> {
+    let sso = get_organization_sso(organization_id, &ctx.db).await
+        .map_err(server_error)?
+        .filter(|sso| sso.domain_verified_at.is_some())
+        .ok_or("unknown_provider")?;
+    let provider = sso_provider(&sso);
+
+    let identity = exchange_callback(
+        &provider,
+        &sso_redirect_uri(organization_id),
+        headers,
+        query,
+        ctx
+    ).await?;
+
+    let user_id = match
+        get_user_id_by_identity(&provider.name, &identity.subject, ctx).await.map_err(server_error)?
+    {
+        Some(user_id) => user_id,
+        None => {
+            let email = identity.email
+                .filter(|email| email_domain(email).as_deref() == Some(sso.domain.as_str()))
+                .ok_or("email_domain_mismatch")?;
+            link_or_create_user(&provider.name, &identity.subject, &email, ctx).await?
+        }
+    };
+
+    if !is_user_in_organization(&user_id, organization_id, ctx).await.map_err(server_error)? {
+        let role = Role::Member.as_str();
+        attach_user_to_organization(&user_id, organization_id, role, &ctx.db).await.map_err(
+            server_error
+        )?;
+        insert_audit_event("sso_provisioned", Some(&user_id), &provider.name, ctx).await.map_err(
+            server_error
+        )?;
+    }
+
+    finish_login(&user_id, Some(organization_id), client, ctx).await
+}
+
+#[axum::debug_handler]
+async fn callback(
+    ctx: Extension<AppState>,
+    client: ClientInfo,
+    Path(organization_id): Path<Uuid>,
+    headers: HeaderMap,
+    Query(query): Query<CallbackQuery>
+) -> Response {
+    frontend_redirect(complete_login(&ctx, &client, &organization_id, &headers, query).await)
+}
+
+/// Logs out the members that did not log in through the organization's identity provider once
+/// enforcement takes effect. Their access tokens stay valid until they expire.
+async fn revoke_sessions_if_enforced(
+    sso: &OrganizationSso,
+    ctx: &AppState
+) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    if !sso.enforce_sso {
+        return Ok(());
+    }
+    revoke_sessions_without_sso(&sso.organization_id, ctx).await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("Failed to revoke sessions of organization {}: {}", sso.organization_id, e);
+            internal_error("Failed to log out members without single sign-on")
+        })
+}
+
+fn sso_response(sso: &OrganizationSso) -> Json<serde_json::Value> {
+    Json(
+        serde_json::json!({
+        "status": "ok",
+        "sso": sso,
+        "redirect_uri": sso_redirect_uri(&sso.organization_id),
+        "verification_record": {
+            "name": verification_record_name(&sso.domain),
+            "value": verification_record_value(&sso.domain_verification_token),
+        },
+    })
+    )
+}
+
+#[axum::debug_handler]
+async fn get_sso(
+    ctx: Extension<AppState>,
+    member: OrganizationMember
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::SsoManage)?;
+    let organization_id = member.organization_id;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let sso = get_organization_sso(&organization_id, &mut *tx).await
+        .map_err(|_e| internal_error("Failed to fetch single sign-on settings"))?
+        .ok_or_else(sso_not_found)?;
+
+    Ok(sso_response(&sso))
+}
+
+#[derive(serde::Deserialize)]
+struct SsoSettings {
+    domain: String,
+    issuer: String,
+    client_id: String,
+    client_secret: String,
+    #[serde(default)]
+    enforce_sso: bool,
+}
+
+/// Configures the OpenID identity provider of the organization. It only takes effect once the
+/// domain has been verified.
+#[axum::debug_handler]
+async fn put_sso(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Json(req): Json<SsoSettings>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::SsoManage)?;
+    let organization_id = member.organization_id;
+
+    let Some(domain) = normalize_domain(&req.domain) else {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Invalid domain",
+        });
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    };
+
+    let settings = UpsertOrganizationSso {
+        domain,
+        issuer: req.issuer,
+        client_id: req.client_id,
+        client_secret: req.client_secret,
+        enforce_sso: req.enforce_sso,
+    };
+
+    if !*SSO_ALLOW_PRIVATE_NETWORKS {
+        if let Err(e) = check_public_url(&settings.issuer) {
+            warn!("Rejected issuer for organization {}: {}", organization_id, e);
+            let error_response =
+                serde_json::json!({
+                "status": "error",
+                "message": "The issuer must be an https URL on a public address",
+            });
+            return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+        }
+    }
+
+    let provider = Provider::organization(
+        "sso",
+        &settings.issuer,
+        &settings.client_id,
+        &settings.client_secret
+    );
+    if let Err(e) = provider.check(ctx.oidc.http_for(&provider)).await {
+        error!("Invalid identity provider for organization {}: {}", organization_id, e);
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Failed to load the OpenID configuration of the issuer",
+        });
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let token = random_string(32);
+    let sso = match upsert_organization_sso(&organization_id, settings, &token, &mut *tx).await {
+        Ok(sso) => tx.commit().await.map(|_| sso),
+        Err(e) => Err(e),
+    }.map_err(|e| {
+        error!("Failed to save single sign-on settings: {}", e);
+        internal_error("Failed to save single sign-on settings")
+    })?;
+
+    if sso.domain_verified_at.is_some() {
+        revoke_sessions_if_enforced(&sso, &ctx).await?;
+    }
+
+    Ok(sso_response(&sso))
+}
+
+#[axum::debug_handler]
+async fn delete_sso(
+    ctx: Extension<AppState>,
+    member: OrganizationMember
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::SsoManage)?;
+    let organization_id = member.organization_id;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let deleted = match delete_organization_sso(&organization_id, &mut *tx).await {
+        Ok(deleted) => tx.commit().await.map(|_| deleted),
+        Err(e) => Err(e),
+    };
+    match deleted {
+        Ok(true) => Ok(Json(serde_json::json!({ "status": "ok" }))),
+        Ok(false) => Err(sso_not_found()),
+        Err(_e) => Err(internal_error("Failed to delete single sign-on settings")),
+    }
+}
+
+/// Checks the DNS verification record of the configured domain.
+#[axum::debug_handler]
+async fn verify_domain(
+    ctx: Extension<AppState>,
+    member: OrganizationMember
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::SsoManage)?;
+    let organization_id = member.organization_id;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let sso = get_organization_sso(&organization_id, &mut *tx).await
+        .map_err(|_e| internal_error("Failed to fetch single sign-on settings"))?
+        .ok_or_else(sso_not_found)?;
+
+    if sso.domain_verified_at.is_none() {
+        let found = has_verification_record(&sso.domain, &sso.domain_verification_token).await
+            .map_err(|e| {
+                error!("Failed to look up verification record of {}: {}", sso.domain, e);
+                let error_response =
+                    serde_json::json!({
+                    "status": "error",
+                    "message": "Failed to look up the verification record",
+                });
+                (StatusCode::BAD_GATEWAY, Json(error_response))
+            })?;
+
+        if !found {
+            let error_response =
+                serde_json::json!({
+                "status": "error",
+                "message": "Verification record not found",
+            });
+            return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+        }
+
+        let verified = match mark_sso_domain_verified(&organization_id, &mut *tx).await {
+            Ok(()) => tx.commit().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = verified {
+            if let Some(db_error) = e.as_database_error() {
+                if db_error.code().as_deref() == Some("23505") {
+                    let error_response =
+                        serde_json::json!({
+                        "status": "error",
+                        "message": "Domain is already verified by another organization",
+                    });
+                    return Err((StatusCode::CONFLICT, Json(error_response)));
+                }
+            }
+            return Err(internal_error("Failed to verify domain"));
+        }
+        revoke_sessions_if_enforced(&sso, &ctx).await?;
+    }
+
+    Ok(Json(serde_json::json!({ "status": "ok" })))
+}
+
+/// Login routes, nested under `/auth/sso`.
+pub fn router() -> Router {
+    Router::new()
+        .route("/start", get(start))
+        .route("/:organization_id/callback", get(callback))
+}
+
+/// Settings routes, nested under `/organizations/:organization_id/sso`.
+pub fn organization_router() -> Router {
+    Router::new()
+        .route("/", get(get_sso).put(put_sso).delete(delete_sso))
+        .route("/verify-domain", post(verify_domain))
+        .layer(middleware::from_fn(auth_session))
+}
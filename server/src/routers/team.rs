@@ -0,0 +1,317 @@
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::routing::{ get, put };
+use axum::{ middleware, Extension, Json };
+use axum::{ response::IntoResponse, Router };
+use log::error;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::authorization_middleware::auth;
+use crate::auth::organization_access::{ parse_permissions, OrganizationMember, Permission };
+use crate::db::team::{
+    add_team_member,
+    create_team,
+    delete_team,
+    get_team,
+    get_team_members,
+    get_teams,
+    remove_team_member,
+    update_team,
+    Team,
+};
+use crate::AppState;
+
+const MAX_NAME_LENGTH: usize = 64;
+
+fn error(status: StatusCode, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": message,
+    });
+    (status, Json(error_response))
+}
+
+fn team_not_found() -> (StatusCode, Json<serde_json::Value>) {
+    error(StatusCode::NOT_FOUND, "Team not found")
+}
+
+fn validate_name(name: &str) -> Result<&str, (StatusCode, Json<serde_json::Value>)> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err(error(StatusCode::BAD_REQUEST, "Invalid team name"));
+    }
+    Ok(name)
+}
+
+fn permission_names(permissions: &[Permission]) -> Vec<String> {
+    let mut names: Vec<String> = permissions
+        .iter()
+        .map(|permission| permission.as_str().to_string())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn team_error(e: sqlx::Error, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    match e.as_database_error().and_then(|db_error| db_error.code()).as_deref() {
+        Some("23505") => error(StatusCode::CONFLICT, "A team with this name already exists"),
+        _ => {
+            error!("{}: {:?}", message, e);
+            error(StatusCode::INTERNAL_SERVER_ERROR, message)
+        }
+    }
+}
+
+fn is_foreign_key_violation(e: &sqlx::Error) -> bool {
+    e.as_database_error().and_then(|db_error| db_error.code()).as_deref() == Some("23503")
+}
+
+/// Loads a team the member is allowed to change. Members of a sub-team also get the permissions
+/// of its parent, so the member needs those as well.
+async fn find_manageable_team(
+    member: &OrganizationMember,
+    team_id: &Uuid,
+    tx: &mut PgConnection
+) -> Result<Team, (StatusCode, Json<serde_json::Value>)> {
+    let team = get_team(&member.organization_id, team_id, &mut *tx).await
+        .map_err(|_e| error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch team"))?
+        .ok_or_else(team_not_found)?;
+    member.require_grantable(&parse_permissions(&team.permissions))?;
+
+    if let Some(parent_team_id) = &team.parent_team_id {
+        let parent = get_team(&member.organization_id, parent_team_id, &mut *tx).await
+            .map_err(|_e| error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch team"))?
+            .ok_or_else(team_not_found)?;
+        member.require_grantable(&parse_permissions(&parent.permissions))?;
+    }
+    Ok(team)
+}
+
+#[axum::debug_handler]
+async fn get_teams_handler(
+    ctx: Extension<AppState>,
+    member: OrganizationMember
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::OrganizationRead)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let teams = get_teams(&member.organization_id, &mut *tx).await.map_err(|_e|
+        error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch teams")
+    )?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "teams": teams,
+    })
+        )
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct NewTeam {
+    name: String,
+    parent_team_id: Option<Uuid>,
+    #[serde(default)]
+    permissions: Vec<Permission>,
+}
+
+/// Creates a team, or a sub-team below a top-level team.
+#[axum::debug_handler]
+async fn post_team(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Json(req): Json<NewTeam>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::TeamsManage)?;
+    member.require_grantable(&req.permissions)?;
+    let name = validate_name(&req.name)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    if let Some(parent_team_id) = &req.parent_team_id {
+        let parent = get_team(&member.organization_id, parent_team_id, &mut *tx).await
+            .map_err(|_e| error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch team"))?
+            .ok_or_else(team_not_found)?;
+        if parent.parent_team_id.is_some() {
+            return Err(
+                error(StatusCode::BAD_REQUEST, "Teams can only be nested one level deep")
+            );
+        }
+    }
+
+    let team = create_team(
+        &member.organization_id,
+        req.parent_team_id.as_ref(),
+        name,
+        &permission_names(&req.permissions),
+        &mut *tx
+    ).await.map_err(|e| team_error(e, "Failed to create team"))?;
+    tx.commit().await.map_err(|e| team_error(e, "Failed to create team"))?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "team": team,
+    })
+        ),
+    ))
+}
+
+/// The team together with its members.
+#[axum::debug_handler]
+async fn get_team_handler(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, team_id)): Path<(String, Uuid)>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::OrganizationRead)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let team = get_team(&member.organization_id, &team_id, &mut *tx).await
+        .map_err(|_e| error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch team"))?
+        .ok_or_else(team_not_found)?;
+    let members = get_team_members(&member.organization_id, &team_id, &mut *tx).await.map_err(
+        |_e| error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch team members")
+    )?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "team": team,
+        "members": members,
+    })
+        )
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct TeamUpdate {
+    name: Option<String>,
+    permissions: Option<Vec<Permission>>,
+}
+
+/// Renames a team or replaces its permissions, which applies to every member of the team and of
+/// its sub-teams.
+#[axum::debug_handler]
+async fn patch_team(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, team_id)): Path<(String, Uuid)>,
+    Json(req): Json<TeamUpdate>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::TeamsManage)?;
+    let mut tx = member.begin_tenant(&ctx).await?;
+    find_manageable_team(&member, &team_id, &mut tx).await?;
+
+    let name = req.name.as_deref().map(validate_name).transpose()?;
+    let permissions = match &req.permissions {
+        Some(permissions) => {
+            member.require_grantable(permissions)?;
+            Some(permission_names(permissions))
+        }
+        None => None,
+    };
+
+    let team = update_team(
+        &member.organization_id,
+        &team_id,
+        name,
+        permissions.as_deref(),
+        &mut *tx
+    ).await
+        .map_err(|e| team_error(e, "Failed to update team"))?
+        .ok_or_else(team_not_found)?;
+    tx.commit().await.map_err(|e| team_error(e, "Failed to update team"))?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "team": team,
+    })
+        )
+    )
+}
+
+#[axum::debug_handler]
+async fn delete_team_handler(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, team_id)): Path<(String, Uuid)>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::TeamsManage)?;
+    let mut tx = member.begin_tenant(&ctx).await?;
+    find_manageable_team(&member, &team_id, &mut tx).await?;
+
+    match delete_team(&member.organization_id, &team_id, &mut *tx).await {
+        Ok(true) => {
+            tx.commit().await.map_err(|e| team_error(e, "Failed to delete team"))?;
+            Ok(Json(serde_json::json!({ "status": "ok" })))
+        }
+        Ok(false) => Err(team_not_found()),
+        Err(e) if is_foreign_key_violation(&e) => {
+            Err(error(StatusCode::CONFLICT, "Delete the sub-teams of the team first"))
+        }
+        Err(e) => Err(team_error(e, "Failed to delete team")),
+    }
+}
+
+/// Adds a member of the organization to the team. It grants the team's permissions, so the
+/// caller has to have them.
+#[axum::debug_handler]
+async fn put_team_member(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, team_id, user_id)): Path<(String, Uuid, Uuid)>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::TeamsManage)?;
+    let mut tx = member.begin_tenant(&ctx).await?;
+    find_manageable_team(&member, &team_id, &mut tx).await?;
+
+    match add_team_member(&member.organization_id, &team_id, &user_id, &mut *tx).await {
+        Ok(()) => {
+            tx.commit().await.map_err(|e| team_error(e, "Failed to add team member"))?;
+            Ok(Json(serde_json::json!({ "status": "ok" })))
+        }
+        Err(e) if is_foreign_key_violation(&e) => {
+            Err(error(StatusCode::NOT_FOUND, "Member not found"))
+        }
+        Err(e) => Err(team_error(e, "Failed to add team member")),
+    }
+}
+
+#[axum::debug_handler]
+async fn delete_team_member(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, team_id, user_id)): Path<(String, Uuid, Uuid)>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::TeamsManage)?;
+    let mut tx = member.begin_tenant(&ctx).await?;
+    find_manageable_team(&member, &team_id, &mut tx).await?;
+
+    match remove_team_member(&member.organization_id, &team_id, &user_id, &mut *tx).await {
+        Ok(true) => {
+            tx.commit().await.map_err(|e| team_error(e, "Failed to remove team member"))?;
+            Ok(Json(serde_json::json!({ "status": "ok" })))
+        }
+        Ok(false) => Err(error(StatusCode::NOT_FOUND, "Member not found")),
+        Err(e) => Err(team_error(e, "Failed to remove team member")),
+    }
+}
+
+/// Nested under `/organizations/:organization_id/teams`.
+pub fn router() -> Router {
+    Router::new()
+        .route("/", get(get_teams_handler).post(post_team))
+        .route("/:team_id", get(get_team_handler).patch(patch_team).delete(delete_team_handler))
+        .route("/:team_id/members/:user_id", put(put_team_member).delete(delete_team_member))
+        .layer(middleware::from_fn(auth))
+}
@@ -0,0 +1,322 @@
+use axum::extract::{ Path, Query };
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{ middleware, Extension, Json };
+use axum::{ response::IntoResponse, Router };
+use chrono::{ DateTime, Utc };
+use log::error;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::api_key::Scope;
+use crate::auth::authorization_middleware::auth;
+use crate::auth::organization_access::{ OrganizationMember, Permission };
+use crate::billing::RateCard;
+use crate::db::project::get_project;
+use crate::db::time_entry::{
+    create_time_entry,
+    delete_time_entry,
+    get_time_entries,
+    get_time_entry,
+    update_time_entry,
+    TimeEntryFields,
+    TimeEntryFilter,
+};
+use crate::models::time_entry::TimeEntry;
+use crate::realtime::{ publish_event, Event };
+use crate::routers::project::deserialize_nullable;
+use crate::AppState;
+
+fn error(status: StatusCode, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": message,
+    });
+    (status, Json(error_response))
+}
+
+fn entry_not_found() -> (StatusCode, Json<serde_json::Value>) {
+    error(StatusCode::NOT_FOUND, "Time entry not found")
+}
+
+fn entry_error(e: sqlx::Error, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    error!("{}: {:?}", message, e);
+    error(StatusCode::INTERNAL_SERVER_ERROR, message)
+}
+
+/// Checks the fields an entry is about to be saved with. Running entries cannot start in the
+/// future. Entries can stay on the project they were on when it got archived, but no time can be
+/// moved onto an archived project.
+pub async fn validate_entry(
+    member: &OrganizationMember,
+    entry: &TimeEntryFields,
+    previous_project_id: Option<&Uuid>,
+    tx: &mut PgConnection
+) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    if entry.ended_at.is_some_and(|ended_at| ended_at <= entry.started_at) {
+        return Err(error(StatusCode::BAD_REQUEST, "The entry has to end after it starts"));
+    }
+    if entry.ended_at.is_none() && entry.started_at > Utc::now().naive_utc() {
+        return Err(error(StatusCode::BAD_REQUEST, "A running entry cannot start in the future"));
+    }
+    let new_project_id = entry.project_id.as_ref().filter(|id| Some(*id) != previous_project_id);
+    if let Some(project_id) = new_project_id {
+        let project = get_project(&member.organization_id, project_id, tx).await
+            .map_err(|_e| error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch project"))?
+            .ok_or_else(|| error(StatusCode::NOT_FOUND, "Project not found"))?;
+        if project.archived_at.is_some() {
+            return Err(error(StatusCode::BAD_REQUEST, "Project is archived"));
+        }
+    }
+    Ok(())
+}
+
+async fn load_rates(
+    member: &OrganizationMember,
+    tx: &mut PgConnection
+) -> Result<RateCard, (StatusCode, Json<serde_json::Value>)> {
+    RateCard::load(&member.organization_id, tx).await.map_err(|e|
+        entry_error(e, "Failed to fetch rates")
+    )
+}
+
+/// Loads an entry of the member, or of anyone for members who manage time. Other entries are
+/// reported as missing.
+async fn find_entry(
+    member: &OrganizationMember,
+    entry_id: &Uuid,
+    tx: &mut PgConnection
+) -> Result<TimeEntry, (StatusCode, Json<serde_json::Value>)> {
+    get_time_entry(&member.organization_id, entry_id, tx).await
+        .map_err(|_e| error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch time entry"))?
+        .filter(|entry| {
+            entry.user_id == member.auth.user.id || member.has_permission(Permission::TimeManage)
+        })
+        .ok_or_else(entry_not_found)
+}
+
+/// Changing entries needs `time:track` for one's own and `time:manage` for everybody else's.
+fn require_editable(
+    member: &OrganizationMember,
+    entry: &TimeEntry
+) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    if entry.user_id == member.auth.user.id {
+        member.require(Permission::TimeTrack)
+    } else {
+        member.require(Permission::TimeManage)
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct TimeEntryQuery {
+    user_id: Option<Uuid>,
+    project_id: Option<Uuid>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+/// Lists the caller's entries, or with `time:manage` those of any or all members.
+#[axum::debug_handler]
+async fn get_time_entries_handler(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Query(query): Query<TimeEntryQuery>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.auth.require_scope(Scope::TimeRead)?;
+
+    let user_id = if member.has_permission(Permission::TimeManage) {
+        query.user_id
+    } else if query.user_id.is_none_or(|user_id| user_id == member.auth.user.id) {
+        Some(member.auth.user.id)
+    } else {
+        return Err(error(StatusCode::FORBIDDEN, "Missing the time:manage permission"));
+    };
+    let filter = TimeEntryFilter {
+        user_id,
+        project_id: query.project_id,
+        from: query.from.map(|from| from.naive_utc()),
+        to: query.to.map(|to| to.naive_utc()),
+    };
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let entries = get_time_entries(&member.organization_id, &filter, &mut *tx).await.map_err(
+        |_e| error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch time entries")
+    )?;
+    let rates = load_rates(&member, &mut tx).await?;
+    let entries: Vec<_> = entries
+        .into_iter()
+        .map(|entry| rates.bill(entry))
+        .collect();
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "time_entries": entries,
+    })
+        )
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct NewTimeEntry {
+    project_id: Option<Uuid>,
+    started_at: DateTime<Utc>,
+    ended_at: DateTime<Utc>,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    billable: bool,
+}
+
+/// Records time the caller spent.
+#[axum::debug_handler]
+async fn post_time_entry(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Json(req): Json<NewTimeEntry>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::TimeTrack)?;
+    let fields = TimeEntryFields {
+        project_id: req.project_id,
+        started_at: req.started_at.naive_utc(),
+        ended_at: Some(req.ended_at.naive_utc()),
+        description: req.description.trim().to_string(),
+        billable: req.billable,
+    };
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    validate_entry(&member, &fields, None, &mut tx).await?;
+    let entry = create_time_entry(&member.organization_id, &member.auth.user.id, &fields, &mut *tx)
+        .await
+        .map_err(|e| entry_error(e, "Failed to create time entry"))?;
+    let rates = load_rates(&member, &mut tx).await?;
+    tx.commit().await.map_err(|e| entry_error(e, "Failed to create time entry"))?;
+    publish_event(Event::TimeEntryCreated { time_entry: entry.clone() }, &ctx).await;
+    let entry = rates.bill(entry);
+
+    Ok((
+        StatusCode::CREATED,
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "time_entry": entry,
+    })
+        ),
+    ))
+}
+
+#[axum::debug_handler]
+async fn get_time_entry_handler(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, entry_id)): Path<(String, Uuid)>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.auth.require_scope(Scope::TimeRead)?;
+
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let entry = find_entry(&member, &entry_id, &mut tx).await?;
+    let entry = load_rates(&member, &mut tx).await?.bill(entry);
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "time_entry": entry,
+    })
+        )
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct TimeEntryUpdate {
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    project_id: Option<Option<Uuid>>,
+    started_at: Option<DateTime<Utc>>,
+    ended_at: Option<DateTime<Utc>>,
+    description: Option<String>,
+    billable: Option<bool>,
+}
+
+/// Changes the given fields of an entry, the others keep their values. A `null` project takes the
+/// entry off its project. Setting the end of a running timer stops it.
+#[axum::debug_handler]
+async fn patch_time_entry(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, entry_id)): Path<(String, Uuid)>,
+    Json(req): Json<TimeEntryUpdate>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let entry = find_entry(&member, &entry_id, &mut tx).await?;
+    require_editable(&member, &entry)?;
+
+    let fields = TimeEntryFields {
+        project_id: req.project_id.unwrap_or(entry.project_id),
+        started_at: req.started_at.map_or(entry.started_at, |started_at| started_at.naive_utc()),
+        ended_at: req.ended_at.map(|ended_at| ended_at.naive_utc()).or(entry.ended_at),
+        description: req.description.map_or(entry.description, |description| {
+            description.trim().to_string()
+        }),
+        billable: req.billable.unwrap_or(entry.billable),
+    };
+    validate_entry(&member, &fields, entry.project_id.as_ref(), &mut tx).await?;
+
+    let was_running = entry.ended_at.is_none();
+    let entry = update_time_entry(&member.organization_id, &entry_id, &fields, &mut *tx).await
+        .map_err(|e| entry_error(e, "Failed to update time entry"))?
+        .ok_or_else(entry_not_found)?;
+    let rates = load_rates(&member, &mut tx).await?;
+    tx.commit().await.map_err(|e| entry_error(e, "Failed to update time entry"))?;
+
+    let time_entry = entry.clone();
+    let event = if was_running && entry.ended_at.is_some() {
+        Event::TimerStopped { time_entry }
+    } else {
+        Event::TimeEntryUpdated { time_entry }
+    };
+    publish_event(event, &ctx).await;
+    let entry = rates.bill(entry);
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "time_entry": entry,
+    })
+        )
+    )
+}
+
+#[axum::debug_handler]
+async fn delete_time_entry_handler(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Path((_, entry_id)): Path<(String, Uuid)>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let mut tx = member.begin_tenant(&ctx).await?;
+    let entry = find_entry(&member, &entry_id, &mut tx).await?;
+    require_editable(&member, &entry)?;
+
+    match delete_time_entry(&member.organization_id, &entry_id, &mut *tx).await {
+        Ok(true) => {
+            tx.commit().await.map_err(|e| entry_error(e, "Failed to delete time entry"))?;
+            publish_event(Event::TimeEntryDeleted { time_entry: entry }, &ctx).await;
+            Ok(Json(serde_json::json!({ "status": "ok" })))
+        }
+        Ok(false) => Err(entry_not_found()),
+        Err(e) => Err(entry_error(e, "Failed to delete time entry")),
+    }
+}
+
+/// Nested under `/organizations/:organization_id/time-entries`.
+pub fn router() -> Router {
+    Router::new()
+        .route("/", get(get_time_entries_handler).post(post_time_entry))
+        .route(
+            "/:entry_id",
+            get(get_time_entry_handler).patch(patch_time_entry).delete(delete_time_entry_handler)
+        )
+        .layer(middleware::from_fn(auth))
+}
@@ -0,0 +1,290 @@
+use axum::http::StatusCode;
+use axum::routing::{ get, post };
+use axum::{ middleware, Extension, Json };
+use axum::{ response::IntoResponse, Router };
+use chrono::Utc;
+use log::error;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::auth::api_key::Scope;
+use crate::auth::authorization_middleware::{ auth, AuthExtension };
+use crate::auth::organization_access::{ OrganizationMember, Permission };
+use crate::billing::{ BilledTimeEntry, RateCard };
+use crate::db::time_entry::{
+    create_time_entry,
+    get_running_time_entry,
+    stop_running_time_entry,
+    TimeEntryFields,
+};
+use crate::models::time_entry::TimeEntry;
+use crate::realtime::{ publish_event, Event };
+use crate::AppState;
+
+use super::time_entry::validate_entry;
+
+fn error(status: StatusCode, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    let error_response =
+        serde_json::json!({
+        "status": "error",
+        "message": message,
+    });
+    (status, Json(error_response))
+}
+
+fn timer_error(e: sqlx::Error, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    match e.as_database_error().and_then(|db_error| db_error.code()).as_deref() {
+        Some("23505") =>
+            error(StatusCode::CONFLICT, "Another timer was started at the same time"),
+        _ => {
+            error!("{}: {:?}", message, e);
+            error(StatusCode::INTERNAL_SERVER_ERROR, message)
+        }
+    }
+}
+
+/// Prices the entry with the rates of its organization, which can be another one than that of
+/// the request.
+async fn bill(
+    entry: TimeEntry,
+    connection: &mut PgConnection
+) -> Result<BilledTimeEntry, (StatusCode, Json<serde_json::Value>)> {
+    let rates = RateCard::load(&entry.organization_id, connection).await.map_err(|e|
+        timer_error(e, "Failed to fetch rates")
+    )?;
+    Ok(rates.bill(entry))
+}
+
+#[derive(serde::Deserialize)]
+struct StartTimer {
+    project_id: Option<Uuid>,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    billable: bool,
+}
+
+/// Starts a timer in the organization of the `X-Organization-Id` header. A timer that is still
+/// running is stopped first, wherever it was started, as long as the credential can access its
+/// organization.
+#[axum::debug_handler]
+async fn start_timer(
+    ctx: Extension<AppState>,
+    member: OrganizationMember,
+    Json(req): Json<StartTimer>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    member.require(Permission::TimeTrack)?;
+    let user_id = member.auth.user.id;
+    let now = Utc::now().naive_utc();
+    let fields = TimeEntryFields {
+        project_id: req.project_id,
+        started_at: now,
+        ended_at: None,
+        description: req.description.trim().to_string(),
+        billable: req.billable,
+    };
+    validate_entry(&member, &fields, None, &mut *member.begin_tenant(&ctx).await?).await?;
+
+    // The running timer may belong to another organization, so this cannot be limited to the
+    // tenant. Both queries only touch the entries of the user.
+    let mut tx = ctx.db.begin().await.map_err(|e| timer_error(e, "Failed to start timer"))?;
+    let stopped = stop_running_time_entry(&user_id, now, &mut *tx).await.map_err(|e|
+        timer_error(e, "Failed to stop the running timer")
+    )?;
+    // Like in `stop_timer`, a timer the credential cannot access is left running by rolling back.
+    if let Some(stopped) = &stopped {
+        if !member.auth.can_access_organization(&stopped.organization_id) {
+            return Err(
+                error(
+                    StatusCode::CONFLICT,
+                    "A timer is running in an organization this API key cannot access"
+                )
+            );
+        }
+    }
+    let started = create_time_entry(&member.organization_id, &user_id, &fields, &mut *tx).await
+        .map_err(|e| timer_error(e, "Failed to start timer"))?;
+    let billed_started = bill(started.clone(), &mut tx).await?;
+    let billed_stopped = match stopped.clone() {
+        Some(stopped) => Some(bill(stopped, &mut tx).await?),
+        None => None,
+    };
+    tx.commit().await.map_err(|e| timer_error(e, "Failed to start timer"))?;
+
+    if let Some(stopped) = stopped {
+        publish_event(Event::TimerStopped { time_entry: stopped }, &ctx).await;
+    }
+    publish_event(Event::TimerStarted { time_entry: started }, &ctx).await;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "time_entry": billed_started,
+        "stopped_time_entry": billed_stopped,
+    })
+        ),
+    ))
+}
+
+/// Stops the running timer of the user.
+#[axum::debug_handler]
+async fn stop_timer(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    auth.require_scope(Scope::TimeWrite)?;
+
+    // Stopped and checked in one transaction, a timer of an organization the credential cannot
+    // access is left running by rolling back.
+    let mut tx = ctx.db.begin().await.map_err(|e| timer_error(e, "Failed to stop timer"))?;
+    let stopped = stop_running_time_entry(&auth.user.id, Utc::now().naive_utc(), &mut *tx).await
+        .map_err(|e| timer_error(e, "Failed to stop timer"))?
+        .filter(|entry| auth.can_access_organization(&entry.organization_id))
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "No timer is running"))?;
+    let billed = bill(stopped.clone(), &mut tx).await?;
+    tx.commit().await.map_err(|e| timer_error(e, "Failed to stop timer"))?;
+    publish_event(Event::TimerStopped { time_entry: stopped }, &ctx).await;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "time_entry": billed,
+    })
+        )
+    )
+}
+
+/// The running timer of the user, `null` if there is none.
+#[axum::debug_handler]
+async fn get_current_timer(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    auth.require_scope(Scope::TimeRead)?;
+
+    let running = get_running_time_entry(&auth.user.id, &ctx.db).await
+        .map_err(|e| timer_error(e, "Failed to fetch the running timer"))?
+        .filter(|entry| auth.can_access_organization(&entry.organization_id));
+    let running = match running {
+        Some(entry) => {
+            let mut connection = ctx.db.acquire().await.map_err(|e|
+                timer_error(e, "Failed to fetch rates")
+            )?;
+            Some(bill(entry, &mut connection).await?)
+        }
+        None => None,
+    };
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "ok",
+        "time_entry": running,
+    })
+        )
+    )
+}
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/start", post(start_timer))
+        .route("/stop", post(stop_timer))
+        .route("/current", get(get_current_timer))
+        .layer(middleware::from_fn(auth))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{ Method, StatusCode };
+    use chrono::Utc;
+    use sqlx::PgPool;
+    use uuid::Uuid;
+
+    use super::timer_error;
+    use crate::db::time_entry::{ create_time_entry, TimeEntryFields };
+    use crate::test_support::{ app, app_state, create_organization, request, send, sign_up };
+
+    fn running_entry() -> TimeEntryFields {
+        TimeEntryFields {
+            project_id: None,
+            started_at: Utc::now().naive_utc(),
+            ended_at: None,
+            description: String::new(),
+            billable: false,
+        }
+    }
+
+    #[sqlx::test]
+    async fn starting_a_timer_stops_the_running_one(db: PgPool) {
+        let app = app(&app_state(db));
+        let (token, _) = sign_up(&app, "jane@example.com").await;
+        let organization_id = create_organization(&app, &token, "Acme").await;
+        let start = || {
+            request(Method::POST, "/api/timers/start", Some(&token)).header(
+                "x-organization-id",
+                &organization_id
+            )
+        };
+
+        let (status, first) = send(&app, start(), Some(serde_json::json!({}))).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", first);
+        assert!(first["stopped_time_entry"].is_null());
+        let (status, second) = send(&app, start(), Some(serde_json::json!({}))).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", second);
+
+        assert_eq!(second["stopped_time_entry"]["id"], first["time_entry"]["id"]);
+        assert!(second["stopped_time_entry"]["ended_at"].is_string());
+        let current = request(Method::GET, "/api/timers/current", Some(&token));
+        let (_, current) = send(&app, current, None).await;
+        assert_eq!(current["time_entry"]["id"], second["time_entry"]["id"]);
+    }
+
+    #[sqlx::test]
+    async fn concurrent_starts_conflict(db: PgPool) {
+        let app = app(&app_state(db.clone()));
+        let (token, _) = sign_up(&app, "jane@example.com").await;
+        let organization_id = create_organization(&app, &token, "Acme").await;
+        let organization_id = Uuid::parse_str(&organization_id).unwrap();
+        let (_, me) = send(&app, request(Method::GET, "/api/users/me", Some(&token)), None).await;
+        let user_id = Uuid::parse_str(me["id"].as_str().unwrap()).unwrap();
+
+        // Both requests found no running timer to stop, the second insert waits for the first.
+        let mut first = db.begin().await.unwrap();
+        create_time_entry(&organization_id, &user_id, &running_entry(), &mut *first).await
+            .unwrap();
+        let second = tokio::spawn(async move {
+            let mut second = db.begin().await.unwrap();
+            create_time_entry(&organization_id, &user_id, &running_entry(), &mut *second).await
+        });
+        first.commit().await.unwrap();
+
+        let Err(e) = second.await.unwrap() else { panic!("The second timer was started") };
+        let constraint = e.as_database_error().and_then(|db_error| db_error.constraint());
+        assert_eq!(constraint, Some("unique_running_time_entry"));
+        assert_eq!(timer_error(e, "Failed to start timer").0, StatusCode::CONFLICT);
+    }
+
+    #[sqlx::test]
+    async fn stopping_without_a_running_timer_is_not_found(db: PgPool) {
+        let app = app(&app_state(db));
+        let (token, _) = sign_up(&app, "jane@example.com").await;
+        let stop = || request(Method::POST, "/api/timers/stop", Some(&token));
+
+        let (status, _) = send(&app, stop(), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let organization_id = create_organization(&app, &token, "Acme").await;
+        let start = request(Method::POST, "/api/timers/start", Some(&token)).header(
+            "x-organization-id",
+            &organization_id
+        );
+        send(&app, start, Some(serde_json::json!({}))).await;
+        let (status, _) = send(&app, stop(), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, stop(), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
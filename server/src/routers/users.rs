@@ -1,26 +1,26 @@
 use axum::extract::Path;
 use axum::http::StatusCode;
 use axum::{ middleware, Extension, Json };
-use axum::{ response::IntoResponse, routing::get, Router };
+use axum::{ response::IntoResponse, routing::{ get, patch, post }, Router };
 use log::error;
 use uuid::Uuid;
 use sqlx::{ query_as, query };
 use pwhash::bcrypt;
 
 use crate::auth::access_token::encode_jwt;
-use crate::auth::authorization_middleware::{ auth, AuthExtension };
-use crate::db::auth::create_refresh_token;
-use crate::db::organization::{ self, get_orgs_by_user_id };
-use crate::db::user::create_user;
+use crate::auth::api_key::Scope;
+use crate::auth::authorization_middleware::{ auth_allow_unverified, auth_session, AuthExtension };
+use crate::auth::client_info::ClientInfo;
+use crate::auth::revocation::access_token_expiry;
+use crate::db::auth::{ create_refresh_token, insert_revoked_access_token, revoke_other_sessions };
+use crate::db::email_verification::{ create_email_verification_token, verify_email_with_token };
+use crate::db::organization::get_orgs_by_user_id;
+use crate::db::user::{ create_user, email_exists, update_user_password };
+use crate::mailer::{ app_url, Email };
+use super::invitation::{ accept, find_invitation_for_email };
 use crate::models::user::User;
 use crate::AppState;
 
-#[derive(serde::Serialize, serde::Deserialize)]
-struct ReturnUsers<T> {
-    count: usize,
-    users: Vec<T>,
-}
-
 #[derive(serde::Serialize, serde::Deserialize)]
 struct ReturnUser {
     id: Uuid,
@@ -34,6 +34,9 @@ struct CreateReturnUser {
     email: String,
     refreshToken: String,
     accessToken: String,
+    /// The organization joined through the invitation the user registered with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    organizationId: Option<Uuid>,
 }
 
 #[axum::debug_handler]
@@ -68,16 +71,42 @@ async fn get_users(
     Ok(Json(json_response))
 }
 
+/// Emails a link proving ownership of `email`, which becomes the user's verified email.
+async fn send_verification_email(
+    user_id: &Uuid,
+    email: &str,
+    ctx: &AppState
+) -> Result<(), String> {
+    let token = create_email_verification_token(user_id, email, ctx).await.map_err(|e|
+        e.to_string()
+    )?;
+
+    let link = format!("{}/verify-email?token={}", app_url(), token);
+    ctx.mailer.send(Email {
+        to: email.to_string(),
+        subject: "Verify your tick-tack email".to_string(),
+        body: format!("Use the link below to verify your email address.\n\n{}", link),
+    }).await
+}
+
 #[derive(serde::Deserialize, Clone)]
 struct NewUser {
     email: String,
     password: String,
+    /// Token of an invitation sent to `email`, which is accepted right away.
+    invitation_token: Option<String>,
 }
 
 async fn post_users(
     ctx: Extension<AppState>,
+    client: ClientInfo,
     Json(req): Json<NewUser>
 ) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let invitation = match &req.invitation_token {
+        Some(token) => Some(find_invitation_for_email(token, &req.email, &ctx).await?),
+        None => None,
+    };
+
     let hashed_password = bcrypt::hash(req.password).map_err(|_e| {
         let error_response =
             serde_json::json!({
@@ -89,36 +118,59 @@ async fn post_users(
 
     let new_user = match create_user(&req.email, &hashed_password, &ctx).await {
         Ok(id) => {
-            let access_token = match encode_jwt(id.to_string(), req.email.clone()) {
+            let refresh_token = match create_refresh_token(id, &client, None, &ctx).await {
                 Ok(token) => token,
-                Err(e) => {
+                Err(_e) => {
                     let error_response =
                         serde_json::json!({
                         "status": "error",
-                        "message": format!("JWT error: {}", e),
+                        "message": format!("Failed to create refresh token"),
                     });
+
                     return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)));
                 }
             };
 
-            let refresh_token = match create_refresh_token(id, &ctx).await {
+            let access_token = match
+                encode_jwt(
+                    &ctx.keys,
+                    id.to_string(),
+                    req.email.clone(),
+                    refresh_token.session_id.to_string()
+                )
+            {
                 Ok(token) => token,
                 Err(e) => {
                     let error_response =
                         serde_json::json!({
                         "status": "error",
-                        "message": format!("Failed to create refresh token"),
+                        "message": format!("JWT error: {}", e),
                     });
-
                     return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)));
                 }
             };
 
+            // Accepting the invitation verifies the email, the token was sent to it.
+            let organization_id = match &invitation {
+                Some(invitation) =>
+                    accept(invitation, &id, &ctx).await
+                        .map_err(|(_, Json(e))| error!("Failed to accept invitation: {}", e))
+                        .ok(),
+                None => None,
+            };
+
+            if organization_id.is_none() {
+                if let Err(e) = send_verification_email(&id, &req.email, &ctx).await {
+                    error!("Failed to send verification email: {}", e);
+                }
+            }
+
             CreateReturnUser {
-                id: id,
+                id,
                 email: req.email,
-                refreshToken: refresh_token,
+                refreshToken: refresh_token.token,
                 accessToken: access_token,
+                organizationId: organization_id,
             }
         }
         Err(e) => {
@@ -184,9 +236,15 @@ async fn me(
     ctx: Extension<AppState>,
     auth: Extension<AuthExtension>
 ) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    auth.require_scope(Scope::UserRead)?;
+
     let organizations = match get_orgs_by_user_id(&auth.user.id, &ctx).await {
-        Ok(orgs) => orgs,
-        Err(e) => {
+        Ok(orgs) =>
+            orgs
+                .into_iter()
+                .filter(|org| auth.can_access_organization(&org.id))
+                .collect::<Vec<_>>(),
+        Err(_e) => {
             let error_response =
                 serde_json::json!({
             "status": "error",
@@ -201,15 +259,377 @@ async fn me(
             serde_json::json!({
         "id": auth.user.id,
         "email": auth.user.email,
+        "email_verified": auth.user.email_verified_at.is_some(),
         "organizations": organizations,
     })
         )
     )
 }
 
+#[derive(serde::Deserialize)]
+struct VerifyEmailRequest {
+    token: String,
+}
+
+async fn verify_email(
+    ctx: Extension<AppState>,
+    Json(req): Json<VerifyEmailRequest>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    match verify_email_with_token(&req.token, &ctx).await {
+        Ok(Some(_)) =>
+            Ok(
+                Json(
+                    serde_json::json!({
+                "status": "success",
+                "message": "Email verified",
+            })
+                )
+            ),
+        Ok(None) => {
+            let error_response =
+                serde_json::json!({
+                "status": "error",
+                "message": "Invalid or expired verification token",
+            });
+            Err((StatusCode::BAD_REQUEST, Json(error_response)))
+        }
+        Err(e) => {
+            error!("Failed to verify email: {:?}", e);
+            let error_response = match &e {
+                sqlx::Error::Database(db_err) if db_err.code().as_deref() == Some("23505") => {
+                    (
+                        StatusCode::CONFLICT,
+                        Json(
+                            serde_json::json!({
+                        "status": "error",
+                        "message": "Email already exists",
+                    })
+                        ),
+                    )
+                }
+                _ => {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(
+                            serde_json::json!({
+                        "status": "error",
+                        "message": "Failed to verify email",
+                            })
+                        ),
+                    )
+                }
+            };
+            Err(error_response)
+        }
+    }
+}
+
+async fn resend_verification_email(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    auth.session()?;
+    if auth.user.email_verified_at.is_some() {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Email is already verified",
+        });
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+
+    send_verification_email(&auth.user.id, &auth.user.email, &ctx).await.map_err(|e| {
+        error!("Failed to send verification email: {}", e);
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Failed to send verification email",
+        });
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "success",
+        "message": "Verification email sent",
+    })
+        )
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct ChangePasswordRequest {
+    current_password: String,
+    new_password: String,
+}
+
+/// Changes the password and logs out every other session. The access token used for the request
+/// is revoked as well, the client has to refresh it with its refresh token.
+async fn change_password(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>,
+    Json(req): Json<ChangePasswordRequest>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let session = auth.session()?;
+    if !auth.user.verify_password(&req.current_password) {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Invalid password",
+        });
+        return Err((StatusCode::UNAUTHORIZED, Json(error_response)));
+    }
+
+    let hashed_password = bcrypt::hash(req.new_password).map_err(|_e| {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Failed to hash password",
+        });
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })?;
+
+    // All or nothing, the new password must not be set while the old sessions stay valid.
+    let expires_at = access_token_expiry(session.exp);
+    let result = async {
+        let mut tx = ctx.db.begin().await?;
+        update_user_password(&auth.user.id, &hashed_password, &mut *tx).await?;
+        revoke_other_sessions(&auth.user.id, &session.session_id, &mut *tx).await?;
+        insert_revoked_access_token(&session.jti, &auth.user.id, expires_at, &mut *tx).await?;
+        tx.commit().await
+    }.await;
+
+    if let Err(e) = result {
+        error!("Failed to change password: {:?}", e);
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Failed to change password",
+        });
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)));
+    }
+    ctx.revoked_access_tokens.insert(session.jti, expires_at);
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "success",
+        "message": "Password changed",
+    })
+        )
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct ChangeEmailRequest {
+    email: String,
+    password: String,
+}
+
+/// Sends a verification link to the new address, the email only changes once it is verified.
+async fn change_email(
+    ctx: Extension<AppState>,
+    auth: Extension<AuthExtension>,
+    Json(req): Json<ChangeEmailRequest>
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    if !auth.user.verify_password(&req.password) {
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Invalid password",
+        });
+        return Err((StatusCode::UNAUTHORIZED, Json(error_response)));
+    }
+
+    match email_exists(&req.email, &ctx).await {
+        Ok(false) => {}
+        Ok(true) => {
+            let error_response =
+                serde_json::json!({
+                "status": "error",
+                "message": "Email already exists",
+            });
+            return Err((StatusCode::CONFLICT, Json(error_response)));
+        }
+        Err(_e) => {
+            let error_response =
+                serde_json::json!({
+                "status": "error",
+                "message": "Failed to change email",
+            });
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)));
+        }
+    }
+
+    send_verification_email(&auth.user.id, &req.email, &ctx).await.map_err(|e| {
+        error!("Failed to send verification email: {}", e);
+        let error_response =
+            serde_json::json!({
+            "status": "error",
+            "message": "Failed to send verification email",
+        });
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })?;
+
+    Ok(
+        Json(
+            serde_json::json!({
+        "status": "success",
+        "message": "Verification email sent to the new address",
+    })
+        )
+    )
+}
+
 pub fn router() -> Router {
     Router::new()
         .route("/", get(get_users).post(post_users))
         .route("/:user_id", axum::routing::delete(delete_user))
-        .route("/me", get(me).layer(middleware::from_fn(auth)))
+        .route("/me", get(me).layer(middleware::from_fn(auth_allow_unverified)))
+        .route("/me/password", patch(change_password).layer(middleware::from_fn(auth_session)))
+        .route("/me/email", patch(change_email).layer(middleware::from_fn(auth_session)))
+        .route("/verify-email", post(verify_email))
+        .route(
+            "/verify-email/resend",
+            post(resend_verification_email).layer(middleware::from_fn(auth_allow_unverified))
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{ Method, StatusCode };
+    use axum::Router;
+    use sqlx::{ query, PgPool };
+
+    use crate::test_support::{
+        app,
+        app_state,
+        app_state_with_mailer,
+        log_in,
+        log_in_with,
+        refresh,
+        request,
+        send,
+        sign_up,
+        PASSWORD,
+    };
+
+    async fn verify_email(app: &Router, token: &str) -> StatusCode {
+        let body = serde_json::json!({ "token": token });
+        send(app, request(Method::POST, "/api/users/verify-email", None), Some(body)).await.0
+    }
+
+    async fn me(app: &Router, token: &str) -> (StatusCode, serde_json::Value) {
+        send(app, request(Method::GET, "/api/users/me", Some(token)), None).await
+    }
+
+    async fn email_verified(app: &Router, token: &str) -> serde_json::Value {
+        me(app, token).await.1["email_verified"].clone()
+    }
+
+    async fn change_password(app: &Router, token: &str, current_password: &str) -> StatusCode {
+        let body = serde_json::json!({
+            "current_password": current_password,
+            "new_password": "new password",
+        });
+        let change = request(Method::PATCH, "/api/users/me/password", Some(token));
+        send(app, change, Some(body)).await.0
+    }
+
+    async fn change_email(app: &Router, token: &str, email: &str, password: &str) -> StatusCode {
+        let body = serde_json::json!({ "email": email, "password": password });
+        let change = request(Method::PATCH, "/api/users/me/email", Some(token));
+        send(app, change, Some(body)).await.0
+    }
+
+    #[sqlx::test]
+    async fn verifies_the_email_with_the_mailed_token(db: PgPool) {
+        let (ctx, mailer) = app_state_with_mailer(db);
+        let app = app(&ctx);
+        let (token, _) = sign_up(&app, "jane@example.com").await;
+        assert_eq!(email_verified(&app, &token).await, false);
+
+        let verification = mailer.last_token("jane@example.com").unwrap();
+        assert_eq!(verify_email(&app, &verification).await, StatusCode::OK);
+
+        assert_eq!(email_verified(&app, &token).await, true);
+        assert_eq!(verify_email(&app, &verification).await, StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn rejects_expired_and_unknown_verification_tokens(db: PgPool) {
+        let (ctx, mailer) = app_state_with_mailer(db);
+        let app = app(&ctx);
+        let (token, _) = sign_up(&app, "jane@example.com").await;
+        let verification = mailer.last_token("jane@example.com").unwrap();
+        query!("UPDATE email_verification_tokens SET expires_at = NOW() - INTERVAL '1 minute'")
+            .execute(&ctx.db).await.unwrap();
+
+        assert_eq!(verify_email(&app, &verification).await, StatusCode::BAD_REQUEST);
+        assert_eq!(verify_email(&app, "not-a-token").await, StatusCode::BAD_REQUEST);
+        assert_eq!(email_verified(&app, &token).await, false);
+    }
+
+    #[sqlx::test]
+    async fn changing_the_password_logs_out_the_other_sessions(db: PgPool) {
+        let app = app(&app_state(db));
+        let (token, refresh_token) = sign_up(&app, "jane@example.com").await;
+        let (other_token, other_refresh_token) = log_in(&app, "jane@example.com").await;
+
+        assert_eq!(change_password(&app, &token, PASSWORD).await, StatusCode::OK);
+
+        assert_eq!(me(&app, &token).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(refresh(&app, &refresh_token).await, StatusCode::OK);
+        assert_eq!(me(&app, &other_token).await.0, StatusCode::OK);
+        assert_eq!(refresh(&app, &other_refresh_token).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(log_in_with(&app, "jane@example.com", "new password").await, StatusCode::OK);
+        let old_password = log_in_with(&app, "jane@example.com", PASSWORD).await;
+        assert_eq!(old_password, StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn changing_the_password_requires_the_current_one(db: PgPool) {
+        let app = app(&app_state(db));
+        let (token, _) = sign_up(&app, "jane@example.com").await;
+        let (_, other_refresh_token) = log_in(&app, "jane@example.com").await;
+
+        let status = change_password(&app, &token, "wrong password").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        assert_eq!(me(&app, &token).await.0, StatusCode::OK);
+        assert_eq!(refresh(&app, &other_refresh_token).await, StatusCode::OK);
+        assert_eq!(log_in_with(&app, "jane@example.com", PASSWORD).await, StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn changes_the_email_once_the_new_address_is_verified(db: PgPool) {
+        let (ctx, mailer) = app_state_with_mailer(db);
+        let app = app(&ctx);
+        let (token, _) = sign_up(&app, "jane@example.com").await;
+
+        let status = change_email(&app, &token, "jane.doe@example.com", PASSWORD).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(me(&app, &token).await.1["email"], "jane@example.com");
+
+        let verification = mailer.last_token("jane.doe@example.com").unwrap();
+        assert_eq!(verify_email(&app, &verification).await, StatusCode::OK);
+        assert_eq!(me(&app, &token).await.1["email"], "jane.doe@example.com");
+        let status = log_in_with(&app, "jane.doe@example.com", PASSWORD).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn rejects_taken_emails_and_wrong_passwords(db: PgPool) {
+        let (ctx, mailer) = app_state_with_mailer(db);
+        let app = app(&ctx);
+        let (token, _) = sign_up(&app, "jane@example.com").await;
+        sign_up(&app, "john@example.com").await;
+
+        let status = change_email(&app, &token, "john@example.com", PASSWORD).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let status = change_email(&app, &token, "jane.doe@example.com", "wrong password").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(mailer.last_token("jane.doe@example.com").is_none());
+    }
 }
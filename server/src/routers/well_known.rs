@@ -0,0 +1,38 @@
+use axum::{ response::IntoResponse, routing::get, Extension, Json, Router };
+
+use crate::AppState;
+
+/// Public keys other services can use to verify access tokens.
+async fn jwks(ctx: Extension<AppState>) -> impl IntoResponse {
+    Json(ctx.keys.jwks())
+}
+
+pub fn router() -> Router {
+    Router::new().route("/jwks.json", get(jwks))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::http::{ Method, StatusCode };
+    use sqlx::PgPool;
+
+    use crate::auth::keys::tests::{ keys_dir, ED25519_KEY, ED25519_PUBLIC_KEY, ED25519_X };
+    use crate::auth::keys::Keys;
+    use crate::test_support::{ app, app_state, request, send };
+    use crate::AppState;
+
+    #[sqlx::test]
+    async fn serves_the_public_keys(db: PgPool) {
+        let dir = keys_dir(&[("2024-01", ED25519_KEY, ED25519_PUBLIC_KEY)]);
+        let ctx = AppState { keys: Arc::new(Keys::from_dir(&dir, None).unwrap()), ..app_state(db) };
+
+        let jwks = request(Method::GET, "/.well-known/jwks.json", None);
+        let (status, jwks) = send(&app(&ctx), jwks, None).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(jwks["keys"][0]["kid"], "2024-01");
+        assert_eq!(jwks["keys"][0]["x"], ED25519_X);
+    }
+}
@@ -1,6 +1,27 @@
+use std::sync::Arc;
+
 use sqlx::{ Pool, Postgres };
+use webauthn_rs::Webauthn;
+
+use crate::{
+    auth::{
+        keys::Keys,
+        login_throttle::AttemptStore,
+        oidc::OidcProviders,
+        revocation::RevocationCache,
+    },
+    mailer::Mailer,
+    realtime::EventBus,
+};
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: Pool<Postgres>,
+    pub keys: Arc<Keys>,
+    pub revoked_access_tokens: RevocationCache,
+    pub mailer: Arc<dyn Mailer>,
+    pub login_attempts: Arc<dyn AttemptStore>,
+    pub oidc: Arc<OidcProviders>,
+    pub webauthn: Arc<Webauthn>,
+    pub events: Arc<dyn EventBus>,
 }
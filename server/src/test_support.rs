@@ -0,0 +1,153 @@
+use std::sync::{ Arc, Mutex };
+
+use async_trait::async_trait;
+use axum::body::{ to_bytes, Body };
+use axum::http::{ header, request, Method, Request, StatusCode };
+use axum::{ Extension, Router };
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+use crate::{
+    auth::{
+        keys::Keys,
+        login_throttle::memory_store::MemoryAttemptStore,
+        oidc::OidcProviders,
+        passkey::webauthn_from_env,
+        revocation::RevocationCache,
+    },
+    mailer::{ Email, Mailer },
+    realtime::memory_bus::MemoryEventBus,
+    routers,
+    AppState,
+};
+
+/// Keeps the sent emails so tests can follow the links in them.
+#[derive(Default)]
+pub struct RecordingMailer {
+    emails: Mutex<Vec<Email>>,
+}
+
+#[async_trait]
+impl Mailer for RecordingMailer {
+    async fn send(&self, email: Email) -> Result<(), String> {
+        self.emails.lock().unwrap().push(email);
+        Ok(())
+    }
+}
+
+impl RecordingMailer {
+    /// The token of the link in the last email sent to the address.
+    pub fn last_token(&self, to: &str) -> Option<String> {
+        let emails = self.emails.lock().unwrap();
+        let email = emails.iter().rev().find(|email| email.to == to)?;
+        let (_, token) = email.body.split_once("token=")?;
+        token.split_whitespace().next().map(|token| token.to_string())
+    }
+}
+
+/// App state for router tests, signing with a shared secret and keeping everything in memory.
+pub fn app_state(db: PgPool) -> AppState {
+    app_state_with_mailer(db).0
+}
+
+/// Like `app_state`, also returning the mailer the emails end up in.
+pub fn app_state_with_mailer(db: PgPool) -> (AppState, Arc<RecordingMailer>) {
+    dotenv::dotenv().ok();
+    let mailer = Arc::new(RecordingMailer::default());
+    let ctx = AppState {
+        db,
+        keys: Arc::new(Keys::from_secret(b"test")),
+        revoked_access_tokens: RevocationCache::default(),
+        mailer: mailer.clone(),
+        login_attempts: Arc::new(MemoryAttemptStore::default()),
+        oidc: Arc::new(OidcProviders::new(vec![])),
+        webauthn: webauthn_from_env(),
+        events: Arc::new(MemoryEventBus::default()),
+    };
+    (ctx, mailer)
+}
+
+/// The HTTP routes of `main`, without the socket.
+pub fn app(ctx: &AppState) -> Router {
+    Router::new()
+        .nest("/api", routers::router())
+        .nest("/.well-known", routers::well_known::router())
+        .layer(Extension(ctx.clone()))
+}
+
+/// Starts a request, authenticated with the access token or API key if there is one.
+pub fn request(method: Method, uri: &str, token: Option<&str>) -> request::Builder {
+    let request = Request::builder().method(method).uri(uri);
+    match token {
+        Some(token) => request.header(header::AUTHORIZATION, format!("Bearer {}", token)),
+        None => request,
+    }
+}
+
+/// Sends the request with an optional JSON body, returning the JSON response.
+pub async fn send(
+    app: &Router,
+    request: request::Builder,
+    body: Option<serde_json::Value>
+) -> (StatusCode, serde_json::Value) {
+    let request = match body {
+        Some(body) =>
+            request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    };
+
+    let response = app.clone().oneshot(request.unwrap()).await.unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null))
+}
+
+/// Password of the users created by `sign_up`.
+pub const PASSWORD: &str = "correct horse battery staple";
+
+fn tokens(response: &serde_json::Value) -> (String, String) {
+    (
+        response["accessToken"].as_str().unwrap().to_string(),
+        response["refreshToken"].as_str().unwrap().to_string(),
+    )
+}
+
+/// Registers a user, returning the access and refresh token of its first session.
+pub async fn sign_up(app: &Router, email: &str) -> (String, String) {
+    let body = serde_json::json!({ "email": email, "password": PASSWORD });
+    let (status, user) = send(app, request(Method::POST, "/api/users", None), Some(body)).await;
+    assert_eq!(status, StatusCode::OK, "{}", user);
+    tokens(&user)
+}
+
+/// Logs in with the password, returning the access and refresh token of the new session.
+pub async fn log_in(app: &Router, email: &str) -> (String, String) {
+    let body = serde_json::json!({ "email": email, "password": PASSWORD });
+    let login = request(Method::POST, "/api/auth/login", None);
+    let (status, response) = send(app, login, Some(body)).await;
+    assert_eq!(status, StatusCode::OK, "{}", response);
+    tokens(&response)
+}
+
+/// Logs in with the given password, returning the status of the response.
+pub async fn log_in_with(app: &Router, email: &str, password: &str) -> StatusCode {
+    let body = serde_json::json!({ "email": email, "password": password });
+    send(app, request(Method::POST, "/api/auth/login", None), Some(body)).await.0
+}
+
+/// Exchanges the refresh token for a new access token, returning the status of the response.
+pub async fn refresh(app: &Router, refresh_token: &str) -> StatusCode {
+    let body = serde_json::json!({ "refreshToken": refresh_token });
+    send(app, request(Method::POST, "/api/auth/access_token", None), Some(body)).await.0
+}
+
+/// Creates an organization owned by the user of the token, returning its id.
+pub async fn create_organization(app: &Router, token: &str, name: &str) -> String {
+    let body = serde_json::json!({ "name": name });
+    let create = request(Method::POST, "/api/organizations", Some(token));
+    let (status, organization) = send(app, create, Some(body)).await;
+    assert_eq!(status, StatusCode::OK, "{}", organization);
+    organization["organization_id"].as_str().unwrap().to_string()
+}